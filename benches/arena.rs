@@ -0,0 +1,126 @@
+// Compares `Arena` against a plain `Vec` at several chunk sizes. Run with
+//
+//   cargo bench --bench arena
+//
+// Each figure is the best of a few rounds, in nanoseconds per node
+// allocated or looked up.
+
+// There is no library target to link against, so the module is compiled
+// in directly
+#[allow(dead_code, unused_imports)]
+#[path = "../src/arena.rs"]
+mod arena;
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use arena::{Arena, NodeId};
+
+const NODES: usize = 2_000_000;
+const LOOKUPS: usize = 4_000_000;
+// Lookups confined to the first `HOT` nodes, which stay in cache, so the
+// index math isn't hidden behind memory latency
+const HOT: usize = 8192;
+const ROUNDS: usize = 5;
+const CHUNK_SIZES: [usize; 6] = [64, 100, 1000, 1024, 4096, 65536];
+
+// Roughly the size of an expression node
+type Node = [u64; 4];
+
+fn node(i: usize) -> Node {
+    [i as u64, 0, 0, 0]
+}
+
+// Fastest of `ROUNDS` runs of `f`, divided over `ops` operations
+fn best(ops: usize, mut f: impl FnMut()) -> f64 {
+    let mut best = Duration::MAX;
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+        f();
+        best = best.min(start.elapsed());
+    }
+    best.as_nanos() as f64 / ops as f64
+}
+
+// xorshift, so the lookup order is the same on every run
+fn random_indices(count: usize, below: usize) -> Vec<u32> {
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    (0..count)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % below as u64) as u32
+        })
+        .collect()
+}
+
+fn bench_vec(indices: &[u32], hot: &[u32]) {
+    let alloc = best(NODES, || {
+        let mut nodes = Vec::new();
+        for i in 0..NODES {
+            nodes.push(node(i));
+        }
+        black_box(&nodes);
+    });
+
+    let nodes: Vec<Node> = (0..NODES).map(node).collect();
+    let get = |indices: &[u32]| {
+        best(indices.len(), || {
+            let mut sum = 0u64;
+            for &i in indices {
+                sum = sum.wrapping_add(nodes[i as usize][0]);
+            }
+            black_box(sum);
+        })
+    };
+    report("Vec", alloc, get(indices), get(hot));
+}
+
+fn bench_arena(chunk_size: usize, indices: &[u32], hot: &[u32]) {
+    let alloc = best(NODES, || {
+        let mut arena = Arena::new(chunk_size);
+        for i in 0..NODES {
+            arena.alloc(node(i));
+        }
+        black_box(&arena);
+    });
+
+    let mut arena = Arena::new(chunk_size);
+    arena.alloc_many((0..NODES).map(node));
+    let get = |indices: &[u32]| {
+        best(indices.len(), || {
+            let mut sum = 0u64;
+            for &i in indices {
+                sum = sum.wrapping_add(arena.get(NodeId::new(i))[0]);
+            }
+            black_box(sum);
+        })
+    };
+    report(&chunk_size.to_string(), alloc, get(indices), get(hot));
+}
+
+fn report(name: &str, alloc: f64, get: f64, hot_get: f64) {
+    println!(
+        "{:>12} {:>10.2} {:>10.2} {:>10.2}",
+        name, alloc, get, hot_get
+    );
+}
+
+fn main() {
+    let indices = random_indices(LOOKUPS, NODES);
+    let hot = random_indices(LOOKUPS, HOT);
+    println!(
+        "{} nodes of {} bytes, ns per node",
+        NODES,
+        std::mem::size_of::<Node>()
+    );
+    println!(
+        "{:>12} {:>10} {:>10} {:>10}",
+        "chunk_size", "alloc", "get", "hot get"
+    );
+    bench_vec(&indices, &hot);
+    for chunk_size in CHUNK_SIZES {
+        bench_arena(chunk_size, &indices, &hot);
+    }
+}
@@ -1,4 +1,8 @@
+use std::hash::{Hash, Hasher};
+use std::iter::{Chain, Flatten};
 use std::marker::PhantomData;
+use std::ops::{Index, IndexMut};
+use std::vec;
 
 #[derive(Debug)]
 pub struct NodeId<T> {
@@ -26,10 +30,52 @@ impl<T> Clone for NodeId<T> {
     }
 }
 
+// Implemented by hand so that `T` itself doesn't need to be comparable/hashable
+impl<T> PartialEq for NodeId<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.idx == other.idx
+    }
+}
+impl<T> Eq for NodeId<T> {}
+impl<T> Hash for NodeId<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.idx.hash(state);
+    }
+}
+
+// Memory use of one or more arenas, see `Arena::stats`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ArenaStats {
+    // Chunks allocated, including the free pool
+    pub chunks: usize,
+    // Room for this many nodes across all chunks
+    pub capacity: usize,
+    // Live nodes
+    pub len: usize,
+    pub bytes: usize,
+}
+
+impl ArenaStats {
+    // Combined figures for two arenas, e.g. to total up an `Ast`
+    pub fn merge(self, other: ArenaStats) -> ArenaStats {
+        ArenaStats {
+            chunks: self.chunks + other.chunks,
+            capacity: self.capacity + other.capacity,
+            len: self.len + other.len,
+            bytes: self.bytes + other.bytes,
+        }
+    }
+}
+
 pub struct Arena<T> {
     chunks: Vec<Vec<T>>,
     chunk: Vec<T>,
     chunk_size: usize,
+    // log2 of `chunk_size` when it is a power of two, so `locate` can
+    // shift and mask instead of dividing
+    chunk_shift: Option<u32>,
+    // Empty chunks kept by `clear`, reused before allocating new ones
+    free: Vec<Vec<T>>,
 }
 
 impl<T> Arena<T> {
@@ -38,6 +84,26 @@ impl<T> Arena<T> {
             chunks: Vec::new(),
             chunk: Vec::with_capacity(chunk_size),
             chunk_size,
+            chunk_shift: chunk_size.is_power_of_two().then(|| chunk_size.trailing_zeros()),
+            free: Vec::new(),
+        }
+    }
+
+    // An arena with room for `total` nodes before it needs to allocate
+    pub fn with_capacity(chunk_size: usize, total: usize) -> Arena<T> {
+        let mut arena = Self::new(chunk_size);
+        arena.reserve(total);
+        arena
+    }
+
+    // Makes sure the next `additional` allocations don't allocate. The
+    // chunks are parked in the free pool, so `len` and the id math are
+    // unaffected.
+    pub fn reserve(&mut self, additional: usize) {
+        let room = self.chunk_size - self.chunk.len();
+        let needed = additional.saturating_sub(room).div_ceil(self.chunk_size);
+        while self.free.len() < needed {
+            self.free.push(Vec::with_capacity(self.chunk_size));
         }
     }
 
@@ -47,17 +113,52 @@ impl<T> Arena<T> {
         self.chunk.push(item);
 
         if self.chunk.len() >= self.chunk_size {
-            let chunk = std::mem::replace(&mut self.chunk, Vec::with_capacity(self.chunk_size));
-            self.chunks.push(chunk);
+            self.retire_chunk();
         }
         NodeId::new(idx as u32)
     }
 
+    // Allocates `items` in order and returns their ids, which are
+    // consecutive. Each chunk is filled with a single `extend`, a batch
+    // larger than the room left simply carries on in the next chunk.
+    pub fn alloc_many(&mut self, items: impl IntoIterator<Item = T>) -> Vec<NodeId<T>> {
+        let mut items = items.into_iter();
+        let start = self.len();
+        self.reserve(items.size_hint().0);
+        loop {
+            let room = self.chunk_size - self.chunk.len();
+            self.chunk.extend(items.by_ref().take(room));
+            if self.chunk.len() < self.chunk_size {
+                break;
+            }
+            self.retire_chunk();
+        }
+        debug_assert!(u32::MAX as usize >= self.len());
+        (start..self.len()).map(|idx| NodeId::new(idx as u32)).collect()
+    }
+
+    // Moves the full current chunk to `chunks` and starts a new one
+    fn retire_chunk(&mut self) {
+        let next = self.free.pop().unwrap_or_else(|| Vec::with_capacity(self.chunk_size));
+        let chunk = std::mem::replace(&mut self.chunk, next);
+        self.chunks.push(chunk);
+    }
+
+    // (chunk index, offset in chunk), where a chunk index equal to
+    // `chunks.len()` refers to the in-progress `chunk`. Shifts and masks
+    // when `chunk_size` is a power of two, divides otherwise.
     #[inline]
-    pub fn get(&self, id: NodeId<T>) -> &T {
+    fn locate(&self, id: NodeId<T>) -> (usize, usize) {
         let idx = id.index();
-        let chunk_id = idx / self.chunk_size;
-        let offset = idx % self.chunk_size;
+        match self.chunk_shift {
+            Some(shift) => (idx >> shift, idx & (self.chunk_size - 1)),
+            None => (idx / self.chunk_size, idx % self.chunk_size),
+        }
+    }
+
+    #[inline]
+    pub fn get(&self, id: NodeId<T>) -> &T {
+        let (chunk_id, offset) = self.locate(id);
         if chunk_id < self.chunks.len() {
             &self.chunks[chunk_id][offset]
         } else {
@@ -67,9 +168,7 @@ impl<T> Arena<T> {
 
     #[inline]
     pub fn get_mut(&mut self, id: NodeId<T>) -> &mut T {
-        let idx = id.index();
-        let chunk_id = idx / self.chunk_size;
-        let offset = idx % self.chunk_size;
+        let (chunk_id, offset) = self.locate(id);
         if chunk_id < self.chunks.len() {
             &mut self.chunks[chunk_id][offset]
         } else {
@@ -77,11 +176,95 @@ impl<T> Arena<T> {
         }
     }
 
+    // Mutable access to two distinct nodes at once. Panics if `a == b` or
+    // either id is out of bounds, like `<[T]>::get_disjoint_mut(..).unwrap()`
+    pub fn get_disjoint_mut(&mut self, a: NodeId<T>, b: NodeId<T>) -> (&mut T, &mut T) {
+        assert!(a != b, "get_disjoint_mut called with the same id twice");
+        let (chunk_a, offset_a) = self.locate(a);
+        let (chunk_b, offset_b) = self.locate(b);
+        let full = self.chunks.len();
+
+        match (chunk_a < full, chunk_b < full) {
+            (true, true) if chunk_a == chunk_b => {
+                let [x, y] = self.chunks[chunk_a]
+                    .get_disjoint_mut([offset_a, offset_b])
+                    .unwrap();
+                (x, y)
+            }
+            (true, true) => {
+                let [x, y] = self.chunks.get_disjoint_mut([chunk_a, chunk_b]).unwrap();
+                (&mut x[offset_a], &mut y[offset_b])
+            }
+            (true, false) => (
+                &mut self.chunks[chunk_a][offset_a],
+                &mut self.chunk[offset_b],
+            ),
+            (false, true) => (
+                &mut self.chunk[offset_a],
+                &mut self.chunks[chunk_b][offset_b],
+            ),
+            (false, false) => {
+                let [x, y] = self.chunk.get_disjoint_mut([offset_a, offset_b]).unwrap();
+                (x, y)
+            }
+        }
+    }
+
+    #[inline]
+    pub fn get_opt(&self, id: NodeId<T>) -> Option<&T> {
+        if id.index() < self.len() {
+            Some(self.get(id))
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    pub fn get_opt_mut(&mut self, id: NodeId<T>) -> Option<&mut T> {
+        if id.index() < self.len() {
+            Some(self.get_mut(id))
+        } else {
+            None
+        }
+    }
+
     #[inline]
     pub fn len(&self) -> usize {
         self.chunks.len() * self.chunk_size + self.chunk.len()
     }
 
+    // Drops every node but keeps the chunks' memory for the next round of
+    // allocations. Ids handed out before the clear become dangling: they
+    // will refer to whatever is allocated at their index next, or be out
+    // of bounds.
+    pub fn clear(&mut self) {
+        self.chunk.clear();
+        for mut chunk in self.chunks.drain(..) {
+            chunk.clear();
+            self.free.push(chunk);
+        }
+    }
+
+    // Moves every node out in id order, leaving the arena empty. Unlike
+    // `clear`, the chunks are freed as they're emptied rather than kept.
+    pub fn drain(&mut self) -> impl Iterator<Item = T> + use<T> {
+        let next = self.free.pop().unwrap_or_else(|| Vec::with_capacity(self.chunk_size));
+        let chunk = std::mem::replace(&mut self.chunk, next);
+        let chunks = std::mem::take(&mut self.chunks);
+        chunks.into_iter().flatten().chain(chunk)
+    }
+
+    pub fn stats(&self) -> ArenaStats {
+        let all = self.chunks.iter().chain(&self.free).chain([&self.chunk]);
+        let capacity = all.map(|chunk| chunk.capacity()).sum::<usize>();
+        ArenaStats {
+            chunks: self.chunks.len() + self.free.len() + 1,
+            capacity,
+            len: self.len(),
+            bytes: capacity * std::mem::size_of::<T>(),
+        }
+    }
+
     #[inline]
     pub fn is_empty(&self) -> bool { self.len() == 0 }
 
@@ -89,6 +272,44 @@ impl<T> Arena<T> {
         debug_assert!(u32::MAX as usize >= self.len());
         (0..self.len()).map(|id| NodeId::new(id as u32))
     }
+
+    // Same order as `iter_ids`, so the two can be zipped
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.chunks.iter().flatten().chain(self.chunk.iter())
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.chunks
+            .iter_mut()
+            .flatten()
+            .chain(self.chunk.iter_mut())
+    }
+}
+
+// Same order as `iter`, so the n-th node produced is the one with index n
+impl<T> IntoIterator for Arena<T> {
+    type Item = T;
+    type IntoIter = Chain<Flatten<vec::IntoIter<Vec<T>>>, vec::IntoIter<T>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.chunks.into_iter().flatten().chain(self.chunk)
+    }
+}
+
+impl<T> Index<NodeId<T>> for Arena<T> {
+    type Output = T;
+
+    #[inline]
+    fn index(&self, id: NodeId<T>) -> &T {
+        self.get(id)
+    }
+}
+
+impl<T> IndexMut<NodeId<T>> for Arena<T> {
+    #[inline]
+    fn index_mut(&mut self, id: NodeId<T>) -> &mut T {
+        self.get_mut(id)
+    }
 }
 
 #[cfg(test)]
@@ -142,4 +363,247 @@ mod tests {
         let sum: i32 = arena.iter_ids().map(|id| *arena.get(id)).sum();
         assert_eq!(sum, 6);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_get_opt() {
+        let mut arena = Arena::new(2);
+        let id1 = arena.alloc(1);
+        arena.alloc(2);
+        arena.alloc(3);
+
+        assert_eq!(arena.get_opt(id1), Some(&1));
+        assert_eq!(arena.get_opt(NodeId::new(2)), Some(&3));
+        assert_eq!(arena.get_opt(NodeId::new(3)), None);
+        assert_eq!(arena.get_opt(NodeId::new(4)), None);
+        assert_eq!(arena.get_opt(NodeId::new(u32::MAX)), None);
+
+        *arena.get_opt_mut(id1).unwrap() = 10;
+        assert_eq!(*arena.get(id1), 10);
+        assert!(arena.get_opt_mut(NodeId::new(3)).is_none());
+    }
+
+    #[test]
+    fn test_iter_values() {
+        let mut arena = Arena::new(2);
+        for i in 0..5 {
+            arena.alloc(i);
+        }
+
+        let values: Vec<i32> = arena.iter().copied().collect();
+        assert_eq!(values, vec![0, 1, 2, 3, 4]);
+        for (id, value) in arena.iter_ids().zip(arena.iter()) {
+            assert_eq!(arena.get(id), value);
+        }
+
+        arena.iter_mut().for_each(|v| *v *= 10);
+        let values: Vec<i32> = arena.iter().copied().collect();
+        assert_eq!(values, vec![0, 10, 20, 30, 40]);
+    }
+
+    #[test]
+    fn test_node_id_eq_and_hash() {
+        use std::collections::HashMap;
+
+        // Neither arena element type implements Eq or Hash
+        #[derive(Debug)]
+        struct Expr;
+        #[derive(Debug)]
+        struct Stmt;
+        let mut exprs = Arena::new(4);
+        let mut stmts = Arena::new(4);
+        let e1 = exprs.alloc(Expr);
+        let e2 = exprs.alloc(Expr);
+        let s1 = stmts.alloc(Stmt);
+
+        assert_eq!(e1, NodeId::<Expr>::new(0));
+        assert_ne!(e1, e2);
+        // Same index, but `e1 == s1` would not compile
+        assert_eq!(e1.index(), s1.index());
+
+        let mut types: HashMap<NodeId<Expr>, &str> = HashMap::new();
+        types.insert(e1, "i32");
+        types.insert(e2, "bool");
+        assert_eq!(types[&e1], "i32");
+        assert_eq!(types[&NodeId::new(1)], "bool");
+    }
+
+    #[test]
+    fn test_get_disjoint_mut() {
+        let mut arena = Arena::new(2);
+        let ids: Vec<_> = (0..5).map(|i| arena.alloc(i)).collect();
+
+        // Same full chunk, different full chunks, full chunk vs. current chunk
+        for (a, b) in [(0, 1), (1, 2), (3, 4), (0, 4), (4, 0)] {
+            let (x, y) = arena.get_disjoint_mut(ids[a], ids[b]);
+            std::mem::swap(x, y);
+            assert_eq!(*arena.get(ids[a]), b as i32);
+            assert_eq!(*arena.get(ids[b]), a as i32);
+            let (x, y) = arena.get_disjoint_mut(ids[a], ids[b]);
+            std::mem::swap(x, y);
+        }
+
+        let mut arena = Arena::new(4);
+        let a = arena.alloc(1);
+        let b = arena.alloc(2);
+        let (x, y) = arena.get_disjoint_mut(a, b);
+        *x += *y;
+        assert_eq!(*arena.get(a), 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "same id")]
+    fn test_get_disjoint_mut_aliasing() {
+        let mut arena = Arena::new(2);
+        let a = arena.alloc(1);
+        arena.get_disjoint_mut(a, a);
+    }
+
+    #[test]
+    fn test_clear_reuses_chunks() {
+        let mut arena = Arena::new(2);
+        for i in 0..5 {
+            arena.alloc(i);
+        }
+        let first = arena.chunks[0].as_ptr();
+        arena.clear();
+        assert_eq!(arena.len(), 0);
+        assert!(arena.is_empty());
+        assert_eq!(arena.get_opt(NodeId::new(0)), None);
+        assert_eq!(arena.free.len(), 2);
+
+        let ids: Vec<_> = (10..15).map(|i| arena.alloc(i)).collect();
+        assert_eq!(arena.len(), 5);
+        assert!(arena.free.is_empty());
+        assert_eq!(*arena.get(ids[0]), 10);
+        assert_eq!(*arena.get(ids[4]), 14);
+        // The old chunks were handed out again rather than reallocated
+        assert!(arena.chunks.iter().chain([&arena.chunk]).any(|c| c.as_ptr() == first));
+    }
+
+    #[test]
+    fn test_reserve() {
+        let mut arena = Arena::new(4);
+        arena.alloc(0);
+        arena.reserve(3);
+        assert!(arena.free.is_empty());
+        arena.reserve(4);
+        assert_eq!(arena.free.len(), 1);
+        arena.reserve(12);
+        assert_eq!(arena.free.len(), 3);
+        assert_eq!(arena.len(), 1);
+
+        let ids: Vec<_> = (1..13).map(|i| arena.alloc(i)).collect();
+        assert!(arena.free.is_empty());
+        assert_eq!(arena.len(), 13);
+        assert_eq!(*arena.get(ids[11]), 12);
+        assert_eq!(arena.iter().copied().collect::<Vec<_>>(), (0..13).collect::<Vec<_>>());
+
+        let arena: Arena<u8> = Arena::with_capacity(4, 9);
+        assert_eq!(arena.free.len(), 2);
+        assert!(arena.is_empty());
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut arena: Arena<u32> = Arena::new(4);
+        let empty = arena.stats();
+        assert_eq!((empty.chunks, empty.capacity, empty.len, empty.bytes), (1, 4, 0, 16));
+
+        for i in 0..5 {
+            arena.alloc(i);
+        }
+        arena.reserve(7);
+        let stats = arena.stats();
+        assert_eq!(stats, ArenaStats { chunks: 3, capacity: 12, len: 5, bytes: 48 });
+        assert_eq!(
+            stats.merge(empty),
+            ArenaStats { chunks: 4, capacity: 16, len: 5, bytes: 64 }
+        );
+    }
+
+    #[test]
+    fn test_index() {
+        let mut arena = Arena::new(2);
+        let ids: Vec<_> = (0..3).map(|i| arena.alloc(i)).collect();
+        assert_eq!(arena[ids[2]], 2);
+        arena[ids[0]] = 7;
+        arena[ids[2]] += 1;
+        assert_eq!(*arena.get(ids[0]), 7);
+        assert_eq!(arena[ids[2]], 3);
+    }
+
+    #[test]
+    fn test_alloc_many() {
+        let mut arena = Arena::new(4);
+        arena.alloc(0);
+        // Fills the rest of the first chunk, a whole second one and part
+        // of a third
+        let ids = arena.alloc_many(1..10);
+        assert_eq!(ids.len(), 9);
+        assert_eq!(ids[0], NodeId::new(1));
+        assert_eq!(ids[8], NodeId::new(9));
+        assert_eq!(arena.chunks.len(), 2);
+        assert_eq!(arena.len(), 10);
+        assert_eq!(arena.iter().copied().collect::<Vec<_>>(), (0..10).collect::<Vec<_>>());
+
+        // Ending exactly on a chunk boundary leaves an empty current chunk
+        let ids = arena.alloc_many([10, 11]);
+        assert_eq!(arena[ids[1]], 11);
+        assert_eq!(arena.chunks.len(), 3);
+        assert!(arena.chunk.is_empty());
+        assert_eq!(arena.alloc(12), NodeId::new(12));
+
+        // Iterators without a useful size hint work too
+        let ids = arena.alloc_many((13..20).filter(|i| i % 2 == 0));
+        assert_eq!(ids, [NodeId::new(13), NodeId::new(14), NodeId::new(15)]);
+        assert_eq!(arena[ids[2]], 18);
+        assert!(arena.alloc_many(std::iter::empty()).is_empty());
+        assert_eq!(arena.len(), 16);
+    }
+
+    #[test]
+    fn test_locate() {
+        // Shifted for powers of two, divided otherwise
+        let pow2: Arena<u8> = Arena::new(8);
+        let other: Arena<u8> = Arena::new(6);
+        assert_eq!(pow2.chunk_shift, Some(3));
+        assert_eq!(other.chunk_shift, None);
+        for idx in [0, 5, 7, 8, 13, 48, 1000] {
+            let id = NodeId::new(idx);
+            let idx = idx as usize;
+            assert_eq!(pow2.locate(id), (idx / 8, idx % 8));
+            assert_eq!(other.locate(id), (idx / 6, idx % 6));
+        }
+        assert_eq!(Arena::<u8>::new(1).chunk_shift, Some(0));
+
+        let mut arena = Arena::new(3);
+        let ids = arena.alloc_many(0..10);
+        assert_eq!(arena[ids[7]], 7);
+    }
+
+    #[test]
+    fn test_into_iter() {
+        let mut arena = Arena::new(2);
+        let ids: Vec<_> = (0..5).map(|i| arena.alloc(i.to_string())).collect();
+        let owned: Vec<String> = arena.into_iter().collect();
+        assert_eq!(owned, ["0", "1", "2", "3", "4"]);
+        for (id, value) in ids.iter().zip(&owned) {
+            assert_eq!(value, &id.index().to_string());
+        }
+    }
+
+    #[test]
+    fn test_drain() {
+        let mut arena = Arena::new(2);
+        arena.alloc_many((0..5).map(|i| vec![i]));
+        let drained: Vec<_> = arena.drain().collect();
+        assert_eq!(drained, (0..5).map(|i| vec![i]).collect::<Vec<_>>());
+        assert!(arena.is_empty());
+        assert_eq!(arena.stats().chunks, 1);
+
+        // The arena is usable again, with ids starting over
+        assert_eq!(arena.alloc(vec![7]), NodeId::new(0));
+        assert_eq!(arena.drain().next(), Some(vec![7]));
+        assert_eq!(arena.drain().next(), None);
+    }
+}
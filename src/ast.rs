@@ -1,11 +1,12 @@
-use crate::arena::{Arena, NodeId};
+use crate::arena::{Arena, ArenaStats, NodeId};
 use crate::common::{Span, Symbol};
+use std::fmt;
 
-type ExprId = NodeId<Expr>;
-type StmtId = NodeId<Stmt>;
-type ItemId = NodeId<Item>;
-type TypeSpecId = NodeId<TypeSpec>;
-type PatternId = NodeId<Pattern>;
+pub type ExprId = NodeId<Expr>;
+pub type StmtId = NodeId<Stmt>;
+pub type ItemId = NodeId<Item>;
+pub type TypeSpecId = NodeId<TypeSpec>;
+pub type PatternId = NodeId<Pattern>;
 
 #[derive(Debug, Clone)]
 pub enum BinaryOp {
@@ -24,13 +25,86 @@ pub enum BinaryOp {
     Or,
 }
 
+// Binding power of binary operators, higher binds tighter. Shared by the
+// parser and the printer so the two can't disagree.
+pub const PREC_PIPELINE: u8 = 1;
+pub const PREC_OR: u8 = 2;
+pub const PREC_AND: u8 = 3;
+pub const PREC_EQUALITY: u8 = 4;
+pub const PREC_COMPARISON: u8 = 5;
+pub const PREC_TERM: u8 = 6;
+pub const PREC_FACTOR: u8 = 7;
+
+impl BinaryOp {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Mod => "%",
+            BinaryOp::Eq => "==",
+            BinaryOp::Ne => "!=",
+            BinaryOp::Lt => "<",
+            BinaryOp::LtEq => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::GtEq => ">=",
+            BinaryOp::And => "&&",
+            BinaryOp::Or => "||",
+        }
+    }
+
+    pub fn precedence(&self) -> u8 {
+        match self {
+            BinaryOp::Or => PREC_OR,
+            BinaryOp::And => PREC_AND,
+            BinaryOp::Eq | BinaryOp::Ne => PREC_EQUALITY,
+            BinaryOp::Lt | BinaryOp::LtEq | BinaryOp::Gt | BinaryOp::GtEq => PREC_COMPARISON,
+            BinaryOp::Add | BinaryOp::Sub => PREC_TERM,
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod => PREC_FACTOR,
+        }
+    }
+
+    // Every binary operator currently groups to the left, `a - b - c` is
+    // `(a - b) - c`
+    pub fn is_right_assoc(&self) -> bool {
+        false
+    }
+}
+
+impl fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum UnaryOp {
     Neg,
     Not,
     Deref,
-    Ref,
+    // `&expr`, a reference that can't be written through
     AddressOf,
+    // `&mut expr`, a reference that can
+    AddressOfMut,
+}
+
+impl UnaryOp {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UnaryOp::Neg => "-",
+            UnaryOp::Not => "!",
+            UnaryOp::Deref => "*",
+            UnaryOp::AddressOf => "&",
+            UnaryOp::AddressOfMut => "&mut",
+        }
+    }
+}
+
+impl fmt::Display for UnaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -42,11 +116,30 @@ pub enum AssignOp {
     Div,
 }
 
+impl AssignOp {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AssignOp::Assign => "=",
+            AssignOp::Add => "+=",
+            AssignOp::Sub => "-=",
+            AssignOp::Mul => "*=",
+            AssignOp::Div => "/=",
+        }
+    }
+}
+
+impl fmt::Display for AssignOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Literal {
     Int(i64),
     Float(f64),
     Bool(bool),
+    Char(char),
     String(String),
     Null,
 }
@@ -57,6 +150,16 @@ pub enum ExprKind {
     Identifier(Symbol),
 
     Paren(ExprId),
+    // `()`, `(a,)` or `(a, b, ...)`
+    Tuple(Vec<ExprId>),
+    // `[a, b, c]`
+    ArrayLit(Vec<ExprId>),
+    // `Name { field: expr, ... }`, a bare `field` is shorthand for
+    // `field: field`
+    StructLit {
+        name: Symbol,
+        fields: Vec<(Symbol, ExprId)>,
+    },
 
     // Control flow
     Block {
@@ -90,6 +193,14 @@ pub enum ExprKind {
     Break(Option<ExprId>),
     Continue,
 
+    // `fn(x: i32) -> i32 { x + 1 }`, an anonymous function. What it
+    // captures from the enclosing scope isn't decided here.
+    Closure {
+        params: Vec<Param>,
+        ret_ty: Option<TypeSpecId>,
+        body: ExprId,
+    },
+
     // Operations
     Binary {
         lhs: ExprId,
@@ -115,6 +226,8 @@ pub enum ExprKind {
     },
     Call {
         callee: ExprId,
+        // Explicit type arguments, `f::<T>(x)`
+        type_args: Vec<TypeSpecId>,
         args: Vec<ExprId>,
     },
     MemberAccess {
@@ -168,10 +281,30 @@ pub struct Stmt {
 }
 
 #[derive(Debug, Clone)]
-pub enum Pattern {
+pub enum PatternKind {
     Literal(Literal),
     Identifier(Symbol),
     Wildcard,
+    Tuple(Vec<PatternId>),
+    // `a | b | c`
+    Or(Vec<PatternId>),
+    Struct {
+        name: Symbol,
+        fields: Vec<FieldPattern>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    pub kind: PatternKind,
+    pub span: Span,
+}
+
+// `x: pattern` inside a struct pattern, `x` alone is shorthand for `x: x`
+#[derive(Debug, Clone)]
+pub struct FieldPattern {
+    pub name: Symbol,
+    pub pattern: PatternId,
 }
 
 #[derive(Debug, Clone)]
@@ -179,8 +312,15 @@ pub enum TypeSpecKind {
     // This type I will treat primitive types like unresolved ones
     // and inject them later
     Named(Symbol),
+    // `Vec<i32>`
+    Generic {
+        base: Symbol,
+        args: Vec<TypeSpecId>,
+    },
     Pointer(TypeSpecId),
     Reference(TypeSpecId),
+    // `&mut T`
+    MutReference(TypeSpecId),
     Optional(TypeSpecId),
     Array {
         size: ExprId,
@@ -192,6 +332,11 @@ pub enum TypeSpecKind {
         return_ty: TypeSpecId,
     },
     Paren(TypeSpecId),
+    // `()`, `(T,)` or `(T, U, ...)`
+    Tuple(Vec<TypeSpecId>),
+
+    // Error recovery
+    Error,
 }
 
 #[derive(Debug, Clone)]
@@ -201,6 +346,7 @@ pub struct TypeSpec {
 }
 
 #[derive(Debug, Clone)]
+#[allow(clippy::enum_variant_names)]
 pub enum ItemKind {
     FnDecl {
         name: Symbol,
@@ -273,10 +419,57 @@ pub struct FnSig {
     pub return_ty: Option<TypeSpecId>,
 }
 
+// `@name` or `@name(args)` in front of an item. Any name is accepted,
+// later passes decide which ones mean something.
+#[derive(Debug, Clone)]
+pub struct Attribute {
+    pub name: Symbol,
+    pub args: Vec<ExprId>,
+    pub span: Span,
+}
+
 #[derive(Debug, Clone)]
 pub struct Item {
     pub kind: ItemKind,
     pub span: Span,
+    pub attrs: Vec<Attribute>,
+    // The `///` lines before the item joined by newlines
+    pub doc: Option<String>,
+}
+
+// Any node that covers a range of the source
+pub trait Spanned {
+    fn span(&self) -> Span;
+}
+
+impl Spanned for Expr {
+    fn span(&self) -> Span {
+        self.span
+    }
+}
+
+impl Spanned for Stmt {
+    fn span(&self) -> Span {
+        self.span
+    }
+}
+
+impl Spanned for Item {
+    fn span(&self) -> Span {
+        self.span
+    }
+}
+
+impl Spanned for TypeSpec {
+    fn span(&self) -> Span {
+        self.span
+    }
+}
+
+impl Spanned for Pattern {
+    fn span(&self) -> Span {
+        self.span
+    }
 }
 
 pub struct Ast {
@@ -297,4 +490,24 @@ impl Ast {
             patterns: Arena::new(chunk_size),
         }
     }
+
+    // Memory used by the whole tree, e.g. for
+    // "AST used 2.3 MB across 48k nodes"
+    pub fn stats(&self) -> ArenaStats {
+        self.exprs.stats()
+            .merge(self.stmts.stats())
+            .merge(self.type_specs.stats())
+            .merge(self.items.stats())
+            .merge(self.patterns.stats())
+    }
+
+    // Empties every arena, keeping their memory for the next parse. All
+    // ids into the old tree become dangling.
+    pub fn clear(&mut self) {
+        self.exprs.clear();
+        self.stmts.clear();
+        self.type_specs.clear();
+        self.items.clear();
+        self.patterns.clear();
+    }
 }
@@ -0,0 +1,1307 @@
+use crate::ast::{
+    ExprId, ExprKind, ItemId, ItemKind, Literal, PREC_PIPELINE, Param, PatternId, PatternKind,
+    StmtId, StmtKind, StructField, TypeSpecId, TypeSpecKind, UnaryOp, UnionVariantData, VarInit,
+};
+use crate::common::Symbol;
+use crate::context::CompilerCtx;
+
+// Renders the AST back into source-like text for eyeballing parser output.
+// The result parses to the same tree but isn't a byte-exact copy of the
+// input: comments are gone, layout is normalized and redundant parentheses
+// the user wrote are kept while missing ones are added.
+
+const INDENT: &str = "    ";
+
+// Binding power of the non-binary expression forms, on the same scale as
+// the binary operator precedences in the parser
+const PREC_ASSIGN: u8 = 0;
+const PREC_CAST: u8 = 8;
+const PREC_UNARY: u8 = 9;
+const PREC_POSTFIX: u8 = 10;
+const PREC_ATOM: u8 = 11;
+
+pub fn print_expr(ctx: &CompilerCtx, id: ExprId) -> String {
+    let mut p = Printer::new(ctx);
+    p.expr(id, PREC_ASSIGN);
+    p.out
+}
+
+pub fn print_stmt(ctx: &CompilerCtx, id: StmtId) -> String {
+    let mut p = Printer::new(ctx);
+    p.stmt(id);
+    p.out
+}
+
+pub fn print_item(ctx: &CompilerCtx, id: ItemId) -> String {
+    let mut p = Printer::new(ctx);
+    p.item(id);
+    p.out
+}
+
+pub fn print_type(ctx: &CompilerCtx, id: TypeSpecId) -> String {
+    let mut p = Printer::new(ctx);
+    p.ty(id);
+    p.out
+}
+
+pub fn print_pattern(ctx: &CompilerCtx, id: PatternId) -> String {
+    let mut p = Printer::new(ctx);
+    p.pattern(id);
+    p.out
+}
+
+// Items separated by blank lines, each ending in a newline
+pub fn print_program(ctx: &CompilerCtx, items: &[ItemId]) -> String {
+    let mut p = Printer::new(ctx);
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            p.out.push('\n');
+        }
+        p.item(*item);
+        p.out.push('\n');
+    }
+    p.out
+}
+
+// Compact single-line dump like `(binary + (lit 1) (lit 2))`, meant for
+// snapshot tests. Every node starts with a fixed tag; types and patterns
+// are written in their source form.
+pub fn to_sexpr(ctx: &CompilerCtx, id: ExprId) -> String {
+    let mut out = String::new();
+    sexpr(ctx, id, false, &mut out);
+    out
+}
+
+// Same as `to_sexpr` with each expression's span appended as `@start..end`
+pub fn to_sexpr_spanned(ctx: &CompilerCtx, id: ExprId) -> String {
+    let mut out = String::new();
+    sexpr(ctx, id, true, &mut out);
+    out
+}
+
+fn sexpr(ctx: &CompilerCtx, id: ExprId, spans: bool, out: &mut String) {
+    let expr = &ctx.ast.exprs[id];
+    let sym = |s: Symbol| ctx.symbol_interner.resolve(s);
+    let child = |out: &mut String, e: ExprId| {
+        out.push(' ');
+        sexpr(ctx, e, spans, out);
+    };
+    out.push('(');
+    match &expr.kind {
+        ExprKind::Literal(lit) => {
+            out.push_str("lit ");
+            out.push_str(&literal_str(lit));
+            out.push_str(literal_suffix(ctx, id).unwrap_or(""));
+        }
+        ExprKind::Identifier(s) => {
+            out.push_str("ident ");
+            out.push_str(sym(*s));
+        }
+        ExprKind::Paren(inner) => {
+            out.push_str("paren");
+            child(out, *inner);
+        }
+        ExprKind::Tuple(elems) => {
+            out.push_str("tuple");
+            for e in elems {
+                child(out, *e);
+            }
+        }
+        ExprKind::ArrayLit(elems) => {
+            out.push_str("array");
+            for e in elems {
+                child(out, *e);
+            }
+        }
+        ExprKind::StructLit { name, fields } => {
+            out.push_str("struct ");
+            out.push_str(sym(*name));
+            for (field, e) in fields {
+                out.push_str(" (");
+                out.push_str(sym(*field));
+                child(out, *e);
+                out.push(')');
+            }
+        }
+        ExprKind::Block { stmts, yield_expr } => {
+            out.push_str("block");
+            for stmt in stmts {
+                out.push(' ');
+                stmt_sexpr(ctx, *stmt, spans, out);
+            }
+            if let Some(e) = yield_expr {
+                out.push_str(" (yield");
+                child(out, *e);
+                out.push(')');
+            }
+        }
+        ExprKind::If {
+            cond,
+            then_branch,
+            else_branch,
+        } => {
+            out.push_str("if");
+            child(out, *cond);
+            child(out, *then_branch);
+            if let Some(e) = else_branch {
+                child(out, *e);
+            }
+        }
+        ExprKind::Match { target, cases } => {
+            out.push_str("match");
+            child(out, *target);
+            for case in cases {
+                out.push_str(" (case ");
+                out.push_str(&print_pattern(ctx, case.pattern));
+                child(out, case.body);
+                out.push(')');
+            }
+        }
+        ExprKind::Loop { body } => {
+            out.push_str("loop");
+            child(out, *body);
+        }
+        ExprKind::While {
+            cond,
+            body,
+            else_branch,
+        } => {
+            out.push_str("while");
+            child(out, *cond);
+            child(out, *body);
+            if let Some(e) = else_branch {
+                child(out, *e);
+            }
+        }
+        ExprKind::For {
+            binding,
+            iterable,
+            body,
+            else_branch,
+        } => {
+            out.push_str("for ");
+            out.push_str(sym(*binding));
+            child(out, *iterable);
+            child(out, *body);
+            if let Some(e) = else_branch {
+                child(out, *e);
+            }
+        }
+        ExprKind::Return(value) => {
+            out.push_str("return");
+            if let Some(e) = value {
+                child(out, *e);
+            }
+        }
+        ExprKind::Break(value) => {
+            out.push_str("break");
+            if let Some(e) = value {
+                child(out, *e);
+            }
+        }
+        ExprKind::Continue => out.push_str("continue"),
+        ExprKind::Closure {
+            params,
+            ret_ty,
+            body,
+        } => {
+            out.push_str("closure (");
+            for (i, param) in params.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                out.push_str(sym(param.name));
+                out.push_str(": ");
+                out.push_str(&print_type(ctx, param.ty));
+            }
+            out.push(')');
+            if let Some(ty) = ret_ty {
+                out.push_str(" -> ");
+                out.push_str(&print_type(ctx, *ty));
+            }
+            child(out, *body);
+        }
+        ExprKind::Binary { lhs, op, rhs } => {
+            out.push_str("binary ");
+            out.push_str(op.as_str());
+            child(out, *lhs);
+            child(out, *rhs);
+        }
+        ExprKind::Assign { target, op, value } => {
+            out.push_str("assign ");
+            out.push_str(op.as_str());
+            child(out, *target);
+            child(out, *value);
+        }
+        ExprKind::Pipeline { lhs, rhs } => {
+            out.push_str("pipe");
+            child(out, *lhs);
+            child(out, *rhs);
+        }
+        ExprKind::Cast {
+            target,
+            target_type,
+        } => {
+            out.push_str("cast");
+            child(out, *target);
+            out.push(' ');
+            out.push_str(&print_type(ctx, *target_type));
+        }
+        ExprKind::Unary { op, operand } => {
+            out.push_str("unary ");
+            out.push_str(op.as_str());
+            child(out, *operand);
+        }
+        ExprKind::Call {
+            callee,
+            type_args,
+            args,
+        } => {
+            out.push_str("call");
+            child(out, *callee);
+            if !type_args.is_empty() {
+                let type_args: Vec<_> = type_args.iter().map(|t| print_type(ctx, *t)).collect();
+                out.push_str(&format!(" ::<{}>", type_args.join(", ")));
+            }
+            for arg in args {
+                child(out, *arg);
+            }
+        }
+        ExprKind::MemberAccess { expr, member } => {
+            out.push_str("member");
+            child(out, *expr);
+            out.push(' ');
+            out.push_str(sym(*member));
+        }
+        ExprKind::IndexAccess { expr, index } => {
+            out.push_str("index");
+            child(out, *expr);
+            child(out, *index);
+        }
+        ExprKind::Error => out.push_str("error"),
+    }
+    if spans {
+        out.push_str(&format!(" @{}..{}", expr.span.start, expr.span.end));
+    }
+    out.push(')');
+}
+
+fn stmt_sexpr(ctx: &CompilerCtx, id: StmtId, spans: bool, out: &mut String) {
+    out.push('(');
+    match &ctx.ast.stmts[id].kind {
+        StmtKind::VarDecl {
+            is_mutable,
+            name,
+            ty,
+            init,
+        } => {
+            out.push_str(if *is_mutable { "var " } else { "val " });
+            out.push_str(ctx.symbol_interner.resolve(*name));
+            out.push(' ');
+            match ty {
+                Some(ty) => out.push_str(&print_type(ctx, *ty)),
+                None => out.push('_'),
+            }
+            out.push(' ');
+            match init {
+                VarInit::Expr(e) => sexpr(ctx, *e, spans, out),
+                VarInit::Undefined => out.push_str("undefined"),
+            }
+        }
+        StmtKind::Defer(e) => {
+            out.push_str("defer ");
+            sexpr(ctx, *e, spans, out);
+        }
+        StmtKind::Expr(e) => {
+            out.push_str("stmt ");
+            sexpr(ctx, *e, spans, out);
+        }
+    }
+    out.push(')');
+}
+
+struct Printer<'a> {
+    ctx: &'a CompilerCtx,
+    out: String,
+    indent: usize,
+}
+
+impl<'a> Printer<'a> {
+    fn new(ctx: &'a CompilerCtx) -> Self {
+        Self {
+            ctx,
+            out: String::new(),
+            indent: 0,
+        }
+    }
+
+    fn push(&mut self, s: &str) {
+        self.out.push_str(s);
+    }
+
+    fn sym(&mut self, sym: Symbol) {
+        let name = self.ctx.symbol_interner.resolve(sym);
+        self.out.push_str(name);
+    }
+
+    fn newline(&mut self) {
+        self.out.push('\n');
+        for _ in 0..self.indent {
+            self.out.push_str(INDENT);
+        }
+    }
+
+    fn comma_sep<T: Copy>(&mut self, items: &[T], mut f: impl FnMut(&mut Self, T)) {
+        for (i, item) in items.iter().enumerate() {
+            if i > 0 {
+                self.push(", ");
+            }
+            f(self, *item);
+        }
+    }
+
+    // Prints `id`, wrapped in parentheses if it binds looser than `min_prec`
+    fn expr(&mut self, id: ExprId, min_prec: u8) {
+        let ctx = self.ctx;
+        let kind = &ctx.ast.exprs[id].kind;
+        let wrap = expr_prec(kind) < min_prec;
+        if wrap {
+            self.push("(");
+        }
+        match kind {
+            ExprKind::Literal(lit) => {
+                self.literal(lit);
+                self.push(literal_suffix(ctx, id).unwrap_or(""));
+            }
+            ExprKind::Identifier(sym) => self.sym(*sym),
+            ExprKind::Paren(inner) => {
+                self.push("(");
+                self.expr(*inner, PREC_ASSIGN);
+                self.push(")");
+            }
+            ExprKind::Tuple(elems) => {
+                self.push("(");
+                self.comma_sep(elems, |p, e| p.expr(e, PREC_ASSIGN));
+                if elems.len() == 1 {
+                    self.push(",");
+                }
+                self.push(")");
+            }
+            ExprKind::ArrayLit(elems) => {
+                self.push("[");
+                self.comma_sep(elems, |p, e| p.expr(e, PREC_ASSIGN));
+                self.push("]");
+            }
+            ExprKind::StructLit { name, fields } => {
+                self.sym(*name);
+                if fields.is_empty() {
+                    self.push(" {}");
+                } else {
+                    self.push(" { ");
+                    self.comma_sep(fields, |p, (field, e)| {
+                        p.sym(field);
+                        p.push(": ");
+                        p.expr(e, PREC_ASSIGN);
+                    });
+                    self.push(" }");
+                }
+            }
+            ExprKind::Block { stmts, yield_expr } => self.block(stmts, *yield_expr),
+            ExprKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                self.push("if ");
+                self.expr(*cond, PREC_ASSIGN);
+                self.push(" ");
+                self.expr(*then_branch, PREC_ASSIGN);
+                if let Some(e) = else_branch {
+                    self.push(" else ");
+                    self.expr(*e, PREC_ASSIGN);
+                }
+            }
+            ExprKind::Match { target, cases } => {
+                self.push("match ");
+                self.expr(*target, PREC_ASSIGN);
+                self.push(" {");
+                self.indent += 1;
+                for case in cases {
+                    self.newline();
+                    self.pattern(case.pattern);
+                    self.push(" => ");
+                    self.expr(case.body, PREC_ASSIGN);
+                    self.push(",");
+                }
+                self.indent -= 1;
+                if !cases.is_empty() {
+                    self.newline();
+                }
+                self.push("}");
+            }
+            ExprKind::Loop { body } => {
+                self.push("loop ");
+                self.expr(*body, PREC_ASSIGN);
+            }
+            ExprKind::While {
+                cond,
+                body,
+                else_branch,
+            } => {
+                self.push("while ");
+                self.expr(*cond, PREC_ASSIGN);
+                self.push(" ");
+                self.expr(*body, PREC_ASSIGN);
+                if let Some(e) = else_branch {
+                    self.push(" else ");
+                    self.expr(*e, PREC_ASSIGN);
+                }
+            }
+            ExprKind::For {
+                binding,
+                iterable,
+                body,
+                else_branch,
+            } => {
+                self.push("for ");
+                self.sym(*binding);
+                self.push(" in ");
+                self.expr(*iterable, PREC_ASSIGN);
+                self.push(" ");
+                self.expr(*body, PREC_ASSIGN);
+                if let Some(e) = else_branch {
+                    self.push(" else ");
+                    self.expr(*e, PREC_ASSIGN);
+                }
+            }
+            ExprKind::Return(value) => self.jump("return", *value),
+            ExprKind::Break(value) => self.jump("break", *value),
+            ExprKind::Continue => self.push("continue"),
+            ExprKind::Closure {
+                params,
+                ret_ty,
+                body,
+            } => {
+                self.push("fn");
+                self.signature(params, *ret_ty);
+                self.push(" ");
+                self.expr(*body, PREC_ASSIGN);
+            }
+            ExprKind::Binary { lhs, op, rhs } => {
+                let prec = op.precedence();
+                let (lhs_prec, rhs_prec) = if op.is_right_assoc() {
+                    (prec + 1, prec)
+                } else {
+                    (prec, prec + 1)
+                };
+                self.expr(*lhs, lhs_prec);
+                self.push(" ");
+                self.push(op.as_str());
+                self.push(" ");
+                self.expr(*rhs, rhs_prec);
+            }
+            ExprKind::Pipeline { lhs, rhs } => {
+                self.expr(*lhs, PREC_PIPELINE);
+                self.push(" |> ");
+                self.expr(*rhs, PREC_PIPELINE + 1);
+            }
+            ExprKind::Assign { target, op, value } => {
+                self.expr(*target, PREC_ASSIGN + 1);
+                self.push(" ");
+                self.push(op.as_str());
+                self.push(" ");
+                self.expr(*value, PREC_ASSIGN);
+            }
+            ExprKind::Cast {
+                target,
+                target_type,
+            } => {
+                self.expr(*target, PREC_CAST);
+                self.push(" as ");
+                self.ty(*target_type);
+            }
+            ExprKind::Unary { op, operand } => {
+                self.push(op.as_str());
+                if let UnaryOp::AddressOfMut = op {
+                    self.push(" ");
+                }
+                self.expr(*operand, PREC_UNARY);
+            }
+            ExprKind::Call {
+                callee,
+                type_args,
+                args,
+            } => {
+                self.expr(*callee, PREC_POSTFIX);
+                if !type_args.is_empty() {
+                    self.push("::");
+                    self.type_args(type_args);
+                }
+                self.push("(");
+                self.comma_sep(args, |p, arg| p.expr(arg, PREC_ASSIGN));
+                self.push(")");
+            }
+            ExprKind::MemberAccess { expr, member } => {
+                self.expr(*expr, PREC_POSTFIX);
+                self.push(".");
+                self.sym(*member);
+            }
+            ExprKind::IndexAccess { expr, index } => {
+                self.expr(*expr, PREC_POSTFIX);
+                self.push("[");
+                self.expr(*index, PREC_ASSIGN);
+                self.push("]");
+            }
+            ExprKind::Error => self.push("<error>"),
+        }
+        if wrap {
+            self.push(")");
+        }
+    }
+
+    fn jump(&mut self, keyword: &str, value: Option<ExprId>) {
+        self.push(keyword);
+        if let Some(value) = value {
+            self.push(" ");
+            self.expr(value, PREC_ASSIGN);
+        }
+    }
+
+    fn literal(&mut self, lit: &Literal) {
+        self.push(&literal_str(lit));
+    }
+
+    fn block(&mut self, stmts: &[StmtId], yield_expr: Option<ExprId>) {
+        if stmts.is_empty() && yield_expr.is_none() {
+            self.push("{}");
+            return;
+        }
+        self.push("{");
+        self.indent += 1;
+        // Where the `;` left off a block-like statement would go
+        let mut open = None;
+        for stmt in stmts {
+            self.newline();
+            let start = self.out.len();
+            self.stmt(*stmt);
+            self.separate(open, start);
+            open = self.ends_open(*stmt).then_some(self.out.len());
+        }
+        if let Some(e) = yield_expr {
+            self.newline();
+            let start = self.out.len();
+            self.expr(e, PREC_ASSIGN);
+            self.separate(open, start);
+        }
+        self.indent -= 1;
+        self.newline();
+        self.push("}");
+    }
+
+    // Whether `id` was printed without a `;`, which is only safe when the
+    // next statement can't be read as continuing it
+    fn ends_open(&self, id: StmtId) -> bool {
+        let ast = &self.ctx.ast;
+        match ast.stmts[id].kind {
+            StmtKind::Expr(e) | StmtKind::Defer(e) => is_block_like(&ast.exprs[e].kind),
+            StmtKind::VarDecl { .. } => false,
+        }
+    }
+
+    // Adds the `;` at `open` back when the text printed from `start` on
+    // would otherwise continue the block-like statement before it, as a
+    // call, index, member access or binary operator: `if c { x }; -1`
+    fn separate(&mut self, open: Option<usize>, start: usize) {
+        if let Some(at) = open
+            && self.out[start..].starts_with(['(', '[', '.', '-', '*', '&', '!'])
+        {
+            self.out.insert(at, ';');
+        }
+    }
+
+    fn stmt(&mut self, id: StmtId) {
+        let ctx = self.ctx;
+        match &ctx.ast.stmts[id].kind {
+            StmtKind::VarDecl {
+                is_mutable,
+                name,
+                ty,
+                init,
+            } => {
+                self.push(if *is_mutable { "var " } else { "val " });
+                self.sym(*name);
+                if let Some(ty) = ty {
+                    self.push(": ");
+                    self.ty(*ty);
+                }
+                self.push(" = ");
+                match init {
+                    VarInit::Expr(e) => self.expr(*e, PREC_ASSIGN),
+                    VarInit::Undefined => self.push("undefined"),
+                }
+                self.push(";");
+            }
+            StmtKind::Defer(e) => {
+                self.push("defer ");
+                self.expr(*e, PREC_ASSIGN);
+                if !is_block_like(&ctx.ast.exprs[*e].kind) {
+                    self.push(";");
+                }
+            }
+            StmtKind::Expr(e) => {
+                self.expr(*e, PREC_ASSIGN);
+                if !is_block_like(&ctx.ast.exprs[*e].kind) {
+                    self.push(";");
+                }
+            }
+        }
+    }
+
+    fn ty(&mut self, id: TypeSpecId) {
+        let ctx = self.ctx;
+        match &ctx.ast.type_specs[id].kind {
+            TypeSpecKind::Named(sym) => self.sym(*sym),
+            TypeSpecKind::Generic { base, args } => {
+                self.sym(*base);
+                self.type_args(args);
+            }
+            TypeSpecKind::Pointer(inner) => {
+                self.push("*");
+                self.ty(*inner);
+            }
+            TypeSpecKind::Reference(inner) => {
+                self.push("&");
+                self.ty(*inner);
+            }
+            TypeSpecKind::MutReference(inner) => {
+                self.push("&mut ");
+                self.ty(*inner);
+            }
+            TypeSpecKind::Optional(inner) => {
+                self.push("?");
+                self.ty(*inner);
+            }
+            TypeSpecKind::Array { size, elem_ty } => {
+                self.push("[");
+                self.expr(*size, PREC_ASSIGN);
+                self.push("]");
+                self.ty(*elem_ty);
+            }
+            TypeSpecKind::Slice(inner) => {
+                self.push("[]");
+                self.ty(*inner);
+            }
+            TypeSpecKind::Fn { param, return_ty } => {
+                self.push("fn(");
+                self.comma_sep(param, |p, ty| p.ty(ty));
+                self.push(") -> ");
+                self.ty(*return_ty);
+            }
+            TypeSpecKind::Paren(inner) => {
+                self.push("(");
+                self.ty(*inner);
+                self.push(")");
+            }
+            TypeSpecKind::Tuple(elems) => {
+                self.push("(");
+                self.comma_sep(elems, |p, ty| p.ty(ty));
+                if elems.len() == 1 {
+                    self.push(",");
+                }
+                self.push(")");
+            }
+            TypeSpecKind::Error => self.push("<error>"),
+        }
+    }
+
+    fn pattern(&mut self, id: PatternId) {
+        let ctx = self.ctx;
+        match &ctx.ast.patterns[id].kind {
+            PatternKind::Literal(lit) => self.literal(lit),
+            PatternKind::Identifier(sym) => self.sym(*sym),
+            PatternKind::Wildcard => self.push("_"),
+            PatternKind::Tuple(elems) => {
+                self.push("(");
+                self.comma_sep(elems, |p, elem| p.pattern(elem));
+                if elems.len() == 1 {
+                    self.push(",");
+                }
+                self.push(")");
+            }
+            PatternKind::Or(alternatives) => {
+                for (i, alt) in alternatives.iter().enumerate() {
+                    if i > 0 {
+                        self.push(" | ");
+                    }
+                    self.pattern(*alt);
+                }
+            }
+            PatternKind::Struct { name, fields } => {
+                self.sym(*name);
+                if fields.is_empty() {
+                    self.push(" {}");
+                    return;
+                }
+                self.push(" { ");
+                for (i, field) in fields.iter().enumerate() {
+                    if i > 0 {
+                        self.push(", ");
+                    }
+                    self.sym(field.name);
+                    self.push(": ");
+                    self.pattern(field.pattern);
+                }
+                self.push(" }");
+            }
+        }
+    }
+
+    fn item(&mut self, id: ItemId) {
+        let ctx = self.ctx;
+        for line in ctx.ast.items[id].doc.iter().flat_map(|doc| doc.split('\n')) {
+            self.push("///");
+            if !line.is_empty() {
+                self.push(" ");
+                self.push(line);
+            }
+            self.newline();
+        }
+        for attr in &ctx.ast.items[id].attrs {
+            self.push("@");
+            self.sym(attr.name);
+            if !attr.args.is_empty() {
+                self.push("(");
+                self.comma_sep(&attr.args, |p, arg| p.expr(arg, PREC_ASSIGN));
+                self.push(")");
+            }
+            self.newline();
+        }
+        match &ctx.ast.items[id].kind {
+            ItemKind::FnDecl {
+                name,
+                params,
+                ret_ty,
+                body,
+            } => {
+                self.fn_sig(*name, params, *ret_ty);
+                self.push(" ");
+                self.expr(*body, PREC_ASSIGN);
+            }
+            ItemKind::StructDecl { name, fields } => {
+                self.push("struct ");
+                self.sym(*name);
+                self.push(" ");
+                self.fields(fields);
+            }
+            ItemKind::EnumDecl {
+                name,
+                backing_ty,
+                variants,
+            } => {
+                self.push("enum ");
+                self.sym(*name);
+                if let Some(ty) = backing_ty {
+                    self.push(": ");
+                    self.ty(*ty);
+                }
+                self.open_body(variants.is_empty());
+                for variant in variants {
+                    self.newline();
+                    self.sym(variant.name);
+                    if let Some(value) = variant.value {
+                        self.push(&format!(" = {}", value));
+                    }
+                    self.push(",");
+                }
+                self.close_body(variants.is_empty());
+            }
+            ItemKind::UnionDecl { name, variants } => {
+                self.push("union ");
+                self.sym(*name);
+                self.open_body(variants.is_empty());
+                for variant in variants {
+                    self.newline();
+                    self.sym(variant.name);
+                    match &variant.data {
+                        None => {}
+                        Some(UnionVariantData::Tuple(types)) => {
+                            self.push("(");
+                            self.comma_sep(types, |p, ty| p.ty(ty));
+                            self.push(")");
+                        }
+                        Some(UnionVariantData::Struct(fields)) => {
+                            self.push(" ");
+                            self.fields(fields);
+                        }
+                    }
+                    self.push(",");
+                }
+                self.close_body(variants.is_empty());
+            }
+            ItemKind::ImplDecl { self_ty, methods } => {
+                self.push("impl ");
+                self.ty(*self_ty);
+                self.open_body(methods.is_empty());
+                for (i, method) in methods.iter().enumerate() {
+                    if i > 0 {
+                        self.out.push('\n');
+                    }
+                    self.newline();
+                    self.item(*method);
+                }
+                self.close_body(methods.is_empty());
+            }
+            ItemKind::ConstDecl { name, ty, expr } => {
+                self.push("const ");
+                self.sym(*name);
+                self.push(": ");
+                self.ty(*ty);
+                self.push(" = ");
+                self.expr(*expr, PREC_ASSIGN);
+                self.push(";");
+            }
+            ItemKind::ExternDecl { api, declarations } => {
+                self.push(&format!("extern {:?}", api));
+                self.open_body(declarations.is_empty());
+                for sig in declarations {
+                    self.newline();
+                    self.fn_sig(sig.name, &sig.params, sig.return_ty);
+                    self.push(";");
+                }
+                self.close_body(declarations.is_empty());
+            }
+        }
+    }
+
+    fn fn_sig(&mut self, name: Symbol, params: &[Param], ret_ty: Option<TypeSpecId>) {
+        self.push("fn ");
+        self.sym(name);
+        self.signature(params, ret_ty);
+    }
+
+    fn type_args(&mut self, args: &[TypeSpecId]) {
+        self.push("<");
+        self.comma_sep(args, |p, ty| p.ty(ty));
+        self.push(">");
+    }
+
+    fn signature(&mut self, params: &[Param], ret_ty: Option<TypeSpecId>) {
+        self.push("(");
+        for (i, param) in params.iter().enumerate() {
+            if i > 0 {
+                self.push(", ");
+            }
+            self.sym(param.name);
+            self.push(": ");
+            self.ty(param.ty);
+        }
+        self.push(")");
+        if let Some(ty) = ret_ty {
+            self.push(" -> ");
+            self.ty(ty);
+        }
+    }
+
+    fn fields(&mut self, fields: &[StructField]) {
+        if fields.is_empty() {
+            self.push("{}");
+            return;
+        }
+        self.push("{");
+        self.indent += 1;
+        for field in fields {
+            self.newline();
+            self.sym(field.name);
+            self.push(": ");
+            self.ty(field.ty);
+            self.push(",");
+        }
+        self.indent -= 1;
+        self.newline();
+        self.push("}");
+    }
+
+    // ` {` for an item body, `{}` on one line when there's nothing inside
+    fn open_body(&mut self, empty: bool) {
+        self.push(" {");
+        if !empty {
+            self.indent += 1;
+        }
+    }
+
+    fn close_body(&mut self, empty: bool) {
+        if !empty {
+            self.indent -= 1;
+            self.newline();
+        }
+        self.push("}");
+    }
+}
+
+fn expr_prec(kind: &ExprKind) -> u8 {
+    match kind {
+        ExprKind::Assign { .. } | ExprKind::Return(Some(_)) | ExprKind::Break(Some(_)) => {
+            PREC_ASSIGN
+        }
+        ExprKind::Pipeline { .. } => PREC_PIPELINE,
+        ExprKind::Binary { op, .. } => op.precedence(),
+        ExprKind::Cast { .. } => PREC_CAST,
+        // Folded constants can be negative. They print like a negation but
+        // also need parentheses as its operand, `-(-5)` rather than `--5`.
+        ExprKind::Literal(Literal::Int(i64::MIN..0)) => PREC_CAST,
+        ExprKind::Unary { .. } => PREC_UNARY,
+        ExprKind::Call { .. } | ExprKind::MemberAccess { .. } | ExprKind::IndexAccess { .. } => {
+            PREC_POSTFIX
+        }
+        _ => PREC_ATOM,
+    }
+}
+
+fn is_block_like(kind: &ExprKind) -> bool {
+    matches!(
+        kind,
+        ExprKind::Block { .. }
+            | ExprKind::If { .. }
+            | ExprKind::While { .. }
+            | ExprKind::Loop { .. }
+            | ExprKind::For { .. }
+            | ExprKind::Match { .. }
+    )
+}
+
+// The type suffix literal `id` was written with, `u8` for `255u8`. The
+// parser records it as the literal's `ty`, spanning the literal itself,
+// which tells it apart from a type given to the literal later.
+fn literal_suffix(ctx: &CompilerCtx, id: ExprId) -> Option<&str> {
+    let expr = &ctx.ast.exprs[id];
+    let ty = &ctx.ast.type_specs[expr.ty?];
+    match ty.kind {
+        TypeSpecKind::Named(sym) if ty.span == expr.span => Some(ctx.symbol_interner.resolve(sym)),
+        _ => None,
+    }
+}
+
+fn literal_str(lit: &Literal) -> String {
+    match lit {
+        Literal::Int(i) => i.to_string(),
+        Literal::Float(f) => format!("{:?}", f),
+        Literal::Bool(b) => b.to_string(),
+        Literal::Char(c) => format!("{:?}", c),
+        Literal::String(s) => format!("{:?}", s),
+        Literal::Null => "null".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::BinaryOp;
+    use crate::parser::Parser;
+
+    fn roundtrip_expr(src: &str) -> String {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let expr = Parser::new(src, &mut ctx).parse_expr();
+        assert!(!ctx.has_errors(), "{:?}", ctx.diagnostics);
+        print_expr(&ctx, expr)
+    }
+
+    fn roundtrip_program(src: &str) -> String {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let items = Parser::new(src, &mut ctx).parse_program();
+        assert!(!ctx.has_errors(), "{:?}", ctx.diagnostics);
+        print_program(&ctx, &items)
+    }
+
+    #[test]
+    fn test_print_separates_block_like_statements() {
+        for (src, printed) in [
+            (
+                "{ if c { x }; -1 }",
+                "{\n    if c {\n        x\n    };\n    -1\n}",
+            ),
+            ("{ {x}; (1) }", "{\n    {\n        x\n    };\n    (1)\n}"),
+            ("{ loop {}; [1] }", "{\n    loop {};\n    [1]\n}"),
+            (
+                "{ {x}; *p = 1; }",
+                "{\n    {\n        x\n    };\n    *p = 1;\n}",
+            ),
+            (
+                "{ defer {x}; !y }",
+                "{\n    defer {\n        x\n    };\n    !y\n}",
+            ),
+            // Nothing to continue, so no `;`
+            ("{ loop {} x }", "{\n    loop {}\n    x\n}"),
+        ] {
+            let out = roundtrip_expr(src);
+            assert_eq!(out, printed);
+            // and it reads back the same
+            assert_eq!(roundtrip_expr(&out), out);
+            assert_eq!(sexpr_of(&out), sexpr_of(src));
+        }
+    }
+
+    #[test]
+    fn test_print_literal_suffixes() {
+        assert_eq!(roundtrip_expr("255u8 + 1"), "255u8 + 1");
+        assert_eq!(roundtrip_expr("f(1.5f32, 2i64)"), "f(1.5f32, 2i64)");
+        assert_eq!(sexpr_of("255u8 + 1"), "(binary + (lit 255u8) (lit 1))");
+    }
+
+    #[test]
+    fn test_print_operators() {
+        assert_eq!(roundtrip_expr("1+2*3"), "1 + 2 * 3");
+        assert_eq!(roundtrip_expr("(1+2)*3"), "(1 + 2) * 3");
+        assert_eq!(roundtrip_expr("a-(b-c)"), "a - (b - c)");
+        assert_eq!(roundtrip_expr("-x as u8 |> f"), "-x as u8 |> f");
+        assert_eq!(roundtrip_expr("& &mut x && *&y"), "&&mut x && *&y");
+        assert_eq!(
+            roundtrip_expr("[P{x:1,y},[],Q{}]"),
+            "[P { x: 1, y: y }, [], Q {}]"
+        );
+        assert_eq!(roundtrip_expr("((a),(b,),())"), "((a), (b,), ())");
+        assert_eq!(
+            roundtrip_expr("if (P{x:1}) == p {}"),
+            "if (P { x: 1 }) == p {}"
+        );
+        assert_eq!(
+            roundtrip_expr("a = b += !c.d[0](1, 2)"),
+            "a = b += !c.d[0](1, 2)"
+        );
+        assert_eq!(
+            roundtrip_expr("1.0 == 'c' || \"s\\n\" != null"),
+            "1.0 == 'c' || \"s\\n\" != null"
+        );
+    }
+
+    #[test]
+    fn test_print_synthesized_parens() {
+        // Trees built by later passes may lack the `Paren` nodes the parser
+        // would have produced
+        let mut ctx = CompilerCtx::new("test", 16);
+        let expr = Parser::new("a * b", &mut ctx).parse_expr();
+        let ExprKind::Binary { lhs, rhs, .. } = ctx.ast.exprs[expr].kind else {
+            panic!("expected binary");
+        };
+        let sum = ctx.ast.exprs.alloc(crate::ast::Expr {
+            kind: ExprKind::Binary {
+                lhs,
+                op: BinaryOp::Add,
+                rhs,
+            },
+            span: crate::common::Span::DUMMY,
+            ty: None,
+        });
+        ctx.ast.exprs[expr].kind = ExprKind::Binary {
+            lhs: sum,
+            op: BinaryOp::Mul,
+            rhs,
+        };
+        assert_eq!(print_expr(&ctx, expr), "(a + b) * b");
+    }
+
+    #[test]
+    fn test_print_blocks() {
+        assert_eq!(
+            roundtrip_expr("{ val x: i32 = 1; if x { f() } else { } loop { break x; } x }"),
+            "{\n    val x: i32 = 1;\n    if x {\n        f()\n    } else {}\n    loop {\n        break x;\n    }\n    x\n}"
+        );
+        assert_eq!(
+            roundtrip_expr("match x { 1 | 2 => a, (y, _) => { b } P { q, r: 0 } => c }"),
+            "match x {\n    1 | 2 => a,\n    (y, _) => {\n        b\n    },\n    P { q: q, r: 0 } => c,\n}"
+        );
+    }
+
+    #[test]
+    fn test_print_items() {
+        let src = "struct P { x: *i32, y: [4]u8 } \
+                   enum E: u8 { A = 1, B } \
+                   union U { A, B(i32, ?T), C { x: []f32 } } \
+                   impl P { fn get(p: &P) -> i32 { p.x } fn nop() {} } \
+                   const N: fn(i32) -> void = f; \
+                   extern \"C\" { fn puts(s: *u8) -> i32; }";
+        let expected = "\
+struct P {
+    x: *i32,
+    y: [4]u8,
+}
+
+enum E: u8 {
+    A = 1,
+    B,
+}
+
+union U {
+    A,
+    B(i32, ?T),
+    C {
+        x: []f32,
+    },
+}
+
+impl P {
+    fn get(p: &P) -> i32 {
+        p.x
+    }
+
+    fn nop() {}
+}
+
+const N: fn(i32) -> void = f;
+
+extern \"C\" {
+    fn puts(s: *u8) -> i32;
+}
+";
+        assert_eq!(roundtrip_program(src), expected);
+        // The printed program parses back to the same tree
+        assert_eq!(roundtrip_program(expected), expected);
+    }
+
+    #[test]
+    fn test_print_attributes() {
+        let src = "@inline fn f() {} @align(4 * 2) @packed struct S {} impl S { @cold fn g() {} }";
+        let expected = "\
+@inline
+fn f() {}
+
+@align(4 * 2)
+@packed
+struct S {}
+
+impl S {
+    @cold
+    fn g() {}
+}
+";
+        assert_eq!(roundtrip_program(src), expected);
+        assert_eq!(roundtrip_program(expected), expected);
+    }
+
+    #[test]
+    fn test_print_doc_comments() {
+        let src = "///  Indented\n///\n/// done\n@inline fn f() {}\nimpl S {\n/// g\nfn g() {} }";
+        let expected = "\
+///  Indented
+///
+/// done
+@inline
+fn f() {}
+
+impl S {
+    /// g
+    fn g() {}
+}
+";
+        assert_eq!(roundtrip_program(src), expected);
+        assert_eq!(roundtrip_program(expected), expected);
+    }
+
+    fn sexpr_of(src: &str) -> String {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let expr = Parser::new(src, &mut ctx).parse_expr();
+        assert!(!ctx.has_errors(), "{:?}", ctx.diagnostics);
+        to_sexpr(&ctx, expr)
+    }
+
+    #[test]
+    fn test_print_types() {
+        // Postfix `?` comes back as the equivalent prefix form
+        for (src, printed) in [
+            ("*fn() -> i32", "*fn() -> i32"),
+            ("fn(i32) -> fn(i32) -> i32", "fn(i32) -> fn(i32) -> i32"),
+            ("(fn(i32) -> i32)?", "?(fn(i32) -> i32)"),
+            ("[](*u8)?", "[]?(*u8)"),
+            ("fn(fn()) -> [4]i32", "fn(fn() -> void) -> [4]i32"),
+            ("&&mut []T", "&&mut []T"),
+            ("((i32,), (), (u8, bool))", "((i32,), (), (u8, bool))"),
+            ("Map<str,Vec<*u8>>?", "?Map<str, Vec<*u8>>"),
+        ] {
+            let mut ctx = CompilerCtx::new("test", 16);
+            let ty = Parser::new(src, &mut ctx).parse_type();
+            assert!(!ctx.has_errors(), "{:?}", ctx.diagnostics);
+            assert_eq!(print_type(&ctx, ty), printed);
+        }
+    }
+
+    #[test]
+    fn test_sexpr_operators() {
+        assert_eq!(sexpr_of("1 + 2"), "(binary + (lit 1) (lit 2))");
+        assert_eq!(
+            sexpr_of("x = -(a as u8) |> f"),
+            "(assign = (ident x) (pipe (unary - (paren (cast (ident a) u8))) (ident f)))"
+        );
+        assert_eq!(
+            sexpr_of("a.b(c)[0]"),
+            "(index (call (member (ident a) b) (ident c)) (lit 0))"
+        );
+        assert_eq!(
+            sexpr_of("'c' == \"s\" || null != 1.5"),
+            "(binary || (binary == (lit 'c') (lit \"s\")) (binary != (lit null) (lit 1.5)))"
+        );
+    }
+
+    #[test]
+    fn test_sexpr_control_flow() {
+        assert_eq!(
+            sexpr_of("{ val x: i32 = 1; var y = undefined; defer f(); g(); x }"),
+            "(block (val x i32 (lit 1)) (var y _ undefined) (defer (call (ident f))) \
+             (stmt (call (ident g))) (yield (ident x)))"
+        );
+        assert_eq!(
+            sexpr_of("if c { } else { 1 }"),
+            "(if (ident c) (block) (block (yield (lit 1))))"
+        );
+        assert_eq!(
+            sexpr_of("match x { 1 | _ => y }"),
+            "(match (ident x) (case 1 | _ (ident y)))"
+        );
+        assert_eq!(
+            sexpr_of("loop { while c { break } else { continue } }"),
+            "(loop (block (yield (while (ident c) (block (yield (break))) (block (yield (continue)))))))"
+        );
+        assert_eq!(
+            sexpr_of("for i in xs { return i }"),
+            "(for i (ident xs) (block (yield (return (ident i)))))"
+        );
+    }
+
+    #[test]
+    fn test_print_type_args() {
+        assert_eq!(
+            roundtrip_expr("a.f::<Vec<i32>,u8>(x)"),
+            "a.f::<Vec<i32>, u8>(x)"
+        );
+        assert_eq!(roundtrip_expr("x as (Vec<i32>) < y"), "x as (Vec<i32>) < y");
+        assert_eq!(
+            sexpr_of("f::<Vec<i32>, u8>(1)"),
+            "(call (ident f) ::<Vec<i32>, u8> (lit 1))"
+        );
+    }
+
+    #[test]
+    fn test_closure() {
+        assert_eq!(
+            roundtrip_expr("map(xs,fn(x:i32)->i32{x*2})"),
+            "map(xs, fn(x: i32) -> i32 {\n    x * 2\n})"
+        );
+        assert_eq!(roundtrip_expr("fn(){}()"), "fn() {}()");
+        assert_eq!(
+            sexpr_of("fn(a: i32, b: *u8) -> bool { a }"),
+            "(closure (a: i32, b: *u8) -> bool (block (yield (ident a))))"
+        );
+    }
+
+    #[test]
+    fn test_sexpr_spans() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let expr = Parser::new("a + 1", &mut ctx).parse_expr();
+        assert_eq!(
+            to_sexpr_spanned(&ctx, expr),
+            "(binary + (ident a @0..1) (lit 1 @4..5) @0..5)"
+        );
+
+        let mut ctx = CompilerCtx::new("test", 16);
+        let expr = Parser::new("1 +", &mut ctx).parse_expr();
+        assert_eq!(to_sexpr(&ctx, expr), "(binary + (lit 1) (error))");
+    }
+}
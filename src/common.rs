@@ -1,44 +1,606 @@
-use std::collections::HashMap;
-use std::hash::Hash;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Span {
     pub start: u32,
     pub end: u32,
 }
 
 impl Span {
-    pub fn new(start: u32, end: u32) -> Self {
+    // For compiler-synthesized nodes that have no source text
+    pub const DUMMY: Span = Span { start: 0, end: 0 };
+
+    pub const fn new(start: u32, end: u32) -> Self {
         Self { start, end }
     }
+
+    // Zero-width span at `at`, for pointing between two tokens
+    pub const fn point(at: u32) -> Span {
+        Span::new(at, at)
+    }
+
+    // The first byte, which is the first character for the ASCII tokens
+    // this is used on. An empty span stays as it is.
+    pub const fn first_char(self) -> Span {
+        if self.is_empty() {
+            self
+        } else {
+            Span::new(self.start, self.start + 1)
+        }
+    }
+
+    // Offsets are half-open, so `end` itself isn't contained
+    pub const fn contains(&self, offset: u32) -> bool {
+        self.start <= offset && offset < self.end
+    }
+
+    pub const fn len(&self) -> u32 {
+        self.end - self.start
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    // Smallest span covering both `self` and `other`
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+
+    // Smallest span covering all of `spans`, or `DUMMY` if there are none
+    pub fn join(spans: impl IntoIterator<Item = Span>) -> Span {
+        spans.into_iter().reduce(Span::to).unwrap_or(Span::DUMMY)
+    }
 }
 
+// Index of a file in `SourceFiles`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FileId(pub u32);
+
+// A span together with the file its offsets are relative to
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileSpan {
+    pub file: FileId,
+    pub span: Span,
+}
+
+impl FileSpan {
+    pub fn new(file: FileId, span: Span) -> Self {
+        Self { file, span }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Symbol(pub u32);
 
+// Well-known names interned by `SymbolInterner::new`, so passes can match on
+// them without string comparisons. Each const is the index of its string in
+// `PREDEFINED_SYMBOLS`, and that order is guaranteed not to change.
+impl Symbol {
+    pub const MAIN: Symbol = Symbol(0);
+    pub const SELF: Symbol = Symbol(1);
+    pub const I8: Symbol = Symbol(2);
+    pub const I16: Symbol = Symbol(3);
+    pub const I32: Symbol = Symbol(4);
+    pub const I64: Symbol = Symbol(5);
+    pub const U8: Symbol = Symbol(6);
+    pub const U16: Symbol = Symbol(7);
+    pub const U32: Symbol = Symbol(8);
+    pub const U64: Symbol = Symbol(9);
+    pub const F32: Symbol = Symbol(10);
+    pub const F64: Symbol = Symbol(11);
+    pub const BOOL: Symbol = Symbol(12);
+    pub const CHAR: Symbol = Symbol(13);
+    pub const STR: Symbol = Symbol(14);
+    pub const VOID: Symbol = Symbol(15);
+    pub const UNDERSCORE: Symbol = Symbol(16);
+}
+
+const PREDEFINED_SYMBOLS: [&str; 17] = [
+    "main", "self", "i8", "i16", "i32", "i64", "u8", "u16", "u32", "u64", "f32", "f64", "bool",
+    "char", "str", "void", "_",
+];
+
+// Every string is stored once, back to back in `text`. `spans` maps a
+// symbol to its bytes and `table` is an open-addressing hash table of
+// symbol indices, probed linearly and compared against `text` directly, so
+// the strings never need a second owned copy as map keys.
 pub struct SymbolInterner {
-    strings: Vec<String>,
-    map: HashMap<String, u32>
+    text: String,
+    // (start, end) byte offsets into `text`, indexed by symbol
+    spans: Vec<(u32, u32)>,
+    // Power-of-two sized, `EMPTY` marks a free slot. Kept at most half full.
+    table: Vec<u32>,
+}
+
+const EMPTY: u32 = u32::MAX;
+
+// `DefaultHasher::new` uses fixed keys, so probing and therefore the
+// whole interner behave the same on every run
+fn hash_str(s: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    s.hash(&mut hasher);
+    hasher.finish()
 }
 
 impl SymbolInterner {
     pub fn new() -> Self {
-        Self { strings: vec![], map: HashMap::new() }
+        let mut interner = Self {
+            text: String::new(),
+            spans: Vec::new(),
+            table: vec![EMPTY; 64],
+        };
+        for s in PREDEFINED_SYMBOLS {
+            interner.intern(s);
+        }
+        interner
     }
 
     pub fn intern(&mut self, s: &str) -> Symbol {
-        if let Some(idx) = self.map.get(s) {
-            Symbol(*idx)
-        } else {
-            let idx = self.strings.len() as u32;
-            self.strings.push(s.to_string());
-            self.map.insert(s.to_string(), idx);
-            Symbol(idx)
+        let slot = match self.find(s) {
+            Ok(idx) => return Symbol(idx),
+            Err(slot) => slot,
+        };
+        let idx = self.spans.len() as u32;
+        let start = self.text.len() as u32;
+        self.text.push_str(s);
+        self.spans.push((start, self.text.len() as u32));
+        self.table[slot] = idx;
+        if self.spans.len() * 2 > self.table.len() {
+            self.grow();
+        }
+        Symbol(idx)
+    }
+
+    // The index of `s` if interned, otherwise the free slot it would go in
+    fn find(&self, s: &str) -> Result<u32, usize> {
+        let mask = self.table.len() - 1;
+        let mut slot = hash_str(s) as usize & mask;
+        loop {
+            match self.table[slot] {
+                EMPTY => return Err(slot),
+                idx if self.str_at(idx) == s => return Ok(idx),
+                _ => slot = (slot + 1) & mask,
+            }
+        }
+    }
+
+    fn grow(&mut self) {
+        let mut table = vec![EMPTY; self.table.len() * 2];
+        let mask = table.len() - 1;
+        for idx in 0..self.spans.len() as u32 {
+            let mut slot = hash_str(self.str_at(idx)) as usize & mask;
+            while table[slot] != EMPTY {
+                slot = (slot + 1) & mask;
+            }
+            table[slot] = idx;
         }
+        self.table = table;
     }
-    
+
+    fn str_at(&self, idx: u32) -> &str {
+        let (start, end) = self.spans[idx as usize];
+        &self.text[start as usize..end as usize]
+    }
+
     pub fn resolve(&self, symbol: Symbol) -> &str {
-        debug_assert!(symbol.0 < self.strings.len() as u32);
-        &self.strings[symbol.0 as usize]
+        debug_assert!(symbol.0 < self.spans.len() as u32);
+        self.str_at(symbol.0)
+    }
+
+    // Bounds-checked `resolve`, for symbols that may come from another interner
+    pub fn try_resolve(&self, symbol: Symbol) -> Option<&str> {
+        ((symbol.0 as usize) < self.spans.len()).then(|| self.str_at(symbol.0))
+    }
+
+    // Looks `s` up without interning it
+    pub fn get(&self, s: &str) -> Option<Symbol> {
+        self.find(s).ok().map(Symbol)
+    }
+
+    // Bytes allocated on the heap, for comparing against a plain map
+    pub fn heap_size(&self) -> usize {
+        self.text.capacity()
+            + self.spans.capacity() * std::mem::size_of::<(u32, u32)>()
+            + self.table.capacity() * std::mem::size_of::<u32>()
+    }
+
+    // Number of interned strings, predefined ones included
+    pub fn len(&self) -> usize {
+        self.spans.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.spans.is_empty()
+    }
+
+    // Every symbol with its string in index order, which is insertion
+    // order. Interning the strings in this order into a fresh interner
+    // gives back the same symbols.
+    pub fn iter(&self) -> impl Iterator<Item = (Symbol, &str)> {
+        (0..self.spans.len() as u32).map(|idx| (Symbol(idx), self.str_at(idx)))
+    }
+
+    // Formats as the symbol's name, e.g. `println!("{}", interner.display(sym))`
+    pub fn display(&self, symbol: Symbol) -> SymbolDisplay<'_> {
+        SymbolDisplay {
+            symbol,
+            interner: self,
+        }
+    }
+}
+
+pub struct SymbolDisplay<'a> {
+    symbol: Symbol,
+    interner: &'a SymbolInterner,
+}
+
+impl fmt::Display for SymbolDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.interner.try_resolve(self.symbol) {
+            Some(name) => f.pad(name),
+            // From some other interner, still worth printing something
+            None => write!(f, "{:?}", self.symbol),
+        }
+    }
+}
+
+// Translates byte offsets into 1-based (line, column) pairs. Columns count
+// Unicode scalar values, so multi-byte characters are recorded alongside the
+// line starts. A tab counts as a fixed `tab_width` columns, 1 unless
+// configured, wherever it sits on the line rather than running up to the
+// next tab stop. `Diagnostic::render` expands it to as many spaces.
+pub struct SourceMap {
+    line_starts: Vec<u32>,
+    // (byte offset, UTF-8 length) of every non-ASCII char, in source order
+    multibyte_chars: Vec<(u32, u8)>,
+    // Byte offset of every tab, in source order
+    tabs: Vec<u32>,
+    tab_width: u32,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self {
+            line_starts: vec![0],
+            multibyte_chars: vec![],
+            tabs: vec![],
+            tab_width: 1,
+        }
+    }
+
+    // Tabs are recorded either way, so this can be set before or after the
+    // source is read. 0 is taken as 1, a tab always takes up a column.
+    pub fn with_tab_width(mut self, tab_width: u32) -> Self {
+        self.tab_width = tab_width.max(1);
+        self
+    }
+
+    pub fn tab_width(&self) -> u32 {
+        self.tab_width
+    }
+
+    pub fn from_source(src: &str) -> Self {
+        let mut map = Self::new();
+        for (idx, c) in src.char_indices() {
+            map.record_char(idx as u32, c);
+        }
+        map
     }
-}
\ No newline at end of file
+
+    // Must be called once per char, in increasing offset order
+    pub fn record_char(&mut self, offset: u32, c: char) {
+        if c == '\n' {
+            self.line_starts.push(offset + 1);
+        } else if c == '\t' {
+            self.tabs.push(offset);
+        } else if c.len_utf8() > 1 {
+            self.multibyte_chars.push((offset, c.len_utf8() as u8));
+        }
+    }
+
+    // Byte offset where the 1-based `line` begins
+    pub fn line_start(&self, line: u32) -> u32 {
+        self.line_starts[line as usize - 1]
+    }
+
+    pub fn locate(&self, offset: u32) -> (u32, u32) {
+        let line = match self.line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(next) => next - 1,
+        };
+        let line_start = self.line_starts[line];
+
+        let lo = self.multibyte_chars.partition_point(|&(pos, _)| pos < line_start);
+        let hi = self.multibyte_chars.partition_point(|&(pos, _)| pos < offset);
+        let extra_bytes: u32 = self.multibyte_chars[lo..hi]
+            .iter()
+            .map(|&(_, len)| len as u32 - 1)
+            .sum();
+        let tabs = self.tabs.partition_point(|&pos| pos < offset)
+            - self.tabs.partition_point(|&pos| pos < line_start);
+        let extra_columns = tabs as u32 * (self.tab_width - 1);
+
+        (line as u32 + 1, offset - line_start - extra_bytes + extra_columns + 1)
+    }
+}
+
+pub struct SourceFile {
+    pub name: String,
+    pub contents: String,
+    pub map: SourceMap,
+}
+
+// Every file taking part in a compilation, addressed by `FileId`
+pub struct SourceFiles {
+    files: Vec<SourceFile>,
+    // Columns a tab counts for in the files added from then on, see
+    // `SourceMap::with_tab_width`
+    pub tab_width: u32,
+}
+
+impl SourceFiles {
+    pub fn new() -> Self {
+        Self { files: Vec::new(), tab_width: 1 }
+    }
+
+    pub fn add(&mut self, name: &str, contents: &str) -> FileId {
+        let id = FileId(self.files.len() as u32);
+        self.files.push(SourceFile {
+            name: name.to_string(),
+            contents: contents.to_string(),
+            map: SourceMap::from_source(contents).with_tab_width(self.tab_width),
+        });
+        id
+    }
+
+    pub fn get(&self, id: FileId) -> &SourceFile {
+        &self.files[id.0 as usize]
+    }
+
+    // `get` for ids that may not have been registered
+    pub fn try_get(&self, id: FileId) -> Option<&SourceFile> {
+        self.files.get(id.0 as usize)
+    }
+
+    // `file:line:col` for the start of `span`
+    pub fn location(&self, span: FileSpan) -> String {
+        let file = self.get(span.file);
+        let (line, col) = file.map.locate(span.span.start);
+        format!("{}:{}:{}", file.name, line, col)
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+}
+
+// Levenshtein distance counted in characters: the fewest single-character
+// insertions, deletions and substitutions that turn `a` into `b`
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    // Distances from the prefix of `a` seen so far to every prefix of `b`
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitute = diagonal + (ca != cb) as usize;
+            diagonal = row[j + 1];
+            row[j + 1] = substitute.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+// The candidate closest to a misspelled `name`, for "did you mean" notes.
+// Short names only get a suggestion one edit away and nothing is suggested
+// more than two edits away. Ties go to the earliest candidate.
+pub fn closest_match<'a>(
+    name: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Option<&'a str> {
+    let limit = (name.chars().count().max(3) / 3).min(2);
+    candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|&(distance, _)| distance <= limit)
+        .min_by_key(|&(distance, _)| distance)
+        .map(|(_, candidate)| candidate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_span_merge() {
+        let a = Span::new(4, 7);
+        let b = Span::new(10, 12);
+        assert_eq!(a.to(b), Span::new(4, 12));
+        assert_eq!(b.to(a), Span::new(4, 12));
+        assert_eq!(a.to(Span::new(5, 6)), a);
+
+        assert_eq!(Span::join([b, Span::new(1, 2), a]), Span::new(1, 12));
+        assert_eq!(Span::join([]), Span::DUMMY);
+    }
+
+    #[test]
+    fn test_span_helpers() {
+        let span = Span::new(4, 7);
+        assert_eq!(span.len(), 3);
+        assert!(!span.is_empty());
+        assert_eq!(span.first_char(), Span::new(4, 5));
+        assert!(span.contains(4) && span.contains(6));
+        assert!(!span.contains(3) && !span.contains(7));
+
+        let point = Span::point(9);
+        assert_eq!(point, Span::new(9, 9));
+        assert_eq!(point.len(), 0);
+        assert!(point.is_empty());
+        assert_eq!(point.first_char(), point);
+        assert!(!point.contains(9));
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("abc", ""), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("retrun", "return"), 2);
+        assert_eq!(edit_distance("fnn", "fn"), 1);
+        assert_eq!(edit_distance("flaw", "lawn"), 2);
+        assert_eq!(edit_distance("über", "uber"), 1);
+        assert_eq!(edit_distance("same", "same"), 0);
+    }
+
+    #[test]
+    fn test_closest_match() {
+        let words = ["fn", "for", "return", "struct"];
+        assert_eq!(closest_match("retrun", words), Some("return"));
+        assert_eq!(closest_match("strcut", words), Some("struct"));
+        assert_eq!(closest_match("fo", words), Some("fn"));
+        assert_eq!(closest_match("foo", words), Some("for"));
+        assert_eq!(closest_match("rtrn", words), None);
+        assert_eq!(closest_match("ab", words), None);
+        assert_eq!(closest_match("x", ["y"]), Some("y"));
+        assert_eq!(closest_match("x", []), None);
+    }
+
+    #[test]
+    fn test_interner_lookup() {
+        let mut interner = SymbolInterner::new();
+        let a = interner.intern("a");
+        let next = Symbol(a.0 + 1);
+        assert_eq!(interner.intern("a"), a);
+        assert_eq!(interner.try_resolve(a), Some("a"));
+        assert_eq!(interner.try_resolve(next), None);
+        assert_eq!(interner.get("a"), Some(a));
+        assert_eq!(interner.get("b"), None);
+        // `get` must not have interned "b"
+        assert_eq!(interner.try_resolve(next), None);
+    }
+
+    #[test]
+    fn test_interner_many_symbols() {
+        let mut interner = SymbolInterner::new();
+        let names: Vec<String> = (0..5000).map(|i| format!("identifier_number_{}", i)).collect();
+        let syms: Vec<_> = names.iter().map(|n| interner.intern(n)).collect();
+        for (name, sym) in names.iter().zip(&syms) {
+            assert_eq!(interner.intern(name), *sym);
+            assert_eq!(interner.resolve(*sym), name);
+        }
+        assert_eq!(syms[0], Symbol(PREDEFINED_SYMBOLS.len() as u32));
+        assert_eq!(interner.get("identifier_number_"), None);
+
+        // Each string is stored once. Keeping a `String` in a vector and
+        // another as map key took twice the text plus two 24 byte headers
+        // per name, before counting the map's own buckets.
+        let text: usize = names.iter().map(String::len).sum();
+        let stored_twice = 2 * text + 48 * names.len();
+        assert!(interner.heap_size() < stored_twice * 2 / 3);
+    }
+
+    #[test]
+    fn test_interner_iter_round_trips() {
+        let mut interner = SymbolInterner::new();
+        let point = interner.intern("point");
+        let x = interner.intern("x");
+        assert_eq!(interner.len(), PREDEFINED_SYMBOLS.len() + 2);
+
+        let all: Vec<_> = interner.iter().collect();
+        assert_eq!(all[0], (Symbol::MAIN, "main"));
+        assert_eq!(all[all.len() - 2..], [(point, "point"), (x, "x")]);
+
+        let mut reloaded = SymbolInterner::new();
+        for (sym, s) in interner.iter() {
+            assert_eq!(reloaded.intern(s), sym);
+        }
+        assert_eq!(reloaded.len(), interner.len());
+    }
+
+    #[test]
+    fn test_symbol_display() {
+        let mut interner = SymbolInterner::new();
+        let sym = interner.intern("point");
+        assert_eq!(format!("{}", interner.display(sym)), "point");
+        assert_eq!(format!("<{:>4}>", interner.display(Symbol::U8)), "<  u8>");
+        assert_eq!(interner.display(Symbol(999)).to_string(), "Symbol(999)");
+    }
+
+    #[test]
+    fn test_predefined_symbols() {
+        let mut interner = SymbolInterner::new();
+        assert_eq!(interner.intern("main"), Symbol::MAIN);
+        assert_eq!(interner.intern("i32"), Symbol::I32);
+        assert_eq!(interner.resolve(Symbol::SELF), "self");
+        assert_eq!(interner.resolve(Symbol::U64), "u64");
+        assert_eq!(interner.resolve(Symbol::STR), "str");
+        assert_eq!(interner.intern("x"), Symbol(PREDEFINED_SYMBOLS.len() as u32));
+    }
+
+    #[test]
+    fn test_locate_lines() {
+        let map = SourceMap::from_source("ab\ncd\n\nef");
+        assert_eq!(map.locate(0), (1, 1));
+        assert_eq!(map.locate(1), (1, 2));
+        assert_eq!(map.locate(3), (2, 1));
+        assert_eq!(map.locate(6), (3, 1));
+        assert_eq!(map.locate(8), (4, 2));
+    }
+
+    #[test]
+    fn test_locate_counts_chars_not_bytes() {
+        let src = "é😀x\nü y";
+        let map = SourceMap::from_source(src);
+        assert_eq!(map.locate(src.find('x').unwrap() as u32), (1, 3));
+        assert_eq!(map.locate(src.find('y').unwrap() as u32), (2, 3));
+    }
+
+    #[test]
+    fn test_locate_tab_width() {
+        let src = "\tx\n \t\t y\n\tz";
+        let [x, y, z] = ['x', 'y', 'z'].map(|c| src.find(c).unwrap() as u32);
+        let map = SourceMap::from_source(src);
+        assert_eq!([map.locate(x), map.locate(y), map.locate(z)], [(1, 2), (2, 5), (3, 2)]);
+
+        let map = SourceMap::from_source(src).with_tab_width(4);
+        assert_eq!(map.tab_width(), 4);
+        assert_eq!([map.locate(x), map.locate(y), map.locate(z)], [(1, 5), (2, 11), (3, 5)]);
+        // Byte offsets don't move
+        assert_eq!(map.line_start(2), 3);
+    }
+
+    #[test]
+    fn test_zero_tab_width() {
+        let map = SourceMap::from_source("\tx").with_tab_width(0);
+        assert_eq!(map.tab_width(), 1);
+        assert_eq!(map.locate(1), (1, 2));
+    }
+
+    #[test]
+    fn test_source_files() {
+        let mut files = SourceFiles::new();
+        let a = files.add("a.nv", "fn a() {}");
+        let b = files.add("b.nv", "\n  fn b() {}");
+        assert_ne!(a, b);
+        assert_eq!(files.len(), 2);
+        assert_eq!(files.get(b).name, "b.nv");
+        assert_eq!(files.get(a).contents, "fn a() {}");
+        assert_eq!(files.location(FileSpan::new(a, Span::new(3, 4))), "a.nv:1:4");
+        assert_eq!(files.location(FileSpan::new(b, Span::new(3, 5))), "b.nv:2:3");
+
+        files.tab_width = 4;
+        let c = files.add("c.nv", "\tfn c() {}");
+        assert_eq!(files.location(FileSpan::new(c, Span::new(1, 3))), "c.nv:1:5");
+        // Files added before keep their width
+        assert_eq!(files.get(a).map.tab_width(), 1);
+    }
+}
@@ -1,25 +1,218 @@
-use crate::common::{SymbolInterner, Span, Symbol};
+use crate::common::{FileId, FileSpan, SourceFiles, SourceMap, SymbolInterner, Span};
 use crate::ast::Ast;
+use crate::errors::ErrorCode;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum DiagnosticLevel { Info, Warning, Error }
 
 #[derive(Debug)]
 pub struct Diagnostic {
     pub level: DiagnosticLevel,
+    // Stable code from `errors::ErrorCode`, if the diagnostic has one
+    pub code: Option<&'static str>,
     pub message: String,
-    pub span: Span
+    pub span: Span,
+    // File `span` and the labels point into. Left unset when building and
+    // filled in from `CompilerCtx::current_file` when emitted.
+    pub file: Option<FileId>,
+    // `CompilerCtx::current_pass` at the time it was emitted
+    pub pass: Option<&'static str>,
+    // Secondary spans pointing at related code, each with its own message
+    pub labels: Vec<(Span, String)>,
+    // Help text printed after the snippets
+    pub notes: Vec<String>,
+}
+
+impl DiagnosticLevel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DiagnosticLevel::Info => "info",
+            DiagnosticLevel::Warning => "warning",
+            DiagnosticLevel::Error => "error",
+        }
+    }
+}
+
+impl Diagnostic {
+    pub fn new(level: DiagnosticLevel, message: &str, span: Span) -> Self {
+        Diagnostic {
+            level,
+            code: None,
+            message: message.to_string(),
+            span,
+            file: None,
+            pass: None,
+            labels: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn with_code(mut self, code: &'static str) -> Self {
+        self.code = Some(code);
+        self
+    }
+
+    pub fn in_file(mut self, file: FileId) -> Self {
+        self.file = Some(file);
+        self
+    }
+
+    pub fn with_label(mut self, span: Span, message: &str) -> Self {
+        self.labels.push((span, message.to_string()));
+        self
+    }
+
+    pub fn with_note(mut self, note: &str) -> Self {
+        self.notes.push(note.to_string());
+        self
+    }
+
+    // Formats the diagnostic for the terminal, e.g.
+    //
+    //   error: duplicate field `x`
+    //    --> 2:5
+    //     |
+    //   2 |     x: u8,
+    //     |     ^
+    //   1 | struct P { x: i32,
+    //     |            - first defined here
+    //     = note: field names must be unique
+    //
+    // A span running over several lines is underlined up to the end of its
+    // first line, followed by `...`.
+    pub fn render(&self, source: &str, map: &SourceMap) -> String {
+        self.render_at(source, map, "")
+    }
+
+    // Like `render`, but fetches the source from the diagnostic's file and
+    // names it in the location, e.g. ` --> main.nv:2:5`. Without a file
+    // registered in `files` there is nothing to point into, so only the
+    // message and notes are printed.
+    pub fn render_in(&self, files: &SourceFiles) -> String {
+        match self.file.and_then(|id| files.try_get(id)) {
+            Some(file) => self.render_at(&file.contents, &file.map, &format!("{}:", file.name)),
+            None => {
+                let mut out = self.header();
+                for note in &self.notes {
+                    out += &format!("  = note: {}\n", note);
+                }
+                out
+            }
+        }
+    }
+
+    // `error[E0001]: message` and a newline
+    fn header(&self) -> String {
+        match self.code {
+            Some(code) => format!("{}[{}]: {}\n", self.level.as_str(), code, self.message),
+            None => format!("{}: {}\n", self.level.as_str(), self.message),
+        }
+    }
+
+    fn render_at(&self, source: &str, map: &SourceMap, file_name: &str) -> String {
+        let (line, col) = map.locate(self.span.start);
+        let widest = self.labels.iter()
+            .map(|(span, _)| map.locate(span.start).0)
+            .fold(line, u32::max);
+        let gutter = " ".repeat(widest.to_string().len());
+
+        let mut out = self.header();
+        out += &format!("{}--> {}{}:{}\n", gutter, file_name, line, col);
+        out += &format!("{} |\n", gutter);
+        out += &snippet(source, map, self.span, '^', &gutter);
+        out.push('\n');
+        for (span, message) in &self.labels {
+            out += &snippet(source, map, *span, '-', &gutter);
+            out += &format!(" {}\n", message);
+        }
+        for note in &self.notes {
+            out += &format!("{} = note: {}\n", gutter, note);
+        }
+        out
+    }
+}
+
+// The source line holding `span` and an underline beneath it, without a
+// trailing newline
+fn snippet(source: &str, map: &SourceMap, span: Span, marker: char, gutter: &str) -> String {
+    let (line, col) = map.locate(span.start);
+    let line_start = map.line_start(line) as usize;
+    let text = source[line_start..].lines().next().unwrap_or("");
+    let line_end = line_start + text.len();
+
+    let end = (span.end as usize).min(line_end);
+    let width = (map.locate(end as u32).1 - col).max(1) as usize;
+    let more = span.end as usize > line_end + 1;
+
+    // Tabs are expanded the way the map counts them so the marker lines up
+    // whatever the terminal's tab stops are
+    let text = text.replace('\t', &" ".repeat(map.tab_width() as usize));
+    let number = format!("{:>1$}", line, gutter.len());
+    let mut out = format!("{} | {}\n", number, text);
+    out += &format!("{} | {}{}", gutter, " ".repeat(col as usize - 1), marker.to_string().repeat(width));
+    if more {
+        out += "...";
+    }
+    out
+}
+
+// Diagnostics by file, start offset then level. The rest of the key only
+// keeps duplicates next to each other for `dedup_by`.
+fn source_order(a: &Diagnostic, b: &Diagnostic) -> std::cmp::Ordering {
+    (a.file, a.span.start, a.level, a.span.end, &a.message)
+        .cmp(&(b.file, b.span.start, b.level, b.span.end, &b.message))
+}
+
+// `1 error`, `2 errors`
+fn plural(count: usize, noun: &str) -> String {
+    if count == 1 { format!("1 {}", noun) } else { format!("{} {}s", count, noun) }
+}
+
+// `s` as a quoted JSON string
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out += "\\\"",
+            '\\' => out += "\\\\",
+            '\n' => out += "\\n",
+            '\r' => out += "\\r",
+            '\t' => out += "\\t",
+            c if (c as u32) < 0x20 => out += &format!("\\u{:04x}", c as u32),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
 }
 
 pub struct CompilerCtx {
     pub symbol_interner: SymbolInterner,
     pub ast: Ast,
 
+    pub files: SourceFiles,
+    // File being lexed and parsed, stamped on diagnostics as they come in
+    pub current_file: FileId,
+
     pub diagnostics: Vec<Diagnostic>,
 
     pub target: String,
 
     pub current_pass: &'static str,
+
+    // Whether `finalize_diagnostics` drops exact duplicates, turned off
+    // when debugging recovery to see everything that was reported
+    pub dedup_diagnostics: bool,
+
+    // Errors collected before giving up on the rest of the input
+    pub max_errors: usize,
+    error_count: usize,
+    aborted: bool,
+
+    // Warnings count as errors, both for `has_errors` (so they fail the
+    // build and stop the passes) and in the `emit_all` summary
+    pub warnings_as_errors: bool,
 }
 
 impl CompilerCtx {
@@ -27,17 +220,445 @@ impl CompilerCtx {
         CompilerCtx {
             symbol_interner: SymbolInterner::new(),
             ast: Ast::new(arena_chunk_size),
+            files: SourceFiles::new(),
+            current_file: FileId(0),
             diagnostics: Vec::new(),
             target: target.to_string(),
-            current_pass: "Init"
+            current_pass: "Init",
+            dedup_diagnostics: true,
+            max_errors: 100,
+            error_count: 0,
+            aborted: false,
+            warnings_as_errors: false,
         }
     }
 
     pub fn report(&mut self, level: DiagnosticLevel, message: &str, span: Span) {
-        self.diagnostics.push(Diagnostic { level, message: message.to_string(), span })
+        self.emit(Diagnostic::new(level, message, span))
+    }
+
+    // Reports the error registered under `code`, filling its message
+    // template with `args`
+    pub fn report_code(&mut self, code: ErrorCode, span: Span, args: &[&str]) {
+        self.emit(code.diagnostic(span, args))
+    }
+
+    // Records a diagnostic built with labels or notes attached. Once
+    // `max_errors` errors are in, the next one is replaced by a final
+    // "too many errors" and everything after that is dropped.
+    pub fn emit(&mut self, mut diagnostic: Diagnostic) {
+        diagnostic.file.get_or_insert(self.current_file);
+        diagnostic.pass.get_or_insert(self.current_pass);
+        if self.aborted {
+            return;
+        }
+        if diagnostic.level == DiagnosticLevel::Error {
+            if self.error_count >= self.max_errors {
+                self.aborted = true;
+                // Points where the dropped error did, in the same file
+                self.diagnostics.push(Diagnostic {
+                    file: diagnostic.file,
+                    pass: diagnostic.pass,
+                    ..Diagnostic::new(
+                        DiagnosticLevel::Error,
+                        "too many errors, aborting",
+                        diagnostic.span,
+                    )
+                });
+                return;
+            }
+            self.error_count += 1;
+        }
+        self.diagnostics.push(diagnostic)
+    }
+
+    // Reports an error in a file other than the current one
+    pub fn report_at(&mut self, level: DiagnosticLevel, message: &str, span: FileSpan) {
+        self.emit(Diagnostic::new(level, message, span.span).in_file(span.file))
+    }
+
+    // Whether the error cap was hit and passes should stop early
+    pub fn should_abort(&self) -> bool {
+        self.aborted
+    }
+
+    // Serializes the diagnostics as a JSON array for editors and other
+    // tools. Each element has the shape
+    //
+    //   {"level": "error", "code": "E0001", "message": "...",
+    //    "file": "main.nv", "start": 4, "end": 7, "line": 1, "column": 5,
+    //    "labels": [{"start": 0, "end": 2, "line": 1, "column": 1,
+    //                "message": "..."}],
+    //    "notes": ["..."]}
+    //
+    // `start`/`end` are byte offsets into `file`, which labels share.
+    // `code` is null for uncoded diagnostics. `file`, `line` and `column`
+    // (1-based, counted in chars) are null when the diagnostic's file isn't
+    // in `files`. Keys are only ever added, never renamed.
+    pub fn diagnostics_to_json(&self) -> String {
+        let mut out = String::from("[");
+        for (i, d) in self.diagnostics.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            let code = match d.code {
+                Some(code) => json_string(code),
+                None => "null".to_string(),
+            };
+            let file = d.file.and_then(|id| self.files.try_get(id));
+            let name = file.map_or("null".to_string(), |f| json_string(&f.name));
+            // `"line":1,"column":5`
+            let position = |offset: u32| match file {
+                Some(file) => {
+                    let (line, col) = file.map.locate(offset);
+                    format!("\"line\":{},\"column\":{}", line, col)
+                }
+                None => "\"line\":null,\"column\":null".to_string(),
+            };
+            out += &format!(
+                "{{\"level\":{},\"code\":{},\"message\":{},\"file\":{},\"start\":{},\"end\":{},{},\"labels\":[",
+                json_string(d.level.as_str()), code, json_string(&d.message), name,
+                d.span.start, d.span.end, position(d.span.start)
+            );
+            for (j, (span, message)) in d.labels.iter().enumerate() {
+                if j > 0 {
+                    out.push(',');
+                }
+                out += &format!(
+                    "{{\"start\":{},\"end\":{},{},\"message\":{}}}",
+                    span.start, span.end, position(span.start), json_string(message)
+                );
+            }
+            out += "],\"notes\":[";
+            let notes: Vec<_> = d.notes.iter().map(|n| json_string(n)).collect();
+            out += &notes.join(",");
+            out += "]}";
+        }
+        out.push(']');
+        out
+    }
+
+    // Puts the diagnostics in source order, by file, start offset then
+    // level, so output is the same however the passes walked the tree.
+    // Diagnostics with the same level, message and span are reported once.
+    pub fn finalize_diagnostics(&mut self) {
+        // "too many errors" stays last whatever its span
+        let cutoff = if self.aborted { self.diagnostics.pop() } else { None };
+        self.diagnostics.sort_by(source_order);
+        if self.dedup_diagnostics {
+            self.diagnostics.dedup_by(|a, b| {
+                a.level == b.level
+                    && a.message == b.message
+                    && a.span == b.span
+                    && a.file == b.file
+            });
+        }
+        self.diagnostics.extend(cutoff);
+    }
+
+    // Renders every diagnostic in source order, separated by blank lines,
+    // followed by a summary such as `3 errors, 1 warning`. This is what
+    // the command line prints. Diagnostics are shown in their own file from
+    // `files`, `source` is only used for those whose file isn't registered.
+    // Returns an empty string when there is nothing to report.
+    pub fn emit_all(&self, source: &str) -> String {
+        let map = SourceMap::from_source(source);
+        let mut sorted: Vec<&Diagnostic> = self.diagnostics.iter().collect();
+        // "too many errors" stays last, as in `finalize_diagnostics`
+        let cutoff = if self.aborted { sorted.pop() } else { None };
+        sorted.sort_by(|a, b| source_order(a, b));
+        sorted.extend(cutoff);
+
+        let rendered: Vec<String> = sorted.iter()
+            .map(|d| match d.file.and_then(|id| self.files.try_get(id)) {
+                Some(_) => d.render_in(&self.files),
+                None => d.render(source, &map),
+            })
+            .collect();
+        let mut out = rendered.join("\n");
+
+        let (mut errors, mut warnings) = (0, 0);
+        for d in &sorted {
+            match d.level {
+                DiagnosticLevel::Error => errors += 1,
+                DiagnosticLevel::Warning if self.warnings_as_errors => errors += 1,
+                DiagnosticLevel::Warning => warnings += 1,
+                DiagnosticLevel::Info => {}
+            }
+        }
+        let mut counts = Vec::new();
+        if errors > 0 {
+            counts.push(plural(errors, "error"));
+        }
+        if warnings > 0 {
+            counts.push(plural(warnings, "warning"));
+        }
+        if !counts.is_empty() {
+            out += &format!("\n{}\n", counts.join(", "));
+        }
+        out
     }
 
     pub fn has_errors(&self) -> bool {
-        self.diagnostics.iter().any(|d| matches!(d.level, DiagnosticLevel::Error))
+        self.diagnostics.iter().any(|d| match d.level {
+            DiagnosticLevel::Error => true,
+            DiagnosticLevel::Warning => self.warnings_as_errors,
+            DiagnosticLevel::Info => false,
+        })
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(source: &str, start: u32, end: u32) -> String {
+        let diag = Diagnostic::new(DiagnosticLevel::Error, "oops", Span::new(start, end));
+        diag.render(source, &SourceMap::from_source(source))
+    }
+
+    #[test]
+    fn test_render_single_line() {
+        let src = "fn main() {\n    val x = );\n}";
+        assert_eq!(
+            render(src, 24, 25),
+            "error: oops\n --> 2:13\n  |\n2 |     val x = );\n  |             ^\n"
+        );
+        assert_eq!(
+            render(src, 16, 19),
+            "error: oops\n --> 2:5\n  |\n2 |     val x = );\n  |     ^^^\n"
+        );
+    }
+
+    #[test]
+    fn test_render_multi_line() {
+        let src = "a = {\n  b\n}";
+        assert_eq!(render(src, 4, 11), "error: oops\n --> 1:5\n  |\n1 | a = {\n  |     ^...\n");
+    }
+
+    #[test]
+    fn test_render_unicode_and_eof() {
+        let src = "\"héllo\" +";
+        // The caret counts characters, not bytes
+        assert_eq!(render(src, 0, 8), "error: oops\n --> 1:1\n  |\n1 | \"héllo\" +\n  | ^^^^^^^\n");
+        // Empty spans at the end of input still get a caret
+        assert_eq!(render(src, 10, 10), "error: oops\n --> 1:10\n  |\n1 | \"héllo\" +\n  |          ^\n");
+    }
+
+    #[test]
+    fn test_render_tabs() {
+        let src = "fn f() {\n\t  \tval x\t= );\n}";
+        let diag = Diagnostic::new(DiagnosticLevel::Error, "oops", Span::new(21, 22));
+        assert_eq!(
+            diag.render(src, &SourceMap::from_source(src)),
+            "error: oops\n --> 2:13\n  |\n2 |     val x = );\n  |             ^\n"
+        );
+        let diag = Diagnostic::new(DiagnosticLevel::Error, "oops", Span::new(13, 20));
+        assert_eq!(
+            diag.render(src, &SourceMap::from_source(src).with_tab_width(4)),
+            "error: oops\n --> 2:11\n  |\n2 |           val x    = );\n  |           ^^^^^^^^^^\n"
+        );
+    }
+
+    #[test]
+    fn test_render_labels_and_notes() {
+        let src = "struct P {\n    x: i32,\n    y: i32,\n    x: u8,\n}";
+        let diag = Diagnostic::new(DiagnosticLevel::Error, "duplicate field `x`", Span::new(39, 40))
+            .with_label(Span::new(15, 16), "first defined here")
+            .with_note("field names must be unique");
+        assert_eq!(
+            diag.render(src, &SourceMap::from_source(src)),
+            "error: duplicate field `x`\n --> 4:5\n  |\n4 |     x: u8,\n  |     ^\n\
+             2 |     x: i32,\n  |     - first defined here\n  = note: field names must be unique\n"
+        );
+    }
+
+    #[test]
+    fn test_report_has_no_extras() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        ctx.report(DiagnosticLevel::Warning, "w", Span::new(0, 1));
+        ctx.emit(Diagnostic::new(DiagnosticLevel::Error, "e", Span::new(1, 2)).with_note("n"));
+        assert!(ctx.diagnostics[0].labels.is_empty() && ctx.diagnostics[0].notes.is_empty());
+        assert_eq!(ctx.diagnostics[1].notes, ["n"]);
+        assert!(ctx.has_errors());
+    }
+
+    #[test]
+    fn test_render_code() {
+        let src = "1 = 2";
+        let mut ctx = CompilerCtx::new("test", 16);
+        ctx.report_code(ErrorCode::InvalidAssignTarget, Span::new(0, 1), &[]);
+        assert_eq!(
+            ctx.diagnostics[0].render(src, &SourceMap::from_source(src)),
+            "error[E0005]: invalid assignment target\n --> 1:1\n  |\n1 | 1 = 2\n  | ^\n"
+        );
+    }
+
+    #[test]
+    fn test_emit_all() {
+        let src = "val x = 1;\nval y = ;";
+        let mut ctx = CompilerCtx::new("test", 16);
+        ctx.report(DiagnosticLevel::Error, "expected expression", Span::new(19, 20));
+        ctx.report(DiagnosticLevel::Warning, "unused variable `x`", Span::new(4, 5));
+        ctx.report(DiagnosticLevel::Info, "note", Span::new(0, 3));
+        assert_eq!(
+            ctx.emit_all(src),
+            "info: note\n --> 1:1\n  |\n1 | val x = 1;\n  | ^^^\n\n\
+             warning: unused variable `x`\n --> 1:5\n  |\n1 | val x = 1;\n  |     ^\n\n\
+             error: expected expression\n --> 2:9\n  |\n2 | val y = ;\n  |         ^\n\
+             \n1 error, 1 warning\n"
+        );
+        ctx.warnings_as_errors = true;
+        assert!(ctx.emit_all(src).ends_with("\n2 errors\n"));
+        assert_eq!(CompilerCtx::new("test", 16).emit_all(src), "");
+    }
+
+    #[test]
+    fn test_emit_all_uses_files() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let a = ctx.files.add("a.nv", "val x = 1;");
+        ctx.current_file = ctx.files.add("b.nv", "fn f() {\n  oops\n}");
+        ctx.report(DiagnosticLevel::Error, "unknown name", Span::new(11, 15));
+        ctx.report_at(DiagnosticLevel::Warning, "unused", FileSpan::new(a, Span::new(4, 5)));
+        assert_eq!(
+            ctx.emit_all("not used"),
+            "warning: unused\n --> a.nv:1:5\n  |\n1 | val x = 1;\n  |     ^\n\n\
+             error: unknown name\n --> b.nv:2:3\n  |\n2 |   oops\n  |   ^^^^\n\
+             \n1 error, 1 warning\n"
+        );
+    }
+
+    #[test]
+    fn test_warnings_as_errors() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        ctx.report(DiagnosticLevel::Warning, "w", Span::new(0, 1));
+        ctx.report(DiagnosticLevel::Info, "i", Span::new(0, 1));
+        assert!(!ctx.has_errors());
+        ctx.warnings_as_errors = true;
+        assert!(ctx.has_errors());
+    }
+
+    #[test]
+    fn test_json_string_escapes() {
+        assert_eq!(json_string("plain"), r#""plain""#);
+        assert_eq!(json_string("a \"b\"\n\\c\t\u{1}é"), r#""a \"b\"\n\\c\t\u0001é""#);
+    }
+
+    #[test]
+    fn test_diagnostics_to_json() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        assert_eq!(ctx.diagnostics_to_json(), "[]");
+        ctx.report(DiagnosticLevel::Error, "no file", Span::new(0, 1));
+        assert!(ctx.diagnostics_to_json()
+            .contains(r#""file":null,"start":0,"end":1,"line":null,"column":null"#));
+
+        let mut ctx = CompilerCtx::new("test", 16);
+        let other = ctx.files.add("other.nv", "\n\nx");
+        ctx.current_file = ctx.files.add("main.nv", "ab\n  cd");
+
+        ctx.report_code(ErrorCode::DuplicateField, Span::new(5, 7), &["cd"]);
+        ctx.emit(
+            Diagnostic::new(DiagnosticLevel::Warning, "say \"hi\"", Span::new(0, 2))
+                .with_label(Span::new(5, 6), "here")
+                .with_note("a\nb"),
+        );
+        // Located in its own file, not the current one
+        ctx.report_at(DiagnosticLevel::Info, "x", FileSpan::new(other, Span::new(2, 3)));
+        assert_eq!(
+            ctx.diagnostics_to_json(),
+            concat!(
+                r#"[{"level":"error","code":"E0002","message":"duplicate field `cd`","#,
+                r#""file":"main.nv","start":5,"end":7,"line":2,"column":3,"labels":[],"notes":[]},"#,
+                r#"{"level":"warning","code":null,"message":"say \"hi\"","#,
+                r#""file":"main.nv","start":0,"end":2,"line":1,"column":1,"#,
+                r#""labels":[{"start":5,"end":6,"line":2,"column":3,"message":"here"}],"notes":["a\nb"]},"#,
+                r#"{"level":"info","code":null,"message":"x","#,
+                r#""file":"other.nv","start":2,"end":3,"line":3,"column":1,"labels":[],"notes":[]}]"#,
+            )
+        );
+    }
+
+    #[test]
+    fn test_finalize_sorts_and_dedups() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        ctx.report(DiagnosticLevel::Error, "b", Span::new(4, 5));
+        ctx.report(DiagnosticLevel::Error, "a", Span::new(0, 1));
+        ctx.report(DiagnosticLevel::Warning, "w", Span::new(4, 5));
+        ctx.report(DiagnosticLevel::Error, "c", Span::new(4, 5));
+        ctx.report(DiagnosticLevel::Error, "b", Span::new(4, 5));
+        ctx.finalize_diagnostics();
+        let got: Vec<_> = ctx.diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(got, ["a", "w", "b", "c"]);
+    }
+
+    #[test]
+    fn test_finalize_keeps_duplicates_when_disabled() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        ctx.dedup_diagnostics = false;
+        ctx.report(DiagnosticLevel::Error, "b", Span::new(4, 5));
+        ctx.report(DiagnosticLevel::Error, "b", Span::new(4, 5));
+        ctx.report(DiagnosticLevel::Error, "b", Span::new(4, 6));
+        ctx.finalize_diagnostics();
+        assert_eq!(ctx.diagnostics.len(), 3);
+    }
+
+    #[test]
+    fn test_max_errors_cutoff() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        ctx.max_errors = 2;
+        ctx.report(DiagnosticLevel::Error, "a", Span::new(5, 6));
+        ctx.report(DiagnosticLevel::Warning, "w", Span::new(1, 2));
+        ctx.report(DiagnosticLevel::Error, "b", Span::new(3, 4));
+        assert!(!ctx.should_abort());
+        ctx.report(DiagnosticLevel::Error, "c", Span::new(0, 1));
+        assert!(ctx.should_abort());
+        ctx.report(DiagnosticLevel::Error, "d", Span::new(0, 1));
+        ctx.report(DiagnosticLevel::Info, "i", Span::new(0, 1));
+        ctx.finalize_diagnostics();
+        let got: Vec<_> = ctx.diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(got, ["w", "b", "a", "too many errors, aborting"]);
+    }
+
+    #[test]
+    fn test_cutoff_keeps_file_and_pass() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        ctx.max_errors = 1;
+        ctx.current_file = FileId(1);
+        ctx.current_pass = "resolve";
+        ctx.report(DiagnosticLevel::Error, "a", Span::new(0, 1));
+        ctx.report(DiagnosticLevel::Error, "b", Span::new(2, 3));
+        let cutoff = &ctx.diagnostics[1];
+        assert_eq!((cutoff.file, cutoff.pass), (Some(FileId(1)), Some("resolve")));
+        assert_eq!(cutoff.span, Span::new(2, 3));
+    }
+
+    #[test]
+    fn test_render_in_named_file() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let a = ctx.files.add("a.nv", "val x = 1;");
+        let b = ctx.files.add("b.nv", "fn f() {\n  oops\n}");
+        ctx.current_file = b;
+        ctx.report(DiagnosticLevel::Error, "unknown name", Span::new(11, 15));
+        ctx.report_at(DiagnosticLevel::Warning, "unused", FileSpan::new(a, Span::new(4, 5)));
+        assert_eq!(ctx.diagnostics[0].file, Some(b));
+        assert_eq!(
+            ctx.diagnostics[0].render_in(&ctx.files),
+            "error: unknown name\n --> b.nv:2:3\n  |\n2 |   oops\n  |   ^^^^\n"
+        );
+        assert_eq!(
+            ctx.diagnostics[1].render_in(&ctx.files),
+            "warning: unused\n --> a.nv:1:5\n  |\n1 | val x = 1;\n  |     ^\n"
+        );
+    }
+
+    #[test]
+    fn test_render_in_without_file() {
+        let diag = Diagnostic::new(DiagnosticLevel::Error, "oops", Span::new(0, 1))
+            .with_code("E0001")
+            .with_note("n");
+        // Neither an unset file nor an unregistered one is looked up
+        assert_eq!(diag.render_in(&SourceFiles::new()), "error[E0001]: oops\n  = note: n\n");
+        let diag = diag.in_file(FileId(3));
+        assert_eq!(diag.render_in(&SourceFiles::new()), "error[E0001]: oops\n  = note: n\n");
+    }
+}
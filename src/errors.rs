@@ -0,0 +1,110 @@
+use crate::common::Span;
+use crate::context::{Diagnostic, DiagnosticLevel};
+
+// Every coded diagnostic the compiler can emit. Codes are stable: once
+// released a code keeps its meaning, new ones are only ever appended and
+// retired ones are left unused rather than reassigned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    // The parser wanted one thing and found another
+    Expected,
+    DuplicateField,
+    NonFnInImpl,
+    ConstWithoutType,
+    InvalidAssignTarget,
+    MissingInitializer,
+    DivisionByZero,
+    UndeclaredName,
+    DuplicateDefinition,
+    NotConstant,
+    ConstOverflow,
+    DuplicateVariant,
+    DuplicateParam,
+    KeywordAsName,
+    IfWithoutElse,
+}
+
+impl ErrorCode {
+    pub fn code(self) -> &'static str {
+        match self {
+            ErrorCode::Expected => "E0001",
+            ErrorCode::DuplicateField => "E0002",
+            ErrorCode::NonFnInImpl => "E0003",
+            ErrorCode::ConstWithoutType => "E0004",
+            ErrorCode::InvalidAssignTarget => "E0005",
+            ErrorCode::MissingInitializer => "E0006",
+            ErrorCode::DivisionByZero => "E0007",
+            ErrorCode::UndeclaredName => "E0008",
+            ErrorCode::DuplicateDefinition => "E0009",
+            ErrorCode::NotConstant => "E0010",
+            ErrorCode::ConstOverflow => "E0011",
+            ErrorCode::DuplicateVariant => "E0012",
+            ErrorCode::DuplicateParam => "E0013",
+            ErrorCode::KeywordAsName => "E0014",
+            ErrorCode::IfWithoutElse => "E0015",
+        }
+    }
+
+    // Default message, each `{}` is filled in order from the arguments
+    // given when reporting
+    pub fn template(self) -> &'static str {
+        match self {
+            ErrorCode::Expected => "expected {}, found {}",
+            ErrorCode::DuplicateField => "duplicate field `{}`",
+            ErrorCode::NonFnInImpl => "only functions are allowed in impl blocks",
+            ErrorCode::ConstWithoutType => "const items need a type annotation",
+            ErrorCode::InvalidAssignTarget => "invalid assignment target",
+            ErrorCode::MissingInitializer => "`{}` declarations need an initializer",
+            ErrorCode::DivisionByZero => "division by zero",
+            ErrorCode::UndeclaredName => "cannot find `{}` in this scope",
+            ErrorCode::DuplicateDefinition => "the name `{}` is defined multiple times",
+            ErrorCode::NotConstant => "not a constant expression",
+            ErrorCode::ConstOverflow => "arithmetic overflow in constant expression",
+            ErrorCode::DuplicateVariant => "duplicate variant `{}`",
+            ErrorCode::DuplicateParam => "duplicate parameter `{}`",
+            ErrorCode::KeywordAsName => "`{}` is a reserved keyword and cannot be used as a name",
+            ErrorCode::IfWithoutElse => "`if` used as a value must have an `else` branch",
+        }
+    }
+
+    pub fn format(self, args: &[&str]) -> String {
+        let mut parts = self.template().split("{}");
+        let mut message = parts.next().unwrap_or_default().to_string();
+        let mut args = args.iter();
+        for part in parts {
+            message += args.next().expect("too few arguments for diagnostic");
+            message += part;
+        }
+        debug_assert!(args.next().is_none(), "too many arguments for diagnostic");
+        message
+    }
+
+    pub fn diagnostic(self, span: Span, args: &[&str]) -> Diagnostic {
+        Diagnostic::new(DiagnosticLevel::Error, &self.format(args), span).with_code(self.code())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_template() {
+        assert_eq!(
+            ErrorCode::Expected.format(&["`;`", "RBrace"]),
+            "expected `;`, found RBrace"
+        );
+        assert_eq!(
+            ErrorCode::DuplicateField.format(&["x"]),
+            "duplicate field `x`"
+        );
+        assert_eq!(ErrorCode::DivisionByZero.format(&[]), "division by zero");
+    }
+
+    #[test]
+    fn test_diagnostic_carries_code() {
+        let diag = ErrorCode::InvalidAssignTarget.diagnostic(Span::new(0, 1), &[]);
+        assert_eq!(diag.code, Some("E0005"));
+        assert_eq!(diag.message, "invalid assignment target");
+    }
+}
@@ -0,0 +1,228 @@
+use crate::ast::{Ast, BinaryOp, ExprId, ExprKind, Literal, UnaryOp};
+use crate::context::{CompilerCtx, Diagnostic};
+use crate::errors::ErrorCode;
+use crate::visit::{MutVisitor, walk_expr_mut};
+
+// Folds integer arithmetic on literals into a single literal, bottom up so
+// `1 + 2 * 3` becomes `7`. Literals carrying a type suffix are left alone
+// since their overflow behavior depends on a type we don't know yet, and so
+// is anything that would overflow an `i64`. Negation and `!` are folded too;
+// there are no negative literals, so `-5` only becomes one here.
+pub struct ConstFolder;
+
+impl MutVisitor for ConstFolder {
+    fn fold_expr(&mut self, ctx: &mut CompilerCtx, id: ExprId) {
+        walk_expr_mut(self, ctx, id);
+
+        let folded = match ctx.ast.exprs[id].kind {
+            ExprKind::Paren(inner) => match ctx.ast.exprs[inner].kind {
+                ExprKind::Literal(Literal::Bool(b)) => {
+                    ctx.ast.exprs[id].kind = ExprKind::Literal(Literal::Bool(b));
+                    return;
+                }
+                _ => int_literal(ctx, inner),
+            },
+            ExprKind::Unary {
+                op: UnaryOp::Neg,
+                operand,
+            } => {
+                let Some(value) = int_literal(ctx, operand) else {
+                    return;
+                };
+                // `i64::MIN` itself can only come out of folding, as in
+                // `-(-9223372036854775807 - 1)`
+                if value == i64::MIN {
+                    let span = ctx.ast.exprs[id].span;
+                    ctx.report_code(ErrorCode::ConstOverflow, span, &[]);
+                }
+                value.checked_neg()
+            }
+            ExprKind::Unary {
+                op: UnaryOp::Not,
+                operand,
+            } => {
+                if let ExprKind::Literal(Literal::Bool(b)) = ctx.ast.exprs[operand].kind {
+                    ctx.ast.exprs[id].kind = ExprKind::Literal(Literal::Bool(!b));
+                }
+                return;
+            }
+            ExprKind::Binary { lhs, ref op, rhs } => {
+                let (Some(a), Some(b)) = (int_literal(ctx, lhs), int_literal(ctx, rhs)) else {
+                    return;
+                };
+                match op {
+                    BinaryOp::Add => a.checked_add(b),
+                    BinaryOp::Sub => a.checked_sub(b),
+                    BinaryOp::Mul => a.checked_mul(b),
+                    BinaryOp::Div | BinaryOp::Mod if b == 0 => {
+                        let span = ctx.ast.exprs[id].span;
+                        ctx.report_code(ErrorCode::DivisionByZero, span, &[]);
+                        None
+                    }
+                    BinaryOp::Div => a.checked_div(b),
+                    BinaryOp::Mod => a.checked_rem(b),
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+        if let Some(value) = folded {
+            ctx.ast.exprs[id].kind = ExprKind::Literal(Literal::Int(value));
+        }
+    }
+}
+
+fn int_literal(ctx: &CompilerCtx, id: ExprId) -> Option<i64> {
+    let expr = &ctx.ast.exprs[id];
+    match expr.kind {
+        ExprKind::Literal(Literal::Int(i)) if expr.ty.is_none() => Some(i),
+        _ => None,
+    }
+}
+
+// Evaluates an expression that must be known at compile time, such as an
+// array length. Only integer literals, parentheses, negation and
+// `+ - * / %` are allowed. Errors point at the innermost offending node.
+pub fn const_eval(ast: &Ast, id: ExprId) -> Result<i64, Box<Diagnostic>> {
+    let expr = &ast.exprs[id];
+    let value = match &expr.kind {
+        ExprKind::Literal(Literal::Int(i)) => Some(*i),
+        ExprKind::Paren(inner) => return const_eval(ast, *inner),
+        ExprKind::Unary {
+            op: UnaryOp::Neg,
+            operand,
+        } => const_eval(ast, *operand)?.checked_neg(),
+        ExprKind::Binary { lhs, op, rhs }
+            if matches!(
+                op,
+                BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod
+            ) =>
+        {
+            let a = const_eval(ast, *lhs)?;
+            let b = const_eval(ast, *rhs)?;
+            match op {
+                BinaryOp::Div | BinaryOp::Mod if b == 0 => {
+                    return Err(Box::new(
+                        ErrorCode::DivisionByZero.diagnostic(expr.span, &[]),
+                    ));
+                }
+                BinaryOp::Add => a.checked_add(b),
+                BinaryOp::Sub => a.checked_sub(b),
+                BinaryOp::Mul => a.checked_mul(b),
+                BinaryOp::Div => a.checked_div(b),
+                _ => a.checked_rem(b),
+            }
+        }
+        _ => return Err(Box::new(ErrorCode::NotConstant.diagnostic(expr.span, &[]))),
+    };
+    value.ok_or_else(|| Box::new(ErrorCode::ConstOverflow.diagnostic(expr.span, &[])))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast_printer::print_expr;
+    use crate::common::Span;
+    use crate::parser::Parser;
+
+    fn fold(src: &str) -> (CompilerCtx, String) {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let expr = Parser::new(src, &mut ctx).parse_expr();
+        assert!(!ctx.has_errors(), "{:?}", ctx.diagnostics);
+        ConstFolder.fold_expr(&mut ctx, expr);
+        let printed = print_expr(&ctx, expr);
+        (ctx, printed)
+    }
+
+    #[test]
+    fn test_fold_arithmetic() {
+        assert_eq!(fold("1 + 2 * 3").1, "7");
+        assert_eq!(fold("(1 + 2) * 3 - 10").1, "-1");
+        assert_eq!(fold("7 / 2 + 7 % 2").1, "4");
+        assert_eq!(fold("f(2 * 4, x + 1 * 2)").1, "f(8, x + 2)");
+        assert_eq!(
+            fold("{ val a = 2 * 2; a }").1,
+            "{\n    val a = 4;\n    a\n}"
+        );
+    }
+
+    #[test]
+    fn test_fold_leaves_unknowns() {
+        assert_eq!(fold("x * 2 + 1").1, "x * 2 + 1");
+        assert_eq!(fold("1u8 + 2").1, "1u8 + 2");
+        assert_eq!(fold("9223372036854775807 + 1").1, "9223372036854775807 + 1");
+        assert_eq!(fold("1 < 2").1, "1 < 2");
+    }
+
+    #[test]
+    fn test_fold_unary() {
+        let (ctx, printed) = fold("-5");
+        assert!(matches!(
+            ctx.ast.exprs.iter().last().unwrap().kind,
+            ExprKind::Literal(Literal::Int(-5))
+        ));
+        assert_eq!(printed, "-5");
+        assert_eq!(fold("-(2 * 3) + 1").1, "-5");
+        assert_eq!(fold("- -5").1, "5");
+        assert_eq!(fold("!true || !(false)").1, "false || true");
+        assert_eq!(fold("-x + !y").1, "-x + !y");
+        assert_eq!(fold("- -x").1, "--x");
+        let (ctx, _) = fold("-5u8");
+        let last = ctx.ast.exprs.iter().last().unwrap();
+        assert!(matches!(last.kind, ExprKind::Unary { .. }));
+        assert_eq!(fold("(-5).abs()").1, "(-5).abs()");
+        assert_eq!(fold("!1").1, "!1");
+    }
+
+    #[test]
+    fn test_fold_negation_overflow() {
+        let (ctx, printed) = fold("-(-9223372036854775807 - 1)");
+        assert_eq!(printed, "-(-9223372036854775808)");
+        assert_eq!(ctx.diagnostics.len(), 1);
+        assert_eq!(ctx.diagnostics[0].code, Some("E0011"));
+        assert_eq!(ctx.diagnostics[0].span, Span::new(0, 27));
+    }
+
+    #[test]
+    fn test_fold_division_by_zero() {
+        let (ctx, printed) = fold("1 + 4 / (2 - 2)");
+        assert_eq!(printed, "1 + 4 / 0");
+        assert_eq!(ctx.diagnostics.len(), 1);
+        assert_eq!(ctx.diagnostics[0].message, "division by zero");
+        assert_eq!(ctx.diagnostics[0].span, Span::new(4, 15));
+    }
+
+    fn eval(src: &str) -> Result<i64, Box<Diagnostic>> {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let expr = Parser::new(src, &mut ctx).parse_expr();
+        assert!(!ctx.has_errors(), "{:?}", ctx.diagnostics);
+        const_eval(&ctx.ast, expr)
+    }
+
+    #[test]
+    fn test_const_eval() {
+        assert_eq!(eval("4").unwrap(), 4);
+        assert_eq!(eval("(2 + 3) * 4 - 1").unwrap(), 19);
+        assert_eq!(eval("-7 / 2").unwrap(), -3);
+        assert_eq!(eval("-(7 % 4)").unwrap(), -3);
+    }
+
+    #[test]
+    fn test_const_eval_errors() {
+        let err = eval("2 * (n + 1)").unwrap_err();
+        assert_eq!(err.message, "not a constant expression");
+        assert_eq!(err.span, Span::new(5, 6));
+
+        let err = eval("f() + 1").unwrap_err();
+        assert_eq!(err.span, Span::new(0, 3));
+        assert_eq!(eval("1 < 2").unwrap_err().code, Some("E0010"));
+
+        let err = eval("10 % (3 - 3)").unwrap_err();
+        assert_eq!(err.message, "division by zero");
+        assert_eq!(err.span, Span::new(0, 12));
+
+        let err = eval("9223372036854775807 * 2 + 1").unwrap_err();
+        assert_eq!(err.message, "arithmetic overflow in constant expression");
+        assert_eq!(err.span, Span::new(0, 23));
+    }
+}
@@ -1,17 +1,29 @@
-use crate::common::Span;
+use std::collections::VecDeque;
+
+use crate::ast::BinaryOp;
+use crate::common::{SourceMap, Span, Symbol, SymbolInterner};
+use crate::context::{CompilerCtx, DiagnosticLevel};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum TokenKind {
     // Literals
-    IntegerLit(i64),
-    FloatLit(f64),
+    IntegerLit(i64, Option<LitSuffix>),
+    FloatLit(f64, Option<LitSuffix>),
     Char(char),
     StringLit(String),
     BoolLit(bool),
     Null,
 
-    // Identifiers
-    Ident(String),
+    // Identifiers, interned by the lexer as they are read
+    Ident(Symbol),
+
+    // Comments, only produced when `Lexer::keep_trivia` is set. The text
+    // includes the delimiters.
+    LineComment(String),
+    BlockComment(String),
+    // A `///` comment, always produced since the parser attaches them to
+    // items. The text is the rest of the line after `///` and one space.
+    DocComment(String),
 
     // Keywords
     Fn,
@@ -23,6 +35,7 @@ pub enum TokenKind {
     Extern,
     Val,
     Var,
+    Mut,
     Defer,
     While,
     For,
@@ -32,9 +45,11 @@ pub enum TokenKind {
     Else,
     Match,
     Break,
+    Continue,
     Return,
     Void,
     Undefined,
+    As,
 
     // Operators
     Assign,      // =
@@ -92,14 +107,177 @@ pub enum TokenKind {
     LBracket, // [
     RBracket, // ]
 
-    Comma,     // ,
-    Dot,       // .
-    Colon,     // :
-    Semicolon, // ;
-    Question,  // ?
+    Comma,      // ,
+    Dot,        // .
+    DotDot,     // ..
+    DotDotEq,   // ..=
+    Colon,      // :
+    ColonColon, // ::
+    Semicolon,  // ;
+    Question,   // ?
+
+    // Malformed input, a diagnostic has already been reported for its span
+    Error,
     Eof,
 }
 
+// Words the lexer never turns into identifiers
+const RESERVED_WORDS: [(&str, TokenKind); 27] = [
+    ("fn", TokenKind::Fn),
+    ("struct", TokenKind::Struct),
+    ("enum", TokenKind::Enum),
+    ("union", TokenKind::Union),
+    ("impl", TokenKind::Impl),
+    ("const", TokenKind::Const),
+    ("extern", TokenKind::Extern),
+    ("val", TokenKind::Val),
+    ("var", TokenKind::Var),
+    ("mut", TokenKind::Mut),
+    ("defer", TokenKind::Defer),
+    ("while", TokenKind::While),
+    ("for", TokenKind::For),
+    ("in", TokenKind::In),
+    ("loop", TokenKind::Loop),
+    ("if", TokenKind::If),
+    ("else", TokenKind::Else),
+    ("match", TokenKind::Match),
+    ("break", TokenKind::Break),
+    ("continue", TokenKind::Continue),
+    ("return", TokenKind::Return),
+    ("void", TokenKind::Void),
+    ("undefined", TokenKind::Undefined),
+    ("as", TokenKind::As),
+    ("true", TokenKind::BoolLit(true)),
+    ("false", TokenKind::BoolLit(false)),
+    ("null", TokenKind::Null),
+];
+
+impl TokenKind {
+    pub fn keyword(word: &str) -> Option<TokenKind> {
+        RESERVED_WORDS
+            .iter()
+            .find(|(w, _)| *w == word)
+            .map(|(_, kind)| kind.clone())
+    }
+
+    // Source text of every keyword and of `true`, `false` and `null`, the
+    // words an identifier can't be
+    pub fn reserved_words() -> impl Iterator<Item = &'static str> {
+        RESERVED_WORDS.iter().map(|(w, _)| *w)
+    }
+
+    // Source text of a keyword or of `true`, `false` and `null`
+    pub fn reserved_word(&self) -> Option<&'static str> {
+        RESERVED_WORDS
+            .iter()
+            .find(|(_, kind)| kind == self)
+            .map(|(w, _)| *w)
+    }
+
+    // The `Debug` form with identifiers spelled out, e.g. `Ident("x")`
+    // rather than the symbol, for messages and token dumps
+    pub fn describe(&self, interner: &SymbolInterner) -> String {
+        match self {
+            TokenKind::Ident(name) => format!("Ident({:?})", interner.display(*name).to_string()),
+            kind => format!("{:?}", kind),
+        }
+    }
+
+    pub fn is_keyword(&self) -> bool {
+        !self.is_literal() && self.reserved_word().is_some()
+    }
+
+    pub fn is_literal(&self) -> bool {
+        matches!(
+            self,
+            TokenKind::IntegerLit(..)
+                | TokenKind::FloatLit(..)
+                | TokenKind::Char(_)
+                | TokenKind::StringLit(_)
+                | TokenKind::BoolLit(_)
+                | TokenKind::Null
+        )
+    }
+
+    pub fn is_binary_op(&self) -> bool {
+        self.binary_op().is_some()
+    }
+
+    // The operator this token stands for between two operands. `|>` is
+    // not a `BinaryOp` and gets no entry.
+    pub fn binary_op(&self) -> Option<BinaryOp> {
+        match self {
+            TokenKind::OrOr => Some(BinaryOp::Or),
+            TokenKind::AndAnd => Some(BinaryOp::And),
+            TokenKind::EqEq => Some(BinaryOp::Eq),
+            TokenKind::NotEq => Some(BinaryOp::Ne),
+            TokenKind::Lt => Some(BinaryOp::Lt),
+            TokenKind::LtEq => Some(BinaryOp::LtEq),
+            TokenKind::Gt => Some(BinaryOp::Gt),
+            TokenKind::GtEq => Some(BinaryOp::GtEq),
+            TokenKind::Plus => Some(BinaryOp::Add),
+            TokenKind::Minus => Some(BinaryOp::Sub),
+            TokenKind::Star => Some(BinaryOp::Mul),
+            TokenKind::Slash => Some(BinaryOp::Div),
+            TokenKind::Percent => Some(BinaryOp::Mod),
+            _ => None,
+        }
+    }
+}
+
+// Type suffix written directly after a numeric literal, e.g. `255u8` or `1.0f32`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LitSuffix {
+    I8,
+    I16,
+    I32,
+    I64,
+    U8,
+    U16,
+    U32,
+    U64,
+    F32,
+    F64,
+}
+
+impl LitSuffix {
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "i8" => Some(LitSuffix::I8),
+            "i16" => Some(LitSuffix::I16),
+            "i32" => Some(LitSuffix::I32),
+            "i64" => Some(LitSuffix::I64),
+            "u8" => Some(LitSuffix::U8),
+            "u16" => Some(LitSuffix::U16),
+            "u32" => Some(LitSuffix::U32),
+            "u64" => Some(LitSuffix::U64),
+            "f32" => Some(LitSuffix::F32),
+            "f64" => Some(LitSuffix::F64),
+            _ => None,
+        }
+    }
+
+    pub fn is_float(self) -> bool {
+        matches!(self, LitSuffix::F32 | LitSuffix::F64)
+    }
+
+    // The predefined symbol naming the suffix's type
+    pub fn symbol(self) -> Symbol {
+        match self {
+            LitSuffix::I8 => Symbol::I8,
+            LitSuffix::I16 => Symbol::I16,
+            LitSuffix::I32 => Symbol::I32,
+            LitSuffix::I64 => Symbol::I64,
+            LitSuffix::U8 => Symbol::U8,
+            LitSuffix::U16 => Symbol::U16,
+            LitSuffix::U32 => Symbol::U32,
+            LitSuffix::U64 => Symbol::U64,
+            LitSuffix::F32 => Symbol::F32,
+            LitSuffix::F64 => Symbol::F64,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Token {
     pub kind: TokenKind,
@@ -110,33 +288,75 @@ pub struct Lexer<'a> {
     input: &'a str,
     chars: std::iter::Peekable<std::str::CharIndices<'a>>,
     pos: u32,
+    ctx: &'a mut CompilerCtx,
+    source_map: SourceMap,
+    // Return comments as tokens instead of skipping them, for tools that
+    // need the exact source such as a formatter. The parser doesn't expect
+    // them.
+    pub keep_trivia: bool,
+    // Tokens lexed by `peek_nth` and not yet returned by `next_token`
+    lookahead: VecDeque<Token>,
+    // Set once `Eof` has been yielded by the iterator
+    finished: bool,
+    // Escapes of every string literal lexed so far, in source order
+    escapes: Vec<StringEscape>,
 }
 
 impl<'a> Lexer<'a> {
-    pub fn new(input: &'a str) -> Self {
+    pub fn new(input: &'a str, ctx: &'a mut CompilerCtx) -> Self {
         Self {
             input,
             chars: input.char_indices().peekable(),
             pos: 0,
+            ctx,
+            source_map: SourceMap::new(),
+            keep_trivia: false,
+            lookahead: VecDeque::new(),
+            finished: false,
+            escapes: Vec::new(),
         }
     }
 
+    pub fn ctx(&mut self) -> &mut CompilerCtx {
+        self.ctx
+    }
+
+    // Line information for everything lexed so far
+    pub fn source_map(&self) -> &SourceMap {
+        &self.source_map
+    }
+
+    // Escapes of the string literal spanning `literal`, which must already
+    // have been lexed
+    pub fn string_escapes(&self, literal: Span) -> &[StringEscape] {
+        let lo = self
+            .escapes
+            .partition_point(|e| e.span.start < literal.start);
+        let hi = self.escapes.partition_point(|e| e.span.start < literal.end);
+        &self.escapes[lo..hi]
+    }
+
     fn advance(&mut self) -> Option<char> {
         let (byte_idx, c) = self.chars.next()?;
+        self.source_map.record_char(byte_idx as u32, c);
         self.pos = (byte_idx + c.len_utf8()) as u32;
         Some(c)
     }
 
-    fn consume(&mut self, expected: char) {
-        if let Some(c) = self.advance() {
-            if c != expected {
-                panic!("Expected char: {}", expected);
-            }
+    // Advances only if the next char is `expected`
+    fn consume(&mut self, expected: char) -> bool {
+        if self.peek() == Some(expected) {
+            self.advance();
+            true
         } else {
-            panic!("Unexpected end of input");
+            false
         }
     }
 
+    fn error(&mut self, message: &str, span: Span) {
+        self.ctx.report(DiagnosticLevel::Error, message, span);
+    }
+
     fn peek(&mut self) -> Option<char> {
         Some(self.chars.peek()?.1)
     }
@@ -148,7 +368,39 @@ impl<'a> Lexer<'a> {
     }
 
     pub fn next_token(&mut self) -> Token {
+        match self.lookahead.pop_front() {
+            Some(token) => token,
+            None => self.lex_token(),
+        }
+    }
+
+    // The token `next_token` will return, without consuming it
+    pub fn peek_token(&mut self) -> &Token {
+        self.peek_nth(0)
+    }
+
+    // The token `n` places after the next one. Lexing ahead moves the
+    // lexer's position and source map, and reports any errors in the
+    // peeked tokens right away. Past the end this is `Eof`.
+    pub fn peek_nth(&mut self, n: usize) -> &Token {
+        while self.lookahead.len() <= n {
+            let token = self.lex_token();
+            self.lookahead.push_back(token);
+        }
+        &self.lookahead[n]
+    }
+
+    fn lex_token(&mut self) -> Token {
         self.skip_whitespace();
+        if self.at_doc_comment() {
+            return self.lex_doc_comment();
+        }
+        if self.keep_trivia
+            && self.peek() == Some('/')
+            && let Some(token) = self.lex_comment()
+        {
+            return token;
+        }
 
         let start = self.pos;
         let c = match self.advance() {
@@ -156,7 +408,7 @@ impl<'a> Lexer<'a> {
             None => {
                 return Token {
                     kind: TokenKind::Eof,
-                    span: Span::new(start, start),
+                    span: Span::point(start),
                 };
             }
         };
@@ -169,8 +421,33 @@ impl<'a> Lexer<'a> {
             '[' => TokenKind::LBracket,
             ']' => TokenKind::RBracket,
             ',' => TokenKind::Comma,
-            '.' => TokenKind::Dot,
-            ':' => TokenKind::Colon,
+            '.' => {
+                if let Some('.') = self.peek() {
+                    self.advance();
+                    if let Some('=') = self.peek() {
+                        self.advance();
+                        TokenKind::DotDotEq
+                    } else {
+                        TokenKind::DotDot
+                    }
+                } else if self.peek().is_some_and(|c| c.is_ascii_digit())
+                    && !self.follows_operand(start)
+                {
+                    // `.5`, but `x.0` stays a member access
+                    let kind = self.parse_decimal_number('.', start);
+                    self.parse_suffix(kind)
+                } else {
+                    TokenKind::Dot
+                }
+            }
+            ':' => {
+                if let Some(':') = self.peek() {
+                    self.advance();
+                    TokenKind::ColonColon
+                } else {
+                    TokenKind::Colon
+                }
+            }
             ';' => TokenKind::Semicolon,
             '?' => TokenKind::Question,
             '+' => {
@@ -207,9 +484,14 @@ impl<'a> Lexer<'a> {
                 }
             }
             '/' => {
-                if let Some('=') = self.peek() {
-                    self.advance();
-                    TokenKind::SlashAssign
+                if let Some(n) = self.peek() {
+                    match n {
+                        '=' => {
+                            self.advance();
+                            TokenKind::SlashAssign
+                        }
+                        _ => TokenKind::Slash,
+                    }
                 } else {
                     TokenKind::Slash
                 }
@@ -305,16 +587,12 @@ impl<'a> Lexer<'a> {
             '0'..='9' => self.parse_number(c),
             'a'..='z' | 'A'..='Z' | '_' => self.parse_ident(c),
             '"' => self.parse_string(),
-            '\'' => {
-                if let Some(ch) = self.advance() {
-                    let tk = TokenKind::Char(ch);
-                    self.consume('\'');
-                    tk
-                } else {
-                    panic!("Unterminated char literal");
-                }
+            '\'' => self.parse_char(start),
+            _ => {
+                let msg = format!("unknown character '{}'", c);
+                self.error(&msg, Span::new(start, self.pos));
+                TokenKind::Error
             }
-            _ => panic!("Unknown char: {}", c),
         };
         Token {
             kind,
@@ -323,58 +601,143 @@ impl<'a> Lexer<'a> {
     }
 
     fn skip_whitespace(&mut self) {
+        // A `#!/usr/bin/env nerva` line, only allowed as the very first
+        // thing in the file
+        if self.pos == 0 && self.input.starts_with("#!") {
+            self.skip_line_comment();
+        }
         while let Some(c) = self.peek() {
             if c.is_whitespace() {
                 self.advance();
+            } else if self.keep_trivia {
+                break;
+            } else if c == '/' && self.peek_next() == Some('/') {
+                if self.at_doc_comment() {
+                    break;
+                }
+                self.skip_line_comment();
+            } else if c == '/' && self.peek_next() == Some('*') {
+                self.skip_block_comment();
             } else {
                 break;
             }
         }
     }
 
+    // `///` but not `////`, which is an ordinary comment
+    fn at_doc_comment(&self) -> bool {
+        let rest = &self.input[self.pos as usize..];
+        rest.starts_with("///") && !rest.starts_with("////")
+    }
+
+    fn lex_doc_comment(&mut self) -> Token {
+        let start = self.pos;
+        self.skip_line_comment();
+        let text = &self.input[start as usize + 3..self.pos as usize];
+        let text = text.strip_prefix(' ').unwrap_or(text);
+        Token {
+            kind: TokenKind::DocComment(text.to_string()),
+            span: Span::new(start, self.pos),
+        }
+    }
+
+    fn lex_comment(&mut self) -> Option<Token> {
+        let start = self.pos;
+        let block = match self.peek_next() {
+            Some('/') => false,
+            Some('*') => true,
+            _ => return None,
+        };
+        if block {
+            self.skip_block_comment();
+        } else {
+            self.skip_line_comment();
+        }
+        let text = self.input[start as usize..self.pos as usize].to_string();
+        let kind = if block {
+            TokenKind::BlockComment(text)
+        } else {
+            TokenKind::LineComment(text)
+        };
+        Some(Token {
+            kind,
+            span: Span::new(start, self.pos),
+        })
+    }
+
+    // Consumes everything up to (but not including) the next '\n' or EOF
+    fn skip_line_comment(&mut self) {
+        while let Some(c) = self.peek() {
+            if c == '\n' {
+                break;
+            }
+            self.advance();
+        }
+    }
+
+    // Block comments nest, so `/* a /* b */ c */` is consumed as a single comment
+    fn skip_block_comment(&mut self) {
+        let start = self.pos;
+        self.consume('/');
+        self.consume('*');
+        let mut depth = 1;
+        while let Some(c) = self.advance() {
+            match c {
+                '/' if self.peek() == Some('*') => {
+                    self.advance();
+                    depth += 1;
+                }
+                '*' if self.peek() == Some('/') => {
+                    self.advance();
+                    depth -= 1;
+                    if depth == 0 {
+                        return;
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.error("unterminated block comment", Span::new(start, start + 2));
+    }
+
+    // Interns straight from the source slice, so a name only costs an
+    // allocation the first time it is seen
     fn parse_ident(&mut self, c: char) -> TokenKind {
-        let mut ident = String::new();
-        ident.push(c);
+        let start = self.pos as usize - c.len_utf8();
         while let Some(c) = self.peek() {
             if matches!(c, '0'..='9' | 'A'..='Z' | 'a'..='z' | '_') {
-                ident.push(c);
                 self.advance();
             } else {
                 break;
             }
         }
 
-        match ident.as_str() {
-            "fn" => TokenKind::Fn,
-            "struct" => TokenKind::Struct,
-            "enum" => TokenKind::Enum,
-            "union" => TokenKind::Union,
-            "impl" => TokenKind::Impl,
-            "const" => TokenKind::Const,
-            "extern" => TokenKind::Extern,
-            "val" => TokenKind::Val,
-            "var" => TokenKind::Var,
-            "defer" => TokenKind::Defer,
-            "while" => TokenKind::While,
-            "for" => TokenKind::For,
-            "in" => TokenKind::In,
-            "loop" => TokenKind::Loop,
-            "if" => TokenKind::If,
-            "else" => TokenKind::Else,
-            "match" => TokenKind::Match,
-            "break" => TokenKind::Break,
-            "return" => TokenKind::Return,
-            "void" => TokenKind::Void,
-            "undefined" => TokenKind::Undefined,
-            "true" => TokenKind::BoolLit(true),
-            "false" => TokenKind::BoolLit(false),
-            "null" => TokenKind::Null,
-            _ => TokenKind::Ident(ident),
-        }
+        let ident = &self.input[start..self.pos as usize];
+        TokenKind::keyword(ident)
+            .unwrap_or_else(|| TokenKind::Ident(self.ctx.symbol_interner.intern(ident)))
     }
     fn parse_number(&mut self, c: char) -> TokenKind {
+        let start = self.pos - 1;
+        let radix = match (c, self.peek()) {
+            ('0', Some('x')) => Some(16),
+            ('0', Some('o')) => Some(8),
+            ('0', Some('b')) => Some(2),
+            _ => None,
+        };
+        let kind = if let Some(radix) = radix {
+            self.advance();
+            self.parse_radix_number(radix)
+        } else {
+            self.parse_decimal_number(c, start)
+        };
+        self.parse_suffix(kind)
+    }
+
+    // Called with the first char already consumed, which is either a digit
+    // or the `.` of a float like `.5`
+    fn parse_decimal_number(&mut self, c: char, start: u32) -> TokenKind {
         let mut number = String::new();
-        let mut is_float = false;
+        let mut is_float = c == '.';
         number.push(c);
 
         while let Some(c) = self.peek() {
@@ -384,67 +747,356 @@ impl<'a> Lexer<'a> {
                     number.push(c);
                 }
                 '_' => {
-                    self.advance();
+                    let after_digit = number.ends_with(|c: char| c.is_ascii_digit());
+                    self.skip_digit_separators(after_digit, 10);
                 }
                 '.' => {
                     if is_float {
                         break;
                     } else {
-                        if let Some(ch) = self.peek_next() {
-                            if matches!(ch, '0'..='9') {
+                        match self.peek_next() {
+                            Some(ch) if ch.is_ascii_digit() => {
                                 self.advance(); // .
                                 self.advance(); // ch
                                 number.push(c);
                                 number.push(ch);
                                 is_float = true;
-                            } else {
+                            }
+                            // `5.abs()` and `0..n` keep the integer
+                            Some(ch) if ch == '.' || ch == '_' || ch.is_alphabetic() => break,
+                            // `5.` on its own
+                            _ => {
+                                self.advance();
+                                number.push(c);
+                                is_float = true;
                                 break;
                             }
+                        }
+                    }
+                }
+                'e' | 'E' => {
+                    self.advance();
+                    number.push(c);
+                    if let Some(sign @ ('+' | '-')) = self.peek() {
+                        self.advance();
+                        number.push(sign);
+                    }
+                    let mut has_digits = false;
+                    while let Some(d) = self.peek() {
+                        if d.is_ascii_digit() {
+                            self.advance();
+                            number.push(d);
+                            has_digits = true;
+                        } else if d == '_' {
+                            self.skip_digit_separators(has_digits, 10);
                         } else {
                             break;
                         }
                     }
+                    if !has_digits {
+                        self.error(
+                            "expected digits in float exponent",
+                            Span::new(start, self.pos),
+                        );
+                        return TokenKind::Error;
+                    }
+                    is_float = true;
+                    break;
                 }
                 _ => break,
             }
         }
 
         if is_float {
-            let f = number.parse::<f64>();
-            if f.is_err() {
-                panic!("Invalid float literal");
+            match number.parse::<f64>() {
+                Ok(f) if f.is_infinite() => {
+                    self.error(
+                        "float literal too large for f64",
+                        Span::new(start, self.pos),
+                    );
+                    TokenKind::Error
+                }
+                Ok(f) => TokenKind::FloatLit(f, None),
+                Err(_) => {
+                    self.error("invalid float literal", Span::new(start, self.pos));
+                    TokenKind::Error
+                }
             }
-            TokenKind::FloatLit(f.unwrap())
         } else {
-            let i = number.parse::<i64>();
-            if i.is_err() {
-                panic!("Invalid integer literal");
+            // Only digits were collected, so overflow is the only failure
+            match number.parse::<i64>() {
+                Ok(i) => TokenKind::IntegerLit(i, None),
+                Err(_) => {
+                    self.error(
+                        "integer literal too large for i64",
+                        Span::new(start, self.pos),
+                    );
+                    TokenKind::Error
+                }
+            }
+        }
+    }
+
+    // Attaches an optional type suffix to an already lexed numeric literal.
+    // A float suffix on an integer literal (`1f32`) promotes it to a float.
+    fn parse_suffix(&mut self, kind: TokenKind) -> TokenKind {
+        let suffix_start = self.pos;
+        let mut suffix = String::new();
+        while let Some(c) = self.peek() {
+            if c.is_ascii_alphanumeric() || c == '_' {
+                self.advance();
+                suffix.push(c);
+            } else {
+                break;
+            }
+        }
+        if suffix.is_empty() {
+            return kind;
+        }
+
+        let span = Span::new(suffix_start, self.pos);
+        let Some(lit_suffix) = LitSuffix::from_str(&suffix) else {
+            let msg = format!("invalid suffix `{}` for number literal", suffix);
+            self.error(&msg, span);
+            return kind;
+        };
+        match kind {
+            TokenKind::IntegerLit(i, _) if lit_suffix.is_float() => {
+                TokenKind::FloatLit(i as f64, Some(lit_suffix))
+            }
+            TokenKind::IntegerLit(i, _) => TokenKind::IntegerLit(i, Some(lit_suffix)),
+            TokenKind::FloatLit(f, _) if lit_suffix.is_float() => {
+                TokenKind::FloatLit(f, Some(lit_suffix))
+            }
+            TokenKind::FloatLit(f, _) => {
+                let msg = format!("integer suffix `{}` on float literal", suffix);
+                self.error(&msg, span);
+                TokenKind::FloatLit(f, None)
+            }
+            kind => kind,
+        }
+    }
+
+    // Consumes a run of `_` inside a number literal. Only a single
+    // underscore between two digits is allowed: `1_000` but not `1_`,
+    // `1__0` or `0x_1`. Digits outside `radix` are reported separately.
+    fn skip_digit_separators(&mut self, after_digit: bool, radix: u32) {
+        let start = self.pos;
+        while self.peek() == Some('_') {
+            self.advance();
+        }
+        let before_digit = self.peek().is_some_and(|c| c.is_digit(radix.max(10)));
+        if self.pos - start > 1 || !after_digit || !before_digit {
+            self.error(
+                "`_` in a number literal must be between two digits",
+                Span::new(start, self.pos),
+            );
+        }
+    }
+
+    // Whether the char right before `offset` ends an operand, making a `.`
+    // there a member access
+    fn follows_operand(&self, offset: u32) -> bool {
+        self.input[..offset as usize]
+            .chars()
+            .next_back()
+            .is_some_and(|c| c.is_alphanumeric() || matches!(c, '_' | ')' | ']'))
+    }
+
+    // Called with the `0x`, `0o` or `0b` prefix already consumed
+    fn parse_radix_number(&mut self, radix: u32) -> TokenKind {
+        let start = self.pos - 2;
+        let mut digits = String::new();
+        let mut valid = true;
+        let mut after_digit = false;
+
+        while let Some(c) = self.peek() {
+            let digit_start = self.pos;
+            if c == '_' {
+                self.skip_digit_separators(after_digit, radix);
+                continue;
+            }
+            if c.is_digit(radix) {
+                self.advance();
+                digits.push(c);
+                after_digit = true;
+            } else if c.is_ascii_digit() {
+                // A decimal digit outside the radix, e.g. the `2` in `0b102`
+                self.advance();
+                after_digit = true;
+                let msg = format!("invalid digit '{}' in base {} literal", c, radix);
+                self.error(&msg, Span::new(digit_start, self.pos));
+                valid = false;
+            } else {
+                break;
+            }
+        }
+
+        if digits.is_empty() {
+            self.error(
+                "missing digits after integer base prefix",
+                Span::new(start, self.pos),
+            );
+            return TokenKind::Error;
+        }
+        if !valid {
+            return TokenKind::Error;
+        }
+        match i64::from_str_radix(&digits, radix) {
+            Ok(i) => TokenKind::IntegerLit(i, None),
+            Err(_) => {
+                self.error(
+                    "integer literal too large for i64",
+                    Span::new(start, self.pos),
+                );
+                TokenKind::Error
+            }
+        }
+    }
+
+    // Decodes the escape whose char after the backslash is `c`, `start` is the
+    // offset of the backslash. Returns None if the escape was malformed, in
+    // which case a diagnostic has already been reported.
+    fn read_escape(&mut self, start: u32, c: char) -> Option<char> {
+        match c {
+            'x' => self.read_hex_escape(start),
+            'u' => self.read_unicode_escape(start),
+            _ => simple_escape(c).or_else(|| {
+                let msg = format!("unknown escape sequence `\\{}`", c);
+                self.error(&msg, Span::new(start, self.pos));
+                None
+            }),
+        }
+    }
+
+    // Reads the two hex digits of a `\x41` escape, limited to ASCII
+    fn read_hex_escape(&mut self, start: u32) -> Option<char> {
+        let mut value = 0;
+        for _ in 0..2 {
+            match self.peek().and_then(|c| c.to_digit(16)) {
+                Some(digit) => {
+                    self.advance();
+                    value = value * 16 + digit;
+                }
+                None => {
+                    self.error(
+                        "`\\x` escape must be followed by two hex digits",
+                        Span::new(start, self.pos),
+                    );
+                    return None;
+                }
             }
-            TokenKind::IntegerLit(i.unwrap())
+        }
+        if value > 0x7F {
+            self.error(
+                "out of range hex escape, must be at most `\\x7F`",
+                Span::new(start, self.pos),
+            );
+            return None;
+        }
+        char::from_u32(value)
+    }
+
+    // Reads the `{1F600}` part of a `\u{1F600}` escape, `start` is the offset
+    // of the backslash so errors cover the whole escape
+    fn read_unicode_escape(&mut self, start: u32) -> Option<char> {
+        if !self.consume('{') {
+            self.error("expected `{` after `\\u`", Span::new(start, self.pos));
+            return None;
+        }
+        let mut digits = String::new();
+        while let Some(c) = self.peek() {
+            if c.is_ascii_hexdigit() {
+                self.advance();
+                digits.push(c);
+            } else {
+                break;
+            }
+        }
+        if !self.consume('}') {
+            self.error("unterminated unicode escape", Span::new(start, self.pos));
+            return None;
+        }
+        let span = Span::new(start, self.pos);
+        if digits.is_empty() || digits.len() > 6 {
+            self.error("unicode escape must have 1 to 6 hex digits", span);
+            return None;
+        }
+        let ch = u32::from_str_radix(&digits, 16)
+            .ok()
+            .and_then(char::from_u32);
+        if ch.is_none() {
+            self.error("invalid unicode code point in escape", span);
+        }
+        ch
+    }
+
+    fn parse_char(&mut self, start: u32) -> TokenKind {
+        if self.consume('\'') {
+            self.error("empty char literal", Span::new(start, self.pos));
+            return TokenKind::Error;
+        }
+        let value = match self.advance() {
+            Some('\\') => {
+                let escape_start = self.pos - 1;
+                match self.advance() {
+                    Some(next) => self.read_escape(escape_start, next),
+                    None => None,
+                }
+            }
+            other => other,
+        };
+
+        // Swallow anything else up to the closing quote on this line, so that
+        // `'ab'` is reported once instead of derailing the following tokens
+        let mut extra_chars = false;
+        while let Some(c) = self.peek() {
+            if c == '\'' || c == '\n' {
+                break;
+            }
+            self.advance();
+            if c == '\\' {
+                self.advance();
+            }
+            extra_chars = true;
+        }
+
+        if !self.consume('\'') {
+            self.error("unterminated char literal", Span::new(start, self.pos));
+            return TokenKind::Error;
+        }
+        match value {
+            Some(_) if extra_chars => {
+                self.error(
+                    "char literal must contain exactly one character",
+                    Span::new(start, self.pos),
+                );
+                TokenKind::Error
+            }
+            Some(ch) => TokenKind::Char(ch),
+            None => TokenKind::Error,
         }
     }
 
     fn parse_string(&mut self) -> TokenKind {
+        let start = self.pos - 1;
         let mut string = String::new();
         let mut closed = false;
         while let Some(c) = self.advance() {
             match c {
                 '\\' => {
-                    if let Some(next) = self.advance() {
-                        match next {
-                            'n' => string.push('\n'),
-                            't' => string.push('\t'),
-                            'r' => string.push('\r'),
-                            '"' => string.push('"'),
-                            '\\' => string.push('\\'),
-                            _ => {
-                                string.push('\\');
-                                string.push(next);
-                            }
-                        }
-                    } else {
-                        panic!("Unterminated string literal");
-                    }
+                    let escape_start = self.pos - 1;
+                    let Some(next) = self.advance() else {
+                        break;
+                    };
+                    let decoded = string.len() as u32;
+                    let value = self.read_escape(escape_start, next);
+                    string.extend(value);
+                    self.escapes.push(StringEscape {
+                        decoded,
+                        decoded_len: value.map_or(0, |c| c.len_utf8() as u32),
+                        span: Span::new(escape_start, self.pos),
+                    });
                 }
                 '"' => {
                     closed = true;
@@ -454,8 +1106,853 @@ impl<'a> Lexer<'a> {
             }
         }
         if !closed {
-            panic!("Unterminated string literal");
+            self.error("unterminated string literal", Span::new(start, self.pos));
+            return TokenKind::Error;
         }
         TokenKind::StringLit(string)
     }
 }
+
+impl Iterator for Lexer<'_> {
+    type Item = Token;
+
+    // Yields every token including the final `Eof`, then `None` forever
+    fn next(&mut self) -> Option<Token> {
+        if self.finished {
+            return None;
+        }
+        let token = self.next_token();
+        if token.kind == TokenKind::Eof {
+            self.finished = true;
+        }
+        Some(token)
+    }
+}
+
+impl std::iter::FusedIterator for Lexer<'_> {}
+
+// The escapes that are a backslash and one more char
+fn simple_escape(c: char) -> Option<char> {
+    match c {
+        'n' => Some('\n'),
+        't' => Some('\t'),
+        'r' => Some('\r'),
+        '"' => Some('"'),
+        '\'' => Some('\''),
+        '\\' => Some('\\'),
+        '0' => Some('\0'),
+        _ => None,
+    }
+}
+
+// An escape sequence inside a string literal, recorded by the lexer as it
+// decodes it, see `Lexer::string_escapes`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StringEscape {
+    // Offset in the decoded string where the escaped char went
+    pub decoded: u32,
+    // Bytes the escape decoded to, 0 if it was malformed and dropped
+    pub decoded_len: u32,
+    // The escape in the source, backslash included
+    pub span: Span,
+}
+
+// Source offset of byte `offset` of the decoded value of the string literal
+// spanning `literal`, for pointing at a spot inside it. `escapes` are the
+// literal's, from `Lexer::string_escapes`. Offsets that land inside an
+// escaped char map to its backslash.
+pub fn string_source_offset(escapes: &[StringEscape], literal: Span, offset: u32) -> u32 {
+    let mut shift = 0;
+    for escape in escapes {
+        if offset < escape.decoded {
+            break;
+        }
+        if offset < escape.decoded + escape.decoded_len {
+            return escape.span.start;
+        }
+        shift += escape.span.len() - escape.decoded_len;
+    }
+    literal.start + 1 + offset + shift
+}
+
+// Every token of `src` up to and including `Eof` as (kind, start, end),
+// for compact span tests. Diagnostics are dropped.
+#[cfg(test)]
+pub fn lex_all(src: &str) -> Vec<(TokenKind, u32, u32)> {
+    let mut ctx = CompilerCtx::new("test", 16);
+    Lexer::new(src, &mut ctx)
+        .map(|t| (t.kind, t.span.start, t.span.end))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lex(src: &str) -> Vec<Token> {
+        let mut ctx = CompilerCtx::new("test", 16);
+        lex_in(&mut ctx, src)
+    }
+
+    fn lex_in(ctx: &mut CompilerCtx, src: &str) -> Vec<Token> {
+        Lexer::new(src, ctx).collect()
+    }
+
+    fn kinds(src: &str) -> Vec<TokenKind> {
+        lex(src).into_iter().map(|t| t.kind).collect()
+    }
+
+    // `kinds` along with a way to spell the identifiers in it, since their
+    // symbols depend on the order the names were first seen
+    fn named_kinds(src: &str) -> (Vec<TokenKind>, impl Fn(&str) -> TokenKind) {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let kinds = lex_in(&mut ctx, src).into_iter().map(|t| t.kind).collect();
+        (kinds, move |name: &str| ident_in(&ctx, name))
+    }
+
+    fn ident_in(ctx: &CompilerCtx, name: &str) -> TokenKind {
+        TokenKind::Ident(ctx.symbol_interner.get(name).expect("name was never lexed"))
+    }
+
+    #[test]
+    fn test_line_comment_skipped() {
+        let (got, ident) = named_kinds("a // comment\nb");
+        assert_eq!(got, vec![ident("a"), ident("b"), TokenKind::Eof]);
+    }
+
+    #[test]
+    fn test_keep_trivia() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let src = "a // one\n/* two /* nested */ */ b / c";
+        let mut lexer = Lexer::new(src, &mut ctx);
+        lexer.keep_trivia = true;
+        let tokens: Vec<_> = lexer.map(|t| (t.kind, t.span)).collect();
+        let ident = |name| ident_in(&ctx, name);
+        assert_eq!(
+            tokens,
+            vec![
+                (ident("a"), Span::new(0, 1)),
+                (TokenKind::LineComment("// one".into()), Span::new(2, 8)),
+                (
+                    TokenKind::BlockComment("/* two /* nested */ */".into()),
+                    Span::new(9, 31)
+                ),
+                (ident("b"), Span::new(32, 33)),
+                (TokenKind::Slash, Span::new(34, 35)),
+                (ident("c"), Span::new(36, 37)),
+                (TokenKind::Eof, Span::new(37, 37)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_doc_comments() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let src = "/// Adds\n///two\n//// not doc\n// plain\nfn /// x";
+        let tokens: Vec<_> = Lexer::new(src, &mut ctx)
+            .map(|t| (t.kind, t.span))
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                (TokenKind::DocComment("Adds".into()), Span::new(0, 8)),
+                (TokenKind::DocComment("two".into()), Span::new(9, 15)),
+                (TokenKind::Fn, Span::new(38, 40)),
+                (TokenKind::DocComment("x".into()), Span::new(41, 46)),
+                (TokenKind::Eof, Span::new(46, 46)),
+            ]
+        );
+
+        // Still doc comments rather than line comments with trivia kept
+        let mut ctx = CompilerCtx::new("test", 16);
+        let mut lexer = Lexer::new("/// a\n// b", &mut ctx);
+        lexer.keep_trivia = true;
+        let kinds: Vec<_> = lexer.map(|t| t.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                TokenKind::DocComment("a".into()),
+                TokenKind::LineComment("// b".into()),
+                TokenKind::Eof
+            ]
+        );
+    }
+
+    #[test]
+    fn test_keep_trivia_unterminated() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let mut lexer = Lexer::new("x /* open", &mut ctx);
+        lexer.keep_trivia = true;
+        let kinds: Vec<_> = lexer.map(|t| t.kind).collect();
+        assert_eq!(kinds[1], TokenKind::BlockComment("/* open".into()));
+        assert_eq!(ctx.diagnostics.len(), 1);
+    }
+
+    #[test]
+    fn test_shebang() {
+        let (got, ident) = named_kinds("#!/usr/bin/env nerva\nx");
+        assert_eq!(got, vec![ident("x"), TokenKind::Eof]);
+        assert_eq!(lex("#!/usr/bin/env nerva\nx")[0].span, Span::new(21, 22));
+        assert_eq!(kinds("#!/usr/bin/env nerva"), vec![TokenKind::Eof]);
+    }
+
+    #[test]
+    fn test_hash_after_start_is_error() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let tokens = lex_in(&mut ctx, " #!x");
+        assert_eq!(tokens[0].kind, TokenKind::Error);
+        assert_eq!(ctx.diagnostics[0].message, "unknown character '#'");
+    }
+
+    #[test]
+    fn test_line_comment_span() {
+        let tokens = lex("// hi\nx");
+        assert_eq!(tokens[0].span.start, 6);
+        assert_eq!(tokens[0].span.end, 7);
+    }
+
+    #[test]
+    fn test_line_comment_at_eof() {
+        let (got, ident) = named_kinds("x // trailing");
+        assert_eq!(got, vec![ident("x"), TokenKind::Eof]);
+        assert_eq!(kinds("//"), vec![TokenKind::Eof]);
+    }
+
+    #[test]
+    fn test_consecutive_line_comments() {
+        let src = "val x = 1 // hello\n// more\n\n  // and more\nval y = 2";
+        let tokens = lex(src);
+        let (got, ident) = named_kinds(src);
+        assert_eq!(
+            got,
+            vec![
+                TokenKind::Val,
+                ident("x"),
+                TokenKind::Assign,
+                TokenKind::IntegerLit(1, None),
+                TokenKind::Val,
+                ident("y"),
+                TokenKind::Assign,
+                TokenKind::IntegerLit(2, None),
+                TokenKind::Eof
+            ]
+        );
+        assert_eq!(tokens[4].span.start, 42);
+    }
+
+    #[test]
+    fn test_slash_still_lexes() {
+        let (got, ident) = named_kinds("a / b /= c");
+        assert_eq!(
+            got,
+            vec![
+                ident("a"),
+                TokenKind::Slash,
+                ident("b"),
+                TokenKind::SlashAssign,
+                ident("c"),
+                TokenKind::Eof
+            ]
+        );
+    }
+
+    #[test]
+    fn test_block_comment_skipped() {
+        let (got, ident) = named_kinds("a /* one\ntwo */ b");
+        assert_eq!(got, vec![ident("a"), ident("b"), TokenKind::Eof]);
+    }
+
+    #[test]
+    fn test_nested_block_comment() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let tokens = lex_in(&mut ctx, "/* outer /* inner */ still outer */ x");
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(tokens[0].kind, ident_in(&ctx, "x"));
+        assert_eq!(tokens[0].span.start, 36);
+    }
+
+    #[test]
+    fn test_unterminated_block_comment() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let tokens = lex_in(&mut ctx, "x /* never /* closed */");
+        assert_eq!(tokens.last().unwrap().kind, TokenKind::Eof);
+        assert!(ctx.has_errors());
+        assert_eq!(ctx.diagnostics[0].span.start, 2);
+    }
+
+    #[test]
+    fn test_radix_literals() {
+        assert_eq!(
+            kinds("0xFF 0xdead_beef 0o755 0b1010_1010 0"),
+            vec![
+                TokenKind::IntegerLit(0xFF, None),
+                TokenKind::IntegerLit(0xdead_beef, None),
+                TokenKind::IntegerLit(0o755, None),
+                TokenKind::IntegerLit(0b1010_1010, None),
+                TokenKind::IntegerLit(0, None),
+                TokenKind::Eof
+            ]
+        );
+    }
+
+    #[test]
+    fn test_radix_literal_errors() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        lex_in(&mut ctx, "0x");
+        assert_eq!(ctx.diagnostics.len(), 1);
+
+        let mut ctx = CompilerCtx::new("test", 16);
+        lex_in(&mut ctx, "0b102");
+        assert_eq!(ctx.diagnostics.len(), 1);
+        assert_eq!(ctx.diagnostics[0].span.start, 4);
+
+        let mut ctx = CompilerCtx::new("test", 16);
+        lex_in(&mut ctx, "0xFFFF_FFFF_FFFF_FFFF_FF");
+        assert!(ctx.has_errors());
+    }
+
+    #[test]
+    fn test_scientific_floats() {
+        assert_eq!(
+            kinds("1e10 6.022e23 1.5E-9 2e+3"),
+            vec![
+                TokenKind::FloatLit(1e10, None),
+                TokenKind::FloatLit(6.022e23, None),
+                TokenKind::FloatLit(1.5E-9, None),
+                TokenKind::FloatLit(2e3, None),
+                TokenKind::Eof
+            ]
+        );
+    }
+
+    #[test]
+    fn test_missing_exponent_digits() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let tokens = lex_in(&mut ctx, "1e + 2");
+        assert!(ctx.has_errors());
+        assert_eq!(tokens[0].kind, TokenKind::Error);
+        assert_eq!(tokens[1].kind, TokenKind::Plus);
+    }
+
+    #[test]
+    fn test_numeric_suffixes() {
+        assert_eq!(
+            kinds("42i32 255u8 0xFFu64 3.0f64 1f32 7"),
+            vec![
+                TokenKind::IntegerLit(42, Some(LitSuffix::I32)),
+                TokenKind::IntegerLit(255, Some(LitSuffix::U8)),
+                TokenKind::IntegerLit(0xFF, Some(LitSuffix::U64)),
+                TokenKind::FloatLit(3.0, Some(LitSuffix::F64)),
+                TokenKind::FloatLit(1.0, Some(LitSuffix::F32)),
+                TokenKind::IntegerLit(7, None),
+                TokenKind::Eof
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_suffixes() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let tokens = lex_in(&mut ctx, "42q");
+        assert_eq!(tokens[0].kind, TokenKind::IntegerLit(42, None));
+        assert_eq!(ctx.diagnostics.len(), 1);
+        assert!(ctx.diagnostics[0].message.contains("`q`"));
+
+        let mut ctx = CompilerCtx::new("test", 16);
+        lex_in(&mut ctx, "1.5u8");
+        assert_eq!(ctx.diagnostics.len(), 1);
+    }
+
+    #[test]
+    fn test_errors_do_not_stop_lexing() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let tokens = lex_in(&mut ctx, "a # b $ 99999999999999999999 c");
+        let kinds: Vec<_> = tokens.into_iter().map(|t| t.kind).collect();
+        let ident = |name| ident_in(&ctx, name);
+        assert_eq!(
+            kinds,
+            vec![
+                ident("a"),
+                TokenKind::Error,
+                ident("b"),
+                TokenKind::Error,
+                TokenKind::Error,
+                ident("c"),
+                TokenKind::Eof
+            ]
+        );
+        assert_eq!(ctx.diagnostics.len(), 3);
+        assert_eq!(ctx.diagnostics[0].span.start, 2);
+        assert_eq!(ctx.diagnostics[0].span.end, 3);
+    }
+
+    #[test]
+    fn test_unterminated_literals() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let tokens = lex_in(&mut ctx, "\"abc");
+        assert_eq!(tokens[0].kind, TokenKind::Error);
+        assert_eq!(tokens[0].span.end, 4);
+        assert_eq!(ctx.diagnostics.len(), 1);
+
+        let mut ctx = CompilerCtx::new("test", 16);
+        let tokens = lex_in(&mut ctx, "'a");
+        assert_eq!(tokens[0].kind, TokenKind::Error);
+        assert_eq!(tokens[1].kind, TokenKind::Eof);
+        assert_eq!(ctx.diagnostics.len(), 1);
+    }
+
+    #[test]
+    fn test_iterator_is_fused() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let mut lexer = Lexer::new("x", &mut ctx);
+        assert!(matches!(lexer.next().unwrap().kind, TokenKind::Ident(_)));
+        assert_eq!(lexer.next().unwrap().kind, TokenKind::Eof);
+        assert!(lexer.next().is_none());
+        assert!(lexer.next().is_none());
+    }
+
+    #[test]
+    fn test_lexer_builds_source_map() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let src = "val a = 1;\n  /* é */ b";
+        let mut lexer = Lexer::new(src, &mut ctx);
+        let b = lexer
+            .by_ref()
+            .filter(|t| matches!(t.kind, TokenKind::Ident(_)))
+            .nth(1);
+        assert_eq!(lexer.source_map().locate(b.unwrap().span.start), (2, 11));
+    }
+
+    #[test]
+    fn test_range_tokens() {
+        let (got, ident) = named_kinds("0..10 a..=b 1.5 a.b");
+        assert_eq!(
+            got,
+            vec![
+                TokenKind::IntegerLit(0, None),
+                TokenKind::DotDot,
+                TokenKind::IntegerLit(10, None),
+                ident("a"),
+                TokenKind::DotDotEq,
+                ident("b"),
+                TokenKind::FloatLit(1.5, None),
+                ident("a"),
+                TokenKind::Dot,
+                ident("b"),
+                TokenKind::Eof
+            ]
+        );
+    }
+
+    #[test]
+    fn test_path_separator() {
+        let tokens = lex("std::mem val x: i32");
+        assert_eq!(tokens[1].kind, TokenKind::ColonColon);
+        assert_eq!((tokens[1].span.start, tokens[1].span.end), (3, 5));
+        assert_eq!(tokens[5].kind, TokenKind::Colon);
+    }
+
+    #[test]
+    fn test_idents_are_interned() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let before = ctx.symbol_interner.len();
+        let tokens = lex_in(&mut ctx, "foo bar foo i32 fn");
+        assert_eq!(tokens[0].kind, tokens[2].kind);
+        assert_ne!(tokens[0].kind, tokens[1].kind);
+        assert_eq!(tokens[3].kind, TokenKind::Ident(Symbol::I32));
+        assert_eq!(tokens[4].kind, TokenKind::Fn);
+        // Keywords and names already seen add nothing
+        assert_eq!(ctx.symbol_interner.len(), before + 2);
+    }
+
+    #[test]
+    fn test_token_classification() {
+        assert!(TokenKind::Match.is_keyword());
+        assert!(TokenKind::As.is_keyword());
+        assert!(!TokenKind::BoolLit(true).is_keyword());
+        assert!(!TokenKind::Ident(Symbol::MAIN).is_keyword());
+
+        assert!(TokenKind::Null.is_literal());
+        assert!(TokenKind::IntegerLit(1, None).is_literal());
+        assert!(!TokenKind::Undefined.is_literal());
+
+        assert!(TokenKind::Percent.is_binary_op());
+        assert!(matches!(TokenKind::LtEq.binary_op(), Some(BinaryOp::LtEq)));
+        assert!(!TokenKind::PipeGreater.is_binary_op());
+        assert!(!TokenKind::Assign.is_binary_op());
+    }
+
+    #[test]
+    fn test_peek_tokens() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        // Predefined names, so their symbols are known while lexing
+        let mut lexer = Lexer::new("i8 + str", &mut ctx);
+        assert_eq!(lexer.peek_nth(2).span, Span::new(5, 8));
+        assert_eq!(lexer.peek_token().kind, TokenKind::Ident(Symbol::I8));
+        assert_eq!(lexer.peek_nth(5).kind, TokenKind::Eof);
+
+        let a = lexer.next_token();
+        assert_eq!(
+            (a.kind, a.span),
+            (TokenKind::Ident(Symbol::I8), Span::new(0, 2))
+        );
+        assert_eq!(lexer.peek_token().kind, TokenKind::Plus);
+        assert_eq!(lexer.next_token().span, Span::new(3, 4));
+        assert_eq!(lexer.next_token().kind, TokenKind::Ident(Symbol::STR));
+        assert_eq!(lexer.next_token().span, Span::new(8, 8));
+        assert_eq!(lexer.peek_token().kind, TokenKind::Eof);
+    }
+
+    #[test]
+    fn test_number_out_of_range() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let src = "99999999999999999999 + 0xffff_ffff_ffff_ffff_f; 1e999 9223372036854775807";
+        let kinds: Vec<_> = lex_in(&mut ctx, src).into_iter().map(|t| t.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                TokenKind::Error,
+                TokenKind::Plus,
+                TokenKind::Error,
+                TokenKind::Semicolon,
+                TokenKind::Error,
+                TokenKind::IntegerLit(i64::MAX, None),
+                TokenKind::Eof,
+            ]
+        );
+        let got: Vec<_> = ctx
+            .diagnostics
+            .iter()
+            .map(|d| (d.message.as_str(), d.span))
+            .collect();
+        assert_eq!(
+            got,
+            [
+                ("integer literal too large for i64", Span::new(0, 20)),
+                ("integer literal too large for i64", Span::new(23, 46)),
+                ("float literal too large for f64", Span::new(48, 53)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_leading_and_trailing_dot_floats() {
+        let (got, ident) = named_kinds("x = .5; 5. + 1");
+        assert_eq!(
+            got,
+            vec![
+                ident("x"),
+                TokenKind::Assign,
+                TokenKind::FloatLit(0.5, None),
+                TokenKind::Semicolon,
+                TokenKind::FloatLit(5.0, None),
+                TokenKind::Plus,
+                TokenKind::IntegerLit(1, None),
+                TokenKind::Eof,
+            ]
+        );
+        assert_eq!(lex(" .25f32")[0].span, Span::new(1, 7));
+        assert_eq!(
+            kinds(".5f32")[0],
+            TokenKind::FloatLit(0.5, Some(LitSuffix::F32))
+        );
+        assert_eq!(kinds("5.")[0], TokenKind::FloatLit(5.0, None));
+    }
+
+    #[test]
+    fn test_dot_after_integer_stays_member_access() {
+        let (got, ident) = named_kinds("5.abs() 0..n t.0");
+        assert_eq!(
+            got,
+            vec![
+                TokenKind::IntegerLit(5, None),
+                TokenKind::Dot,
+                ident("abs"),
+                TokenKind::LParen,
+                TokenKind::RParen,
+                TokenKind::IntegerLit(0, None),
+                TokenKind::DotDot,
+                ident("n"),
+                ident("t"),
+                TokenKind::Dot,
+                TokenKind::IntegerLit(0, None),
+                TokenKind::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_digit_separators() {
+        assert_eq!(kinds("1_000")[0], TokenKind::IntegerLit(1000, None));
+        assert_eq!(kinds("0xff_ff")[0], TokenKind::IntegerLit(0xffff, None));
+        assert_eq!(kinds("1_0.2_5e1_0")[0], TokenKind::FloatLit(10.25e10, None));
+
+        let mut ctx = CompilerCtx::new("test", 16);
+        let kinds: Vec<_> = lex_in(&mut ctx, "1_ 1__0 0x_1 2_.5 1e_5 0b1_2")
+            .into_iter()
+            .map(|t| t.kind)
+            .collect();
+        assert_eq!(kinds[0], TokenKind::IntegerLit(1, None));
+        assert_eq!(kinds[1], TokenKind::IntegerLit(10, None));
+        let got: Vec<_> = ctx
+            .diagnostics
+            .iter()
+            .map(|d| (d.message.as_str(), d.span))
+            .collect();
+        let msg = "`_` in a number literal must be between two digits";
+        assert_eq!(
+            got,
+            [
+                (msg, Span::new(1, 2)),
+                (msg, Span::new(4, 6)),
+                (msg, Span::new(10, 11)),
+                (msg, Span::new(14, 15)),
+                (msg, Span::new(20, 21)),
+                ("invalid digit '2' in base 2 literal", Span::new(27, 28)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_as_keyword() {
+        let (got, ident) = named_kinds("x as i64 ask");
+        assert_eq!(
+            got,
+            vec![
+                ident("x"),
+                TokenKind::As,
+                ident("i64"),
+                ident("ask"),
+                TokenKind::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_mut_keyword() {
+        let (got, ident) = named_kinds("&mut x mutable");
+        assert_eq!(
+            got,
+            vec![
+                TokenKind::Ampersand,
+                TokenKind::Mut,
+                ident("x"),
+                ident("mutable"),
+                TokenKind::Eof,
+            ]
+        );
+        assert!(TokenKind::Mut.is_keyword());
+    }
+
+    #[test]
+    fn test_unicode_escapes() {
+        assert_eq!(
+            kinds(r#""\u{1F600} \u{41}\n""#),
+            vec![TokenKind::StringLit("😀 A\n".to_string()), TokenKind::Eof]
+        );
+    }
+
+    #[test]
+    fn test_invalid_unicode_escapes() {
+        for src in [
+            r#""\u{D800}""#,
+            r#""\u{110000}""#,
+            r#""\u{41""#,
+            r#""\u41""#,
+            r#""\u{}""#,
+            r#""\u{1234567}""#,
+        ] {
+            let mut ctx = CompilerCtx::new("test", 16);
+            lex_in(&mut ctx, src);
+            assert!(ctx.has_errors(), "{}", src);
+            assert_eq!(ctx.diagnostics[0].span.start, 1, "{}", src);
+        }
+    }
+
+    #[test]
+    fn test_hex_and_null_escapes() {
+        assert_eq!(
+            kinds(r#""\x41\0" '\x7a' '\0' '\n'"#),
+            vec![
+                TokenKind::StringLit("A\0".to_string()),
+                TokenKind::Char('z'),
+                TokenKind::Char('\0'),
+                TokenKind::Char('\n'),
+                TokenKind::Eof
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_hex_escapes() {
+        for src in [r#""\xZZ""#, r#""\x4""#, r#""\xFF""#, r#"'\x4'"#] {
+            let mut ctx = CompilerCtx::new("test", 16);
+            lex_in(&mut ctx, src);
+            assert_eq!(ctx.diagnostics.len(), 1, "{}", src);
+            assert_eq!(ctx.diagnostics[0].span.start, 1, "{}", src);
+        }
+    }
+
+    #[test]
+    fn test_token_spans() {
+        let src = "fn f(a: u8) -> [2]i32 {\n  a |> g(\"s\") ..= 0x1F\n}";
+        let (_, ident) = named_kinds(src);
+        assert_eq!(
+            lex_all(src),
+            vec![
+                (TokenKind::Fn, 0, 2),
+                (ident("f"), 3, 4),
+                (TokenKind::LParen, 4, 5),
+                (ident("a"), 5, 6),
+                (TokenKind::Colon, 6, 7),
+                (ident("u8"), 8, 10),
+                (TokenKind::RParen, 10, 11),
+                (TokenKind::Arrow, 12, 14),
+                (TokenKind::LBracket, 15, 16),
+                (TokenKind::IntegerLit(2, None), 16, 17),
+                (TokenKind::RBracket, 17, 18),
+                (ident("i32"), 18, 21),
+                (TokenKind::LBrace, 22, 23),
+                (ident("a"), 26, 27),
+                (TokenKind::PipeGreater, 28, 30),
+                (ident("g"), 31, 32),
+                (TokenKind::LParen, 32, 33),
+                (TokenKind::StringLit("s".to_string()), 33, 36),
+                (TokenKind::RParen, 36, 37),
+                (TokenKind::DotDotEq, 38, 41),
+                (TokenKind::IntegerLit(31, None), 42, 46),
+                (TokenKind::RBrace, 47, 48),
+                (TokenKind::Eof, 48, 48),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_char_escapes() {
+        assert_eq!(
+            kinds(r"'\'' '\\' '\u{41}' '\t' 'é'"),
+            vec![
+                TokenKind::Char('\''),
+                TokenKind::Char('\\'),
+                TokenKind::Char('A'),
+                TokenKind::Char('\t'),
+                TokenKind::Char('é'),
+                TokenKind::Eof
+            ]
+        );
+        let (_, ident) = named_kinds("x");
+        assert_eq!(
+            lex_all(r"x '\n' 'é'"),
+            vec![
+                (ident("x"), 0, 1),
+                (TokenKind::Char('\n'), 2, 6),
+                (TokenKind::Char('é'), 7, 11),
+                (TokenKind::Eof, 11, 11),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bad_char_literals() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let tokens = lex_in(&mut ctx, "'' 'ab' '\\xG0' x");
+        let kinds: Vec<_> = tokens.iter().map(|t| t.kind.clone()).collect();
+        let ident = |name| ident_in(&ctx, name);
+        assert_eq!(
+            kinds,
+            vec![
+                TokenKind::Error,
+                TokenKind::Error,
+                TokenKind::Error,
+                ident("x"),
+                TokenKind::Eof
+            ]
+        );
+        assert_eq!(ctx.diagnostics.len(), 3);
+        assert_eq!((tokens[1].span.start, tokens[1].span.end), (3, 7));
+    }
+
+    // Value, span and escapes of the first string literal in `src`
+    fn first_string(src: &str) -> (String, Span, Vec<StringEscape>) {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let mut lexer = Lexer::new(src, &mut ctx);
+        let token = lexer
+            .by_ref()
+            .find(|t| matches!(t.kind, TokenKind::StringLit(_)))
+            .expect("no string literal");
+        let TokenKind::StringLit(value) = token.kind else {
+            unreachable!()
+        };
+        // Lex the rest so later literals' escapes are recorded too
+        lexer.by_ref().for_each(drop);
+        let escapes = lexer.string_escapes(token.span).to_vec();
+        (value, token.span, escapes)
+    }
+
+    #[test]
+    fn test_string_escapes() {
+        let src = r#"x = "a\n\u{1F600}b\q\x41" + 1"#;
+        let (value, literal, escapes) = first_string(src);
+        assert_eq!(literal, Span::new(4, 25));
+
+        let found: Vec<_> = escapes
+            .iter()
+            .map(|e| (e.decoded, e.decoded_len, e.span.start, e.span.end))
+            .collect();
+        // `\q` is reported and dropped by the lexer, so it decodes to nothing
+        assert_eq!(
+            found,
+            [(1, 1, 6, 8), (2, 4, 8, 17), (7, 0, 18, 20), (7, 1, 20, 24)]
+        );
+
+        // Every decoded char maps to its source text
+        for (offset, ch) in value.char_indices() {
+            let pos = string_source_offset(&escapes, literal, offset as u32) as usize;
+            let expected = match ch {
+                '\n' => "\\n",
+                '😀' => "\\u{1F600}",
+                'A' => "\\x41",
+                _ => &ch.to_string(),
+            };
+            assert!(src[pos..].starts_with(expected), "{:?} at {}", ch, pos);
+        }
+        // Bytes inside a multi-byte char land on its escape, and the end of
+        // the value on the closing quote
+        assert_eq!(string_source_offset(&escapes, literal, 3), 8);
+        assert_eq!(
+            string_source_offset(&escapes, literal, value.len() as u32),
+            24
+        );
+    }
+
+    #[test]
+    fn test_string_escapes_malformed() {
+        // Each is reported by the lexer, the escapes are still found and
+        // take up exactly the source the lexer consumed for them
+        for (src, span) in [
+            (r#""\x4z""#, (1, 4)),
+            (r#""\x80z""#, (1, 5)),
+            (r#""\u{110000}z""#, (1, 11)),
+            (r#""\u{1234567}z""#, (1, 12)),
+            (r#""\u{z""#, (1, 4)),
+            (r#""\u{12z""#, (1, 6)),
+        ] {
+            let (value, literal, escapes) = first_string(src);
+            assert_eq!(value, "z", "{}", src);
+            assert_eq!(escapes.len(), 1, "{}", src);
+            assert_eq!(escapes[0].decoded_len, 0, "{}", src);
+            assert_eq!(
+                (escapes[0].span.start, escapes[0].span.end),
+                span,
+                "{}",
+                src
+            );
+            assert_eq!(
+                string_source_offset(&escapes, literal, 0),
+                span.1,
+                "{}",
+                src
+            );
+        }
+    }
+}
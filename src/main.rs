@@ -1,25 +1,171 @@
+#![allow(dead_code)]
+
 mod arena;
 mod ast;
+mod ast_printer;
 mod common;
 mod context;
+mod errors;
+mod fold;
 mod lexer;
+mod parser;
+mod pass;
+mod resolve;
+mod value_check;
+mod visit;
+
+use std::process::ExitCode;
 
-use crate::lexer::TokenKind;
 use context::CompilerCtx;
+use lexer::Lexer;
+use parser::Parser;
+
+const USAGE: &str = "usage: nerva <file> [--dump=tokens|ast|diagnostics] [--tab-width=N]";
+
+// What to print instead of the rendered diagnostics
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Dump {
+    Tokens,
+    Ast,
+    // As JSON, see `CompilerCtx::diagnostics_to_json`
+    Diagnostics,
+}
+
+#[derive(Debug, PartialEq)]
+struct Args {
+    path: String,
+    dump: Option<Dump>,
+    // Columns a tab counts for in diagnostic locations
+    tab_width: u32,
+}
+
+fn parse_args(args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut path = None;
+    let mut dump = None;
+    let mut tab_width = 1;
+    for arg in args {
+        if let Some(what) = arg.strip_prefix("--dump=") {
+            dump = Some(match what {
+                "tokens" => Dump::Tokens,
+                "ast" => Dump::Ast,
+                "diagnostics" => Dump::Diagnostics,
+                _ => return Err(format!("unknown dump kind `{}`", what)),
+            });
+        } else if let Some(width) = arg.strip_prefix("--tab-width=") {
+            tab_width = match width.parse() {
+                Ok(width) if width > 0 => width,
+                _ => return Err(format!("invalid tab width `{}`", width)),
+            };
+        } else if arg.starts_with('-') {
+            return Err(format!("unknown option `{}`", arg));
+        } else if path.replace(arg).is_some() {
+            return Err("expected a single source file".to_string());
+        }
+    }
+    let path = path.ok_or("missing source file")?;
+    Ok(Args {
+        path,
+        dump,
+        tab_width,
+    })
+}
+
+// One `kind @ start..end` line per token, identifiers shown by name
+fn dump_tokens(source: &str, ctx: &mut CompilerCtx) -> String {
+    let tokens: Vec<_> = Lexer::new(source, ctx).collect();
+    let mut out = String::new();
+    for token in tokens {
+        out += &format!(
+            "{} @ {}..{}\n",
+            token.kind.describe(&ctx.symbol_interner),
+            token.span.start,
+            token.span.end
+        );
+    }
+    out
+}
+
+fn main() -> ExitCode {
+    let Args {
+        path,
+        dump,
+        tab_width,
+    } = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("error: {}\n{}", message, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    let source = match std::fs::read_to_string(&path) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("error: cannot read `{}`: {}", path, err);
+            return ExitCode::from(2);
+        }
+    };
 
-fn main() {
-    let mut l = lexer::Lexer::new(
-        "val y = {
-    val a = 6_000.9;
-    val b = 8;
-    a + b
-}",
-    );
-    loop {
-        let k = l.next_token().kind;
-        println!("{:?}", k);
-        if k == TokenKind::Eof {
-            break;
+    let mut ctx = CompilerCtx::new("native", 1024);
+    ctx.files.tab_width = tab_width;
+    let file = ctx.files.add(&path, &source);
+    ctx.current_file = file;
+
+    if dump == Some(Dump::Tokens) {
+        print!("{}", dump_tokens(&source, &mut ctx));
+    } else {
+        let items = Parser::new(&source, &mut ctx).parse_program();
+        if dump == Some(Dump::Ast) {
+            print!("{}", ast_printer::print_program(&ctx, &items));
         }
     }
+
+    ctx.finalize_diagnostics();
+    if dump == Some(Dump::Diagnostics) {
+        println!("{}", ctx.diagnostics_to_json());
+    } else {
+        eprint!("{}", ctx.emit_all(&source));
+    }
+
+    if ctx.has_errors() {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Result<(String, Option<Dump>, u32), String> {
+        parse_args(list.iter().map(|s| s.to_string())).map(|a| (a.path, a.dump, a.tab_width))
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(args(&["a.nv"]), Ok(("a.nv".to_string(), None, 1)));
+        assert_eq!(
+            args(&["--dump=ast", "a.nv"]),
+            Ok(("a.nv".to_string(), Some(Dump::Ast), 1))
+        );
+        assert_eq!(
+            args(&["a.nv", "--dump=tokens", "--tab-width=4"]),
+            Ok(("a.nv".to_string(), Some(Dump::Tokens), 4))
+        );
+        assert!(args(&["a.nv", "--tab-width=0"]).is_err());
+        assert!(args(&["a.nv", "--tab-width=x"]).is_err());
+        assert!(args(&[]).is_err());
+        assert!(args(&["a.nv", "b.nv"]).is_err());
+        assert!(args(&["a.nv", "--dump=hir"]).is_err());
+        assert!(args(&["a.nv", "-v"]).is_err());
+    }
+
+    #[test]
+    fn test_dump_tokens() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        assert_eq!(
+            dump_tokens("val abc = \"s\"", &mut ctx),
+            "Val @ 0..3\nIdent(\"abc\") @ 4..7\nAssign @ 8..9\nStringLit(\"s\") @ 10..13\nEof @ 13..13\n"
+        );
+    }
 }
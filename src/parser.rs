@@ -0,0 +1,3067 @@
+use crate::ast::{
+    AssignOp, Attribute, BinaryOp, EnumVariant, Expr, ExprId, ExprKind, FieldPattern, FnSig, Item,
+    ItemId, ItemKind, Literal, MatchCase, PREC_PIPELINE, Param, Pattern, PatternId, PatternKind,
+    Stmt, StmtId, StmtKind, StructField, TypeSpec, TypeSpecId, TypeSpecKind, UnaryOp, UnionVariant,
+    UnionVariantData, VarInit,
+};
+use crate::common::{Span, Symbol, closest_match};
+use crate::context::CompilerCtx;
+use crate::errors::ErrorCode;
+use crate::fold::const_eval;
+use crate::lexer::{Lexer, LitSuffix, Token, TokenKind};
+
+// Operators that can appear between two operands. `|>` isn't a `BinaryOp`
+// but parses exactly like one.
+enum InfixOp {
+    Binary(BinaryOp),
+    Pipeline,
+}
+
+fn infix_op(kind: &TokenKind) -> Option<(InfixOp, u8)> {
+    if let TokenKind::PipeGreater = kind {
+        return Some((InfixOp::Pipeline, PREC_PIPELINE));
+    }
+    let op = kind.binary_op()?;
+    let prec = op.precedence();
+    Some((InfixOp::Binary(op), prec))
+}
+
+fn assign_op(kind: &TokenKind) -> Option<AssignOp> {
+    match kind {
+        TokenKind::Assign => Some(AssignOp::Assign),
+        TokenKind::PlusAssign => Some(AssignOp::Add),
+        TokenKind::MinusAssign => Some(AssignOp::Sub),
+        TokenKind::StarAssign => Some(AssignOp::Mul),
+        TokenKind::SlashAssign => Some(AssignOp::Div),
+        _ => None,
+    }
+}
+
+fn prefix_op(kind: &TokenKind) -> Option<UnaryOp> {
+    match kind {
+        TokenKind::Minus => Some(UnaryOp::Neg),
+        TokenKind::Bang => Some(UnaryOp::Not),
+        TokenKind::Star => Some(UnaryOp::Deref),
+        _ => None,
+    }
+}
+
+// Tokens that can only carry on an expression, never start one, so with
+// `newline_semicolons` on a line starting with one continues the line
+// before: `x\n    |> g`. `-` and `*` are also prefix operators and do
+// start a new statement, `&&` doesn't even though `&&x` is valid.
+fn continues_expr(kind: &TokenKind) -> bool {
+    match kind {
+        TokenKind::Minus | TokenKind::Star => false,
+        TokenKind::Dot
+        | TokenKind::PipeGreater
+        | TokenKind::As
+        | TokenKind::RParen
+        | TokenKind::RBracket
+        | TokenKind::Comma => true,
+        kind => kind.is_binary_op() || assign_op(kind).is_some(),
+    }
+}
+
+// The lexer joins operator characters greedily, so `&&`, `||` and `>>` are
+// single tokens even where the grammar wants their first character alone.
+// `Parser::eat_split` splits them back, and only in these places:
+// - `&&` in prefix position, where `&&x` and `&&T` are two references
+// - `||` in a pattern, where `a || b` reads as `a | |b`
+// - `>>` and `>=` where a `>` closes an angle bracket list, as in nested
+//   generic arguments
+fn split_token(kind: &TokenKind) -> Option<(TokenKind, TokenKind)> {
+    match kind {
+        TokenKind::AndAnd => Some((TokenKind::Ampersand, TokenKind::Ampersand)),
+        TokenKind::OrOr => Some((TokenKind::BitOr, TokenKind::BitOr)),
+        TokenKind::Shr => Some((TokenKind::Gt, TokenKind::Gt)),
+        TokenKind::GtEq => Some((TokenKind::Gt, TokenKind::Assign)),
+        _ => None,
+    }
+}
+
+// Recursive descent parser writing nodes straight into `ctx.ast`. The
+// compiler context is reached through the lexer, which reports its own
+// diagnostics into it as tokens are pulled.
+pub struct Parser<'a> {
+    lexer: Lexer<'a>,
+    token: Token,
+    prev_span: Span,
+    // Set while parsing the head of an `if`, `while`, `for` or `match`,
+    // where `Name {` starts the body rather than a struct literal
+    no_struct_literal: bool,
+    // Set while parsing the target type of a cast, where `x as T < y` is a
+    // comparison rather than `T` with type arguments
+    no_type_args: bool,
+    // Opt-in: a line break ends a statement or declaration wherever its `;`
+    // could go, so `;` is optional at the end of a line. An expression only
+    // carries on to the next line when the line ends in something that
+    // can't finish it, such as a binary operator or an open delimiter.
+    pub newline_semicolons: bool,
+    // Whether a line break ends the expression being parsed, set for
+    // statements with `newline_semicolons` on and cleared inside delimiters
+    newline_ends_expr: bool,
+    // Text of the doc comments right before `token`. They never reach the
+    // grammar, an item takes them and anywhere else they're dropped.
+    doc_lines: Vec<String>,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(input: &'a str, ctx: &'a mut CompilerCtx) -> Self {
+        let mut parser = Self {
+            lexer: Lexer::new(input, ctx),
+            token: Token {
+                kind: TokenKind::Eof,
+                span: Span::DUMMY,
+            },
+            prev_span: Span::DUMMY,
+            no_struct_literal: false,
+            no_type_args: false,
+            newline_semicolons: false,
+            newline_ends_expr: false,
+            doc_lines: Vec::new(),
+        };
+        parser.bump();
+        parser
+    }
+
+    pub fn ctx(&mut self) -> &mut CompilerCtx {
+        self.lexer.ctx()
+    }
+
+    fn bump(&mut self) -> Token {
+        self.doc_lines.clear();
+        let mut next = self.lexer.next_token();
+        while let TokenKind::DocComment(text) = next.kind {
+            self.doc_lines.push(text);
+            next = self.lexer.next_token();
+        }
+        let token = std::mem::replace(&mut self.token, next);
+        self.prev_span = token.span;
+        token
+    }
+
+    // The token after the current one, skipping doc comments like `bump`
+    fn peek(&mut self) -> &TokenKind {
+        let mut n = 0;
+        while matches!(self.lexer.peek_nth(n).kind, TokenKind::DocComment(_)) {
+            n += 1;
+        }
+        &self.lexer.peek_nth(n).kind
+    }
+
+    fn check(&self, kind: &TokenKind) -> bool {
+        self.token.kind == *kind
+    }
+
+    fn eat(&mut self, kind: &TokenKind) -> bool {
+        if self.check(kind) {
+            self.bump();
+            true
+        } else {
+            false
+        }
+    }
+
+    // Like `eat`, but also takes `kind` off the front of a joined token as
+    // listed in `split_token`, leaving the rest as the current token
+    fn eat_split(&mut self, kind: &TokenKind) -> bool {
+        if self.eat(kind) {
+            return true;
+        }
+        match split_token(&self.token.kind) {
+            Some((first, rest)) if first == *kind => {
+                let span = self.token.span;
+                self.prev_span = span.first_char();
+                self.token = Token {
+                    kind: rest,
+                    span: Span::new(span.start + 1, span.end),
+                };
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Consumes `kind` or reports "expected `what`" without consuming anything
+    fn expect(&mut self, kind: &TokenKind, what: &str) -> bool {
+        if self.eat(kind) {
+            return true;
+        }
+        self.expected_here(what);
+        false
+    }
+
+    fn expect_ident(&mut self) -> Option<Symbol> {
+        if let TokenKind::Ident(name) = self.token.kind {
+            self.bump();
+            return Some(name);
+        }
+        // `val match = 1`: say why rather than just that a name was
+        // expected, then carry on as if it were one. Keywords that start a
+        // new statement or item more likely mean the name is missing.
+        if let Some(word) = self.token.kind.reserved_word()
+            && !self.at_sync_point()
+        {
+            let span = self.bump().span;
+            self.report(ErrorCode::KeywordAsName, span, &[word]);
+            return Some(self.ctx().symbol_interner.intern(word));
+        }
+        self.expected_here("identifier");
+        None
+    }
+
+    // Parses `item, item, ...` up to and including `close`, allowing a
+    // trailing comma. The opening delimiter must already be consumed.
+    fn parse_comma_list<T>(
+        &mut self,
+        close: &TokenKind,
+        what: &str,
+        mut parse_item: impl FnMut(&mut Self) -> T,
+    ) -> Vec<T> {
+        let mut items = Vec::new();
+        while !self.check(close) && !self.check(&TokenKind::Eof) {
+            items.push(self.with_struct_literals(&mut parse_item));
+            if !self.eat(&TokenKind::Comma) {
+                break;
+            }
+        }
+        self.expect(close, what);
+        items
+    }
+
+    // Runs `f` with struct literals allowed again, for anything inside
+    // delimiters where a `{` can't be mistaken for the start of a body. A
+    // line break doesn't end anything in there either.
+    fn with_struct_literals<R>(&mut self, f: impl FnOnce(&mut Self) -> R) -> R {
+        let outer = std::mem::replace(&mut self.no_struct_literal, false);
+        let outer_newline = std::mem::replace(&mut self.newline_ends_expr, false);
+        let result = f(self);
+        self.no_struct_literal = outer;
+        self.newline_ends_expr = outer_newline;
+        result
+    }
+
+    // Runs `f` on a statement-level expression, which a line break ends
+    // with `newline_semicolons` on
+    fn with_newline_ends<R>(&mut self, f: impl FnOnce(&mut Self) -> R) -> R {
+        let outer = std::mem::replace(&mut self.newline_ends_expr, self.newline_semicolons);
+        let result = f(self);
+        self.newline_ends_expr = outer;
+        result
+    }
+
+    // Whether the current token is the first on its line
+    fn at_line_start(&self) -> bool {
+        let map = self.lexer.source_map();
+        map.locate(self.prev_span.end).0 < map.locate(self.token.span.start).0
+    }
+
+    // Whether the expression being parsed stops here instead of continuing
+    // with the current token
+    fn at_line_break_end(&self) -> bool {
+        self.newline_ends_expr && self.at_line_start() && !continues_expr(&self.token.kind)
+    }
+
+    // Consumes the `;` ending a statement or declaration. With
+    // `newline_semicolons` on a line break or a closing `}` also ends it,
+    // without consuming anything.
+    fn eat_semicolon(&mut self) -> bool {
+        self.eat(&TokenKind::Semicolon)
+            || (self.newline_semicolons && (self.at_line_start() || self.check(&TokenKind::RBrace)))
+    }
+
+    fn expect_semicolon(&mut self) -> bool {
+        self.eat_semicolon() || self.expect(&TokenKind::Semicolon, "`;`")
+    }
+
+    // The condition of an `if` or `while`, the iterable of a `for` or the
+    // target of a `match`. Struct literals there need parentheses, so that
+    // `if x == y { ... }` doesn't read `y { ... }` as one.
+    fn parse_head_expr(&mut self) -> ExprId {
+        let outer = std::mem::replace(&mut self.no_struct_literal, true);
+        let expr = self.parse_expr();
+        self.no_struct_literal = outer;
+        expr
+    }
+
+    fn report(&mut self, code: ErrorCode, span: Span, args: &[&str]) {
+        self.ctx().report_code(code, span, args);
+    }
+
+    // Reports "expected `what`, found `found`"
+    fn expected(&mut self, what: &str, found: &TokenKind, span: Span) {
+        let found = self.describe(found);
+        self.report(ErrorCode::Expected, span, &[what, &found]);
+    }
+
+    // Reports "expected `what`" at the current token
+    fn expected_here(&mut self, what: &str) {
+        let found = self.describe(&self.token.kind.clone());
+        self.report(ErrorCode::Expected, self.token.span, &[what, &found]);
+    }
+
+    // A token for "found ..." messages
+    fn describe(&mut self, kind: &TokenKind) -> String {
+        kind.describe(&self.ctx().symbol_interner)
+    }
+
+    fn alloc_expr(&mut self, kind: ExprKind, span: Span) -> ExprId {
+        self.ctx().ast.exprs.alloc(Expr {
+            kind,
+            span,
+            ty: None,
+        })
+    }
+
+    fn alloc_item(&mut self, kind: ItemKind, span: Span) -> ItemId {
+        self.ctx().ast.items.alloc(Item {
+            kind,
+            span,
+            attrs: Vec::new(),
+            doc: None,
+        })
+    }
+
+    fn alloc_type(&mut self, kind: TypeSpecKind, span: Span) -> TypeSpecId {
+        self.ctx().ast.type_specs.alloc(TypeSpec { kind, span })
+    }
+
+    fn alloc_pattern(&mut self, kind: PatternKind, span: Span) -> PatternId {
+        self.ctx().ast.patterns.alloc(Pattern { kind, span })
+    }
+
+    fn alloc_stmt(&mut self, kind: StmtKind, span: Span) -> StmtId {
+        self.ctx().ast.stmts.alloc(Stmt { kind, span })
+    }
+
+    fn expr_span(&mut self, id: ExprId) -> Span {
+        self.ctx().ast.exprs[id].span
+    }
+
+    // Parses items until the end of input. Stray tokens between items are
+    // reported and skipped.
+    pub fn parse_program(&mut self) -> Vec<ItemId> {
+        let mut items = Vec::new();
+        while !self.check(&TokenKind::Eof) && !self.ctx().should_abort() {
+            items.extend(self.parse_item());
+        }
+        items
+    }
+
+    // Skips tokens up to the next statement or item boundary: a `;` or `}`
+    // (left for the caller to consume) or a keyword starting a declaration.
+    // Braces opened while skipping are skipped as a whole.
+    fn synchronize(&mut self) {
+        let mut depth = 0usize;
+        loop {
+            match self.token.kind {
+                TokenKind::Eof => return,
+                TokenKind::LBrace => depth += 1,
+                TokenKind::RBrace if depth == 0 => return,
+                TokenKind::RBrace => depth -= 1,
+                _ if depth == 0 && self.at_sync_point() => return,
+                _ => {}
+            }
+            self.bump();
+        }
+    }
+
+    fn at_sync_point(&self) -> bool {
+        self.at_item_start()
+            || matches!(
+                self.token.kind,
+                TokenKind::Semicolon
+                    | TokenKind::RBrace
+                    | TokenKind::Eof
+                    | TokenKind::Val
+                    | TokenKind::Var
+                    | TokenKind::Defer
+            )
+    }
+
+    fn at_item_start(&self) -> bool {
+        matches!(
+            self.token.kind,
+            TokenKind::Fn
+                | TokenKind::Struct
+                | TokenKind::Enum
+                | TokenKind::Union
+                | TokenKind::Impl
+                | TokenKind::Const
+                | TokenKind::Extern
+        )
+    }
+
+    // Parses a single top level declaration. Returns `None` when no item
+    // could be built, after reporting why.
+    pub fn parse_item(&mut self) -> Option<ItemId> {
+        let header = self.parse_item_header();
+        let item = match self.token.kind {
+            TokenKind::Fn => self.parse_fn(),
+            TokenKind::Struct => self.parse_struct(),
+            TokenKind::Enum => self.parse_enum(),
+            TokenKind::Union => self.parse_union(),
+            TokenKind::Impl => self.parse_impl(),
+            TokenKind::Const => self.parse_const(),
+            TokenKind::Extern => self.parse_extern(),
+            _ => {
+                let token = self.bump();
+                let found = self.describe(&token.kind);
+                let mut diag = ErrorCode::Expected.diagnostic(token.span, &["item", &found]);
+                // Most likely a misspelled `fn`, `struct` and so on
+                if let TokenKind::Ident(name) = token.kind
+                    && let Some(keyword) = closest_match(
+                        self.ctx().symbol_interner.resolve(name),
+                        TokenKind::reserved_words(),
+                    )
+                {
+                    diag = diag.with_note(&format!("did you mean `{}`?", keyword));
+                }
+                self.ctx().emit(diag);
+                while !self.at_item_start()
+                    && !self.check(&TokenKind::At)
+                    && !self.check(&TokenKind::Eof)
+                {
+                    self.bump();
+                }
+                None
+            }
+        };
+        self.attach_header(item, header)
+    }
+
+    // The doc comment and attributes before an item. Doc comments can come
+    // before or after the attributes.
+    fn parse_item_header(&mut self) -> (Option<String>, Vec<Attribute>) {
+        let mut lines = std::mem::take(&mut self.doc_lines);
+        let attrs = self.parse_attributes();
+        lines.append(&mut self.doc_lines);
+        let doc = if lines.is_empty() {
+            None
+        } else {
+            Some(lines.join("\n"))
+        };
+        (doc, attrs)
+    }
+
+    // `@name` or `@name(args, ...)`, any number of them before an item
+    fn parse_attributes(&mut self) -> Vec<Attribute> {
+        let mut attrs = Vec::new();
+        while self.check(&TokenKind::At) {
+            let start = self.bump().span;
+            let Some(name) = self.expect_ident() else {
+                continue;
+            };
+            let args = if self.eat(&TokenKind::LParen) {
+                self.parse_comma_list(&TokenKind::RParen, "`)`", |p| p.parse_expr())
+            } else {
+                Vec::new()
+            };
+            attrs.push(Attribute {
+                name,
+                args,
+                span: start.to(self.prev_span),
+            });
+        }
+        attrs
+    }
+
+    fn attach_header(
+        &mut self,
+        item: Option<ItemId>,
+        (doc, attrs): (Option<String>, Vec<Attribute>),
+    ) -> Option<ItemId> {
+        if let Some(id) = item {
+            let item = &mut self.ctx().ast.items[id];
+            item.doc = doc;
+            item.attrs = attrs;
+        }
+        item
+    }
+
+    // `fn name(param: Type, ...) -> Type { ... }`, a missing return type
+    // means the function returns nothing
+    fn parse_fn(&mut self) -> Option<ItemId> {
+        let start = self.bump().span;
+        let sig = self.parse_fn_sig();
+        let body = self.parse_block();
+
+        let sig = sig?;
+        let kind = ItemKind::FnDecl {
+            name: sig.name,
+            params: sig.params,
+            ret_ty: sig.return_ty,
+            body,
+        };
+        Some(self.alloc_item(kind, start.to(self.prev_span)))
+    }
+
+    // `name(param: Type, ...) -> Type`, the part after `fn` shared by
+    // definitions and extern declarations
+    fn parse_fn_sig(&mut self) -> Option<FnSig> {
+        let name = self.expect_ident();
+        let (params, return_ty) = self.parse_signature();
+        Some(FnSig {
+            name: name?,
+            params,
+            return_ty,
+        })
+    }
+
+    // `(param: Type, ...) -> Type` with an optional return type, shared by
+    // named functions and closures
+    fn parse_signature(&mut self) -> (Vec<Param>, Option<TypeSpecId>) {
+        let mut params = Vec::new();
+        if self.expect(&TokenKind::LParen, "`(`") {
+            params = self.parse_comma_list(&TokenKind::RParen, "`)`", |p| {
+                let span = p.token.span;
+                p.parse_param().map(|param| (param, span))
+            });
+        }
+        let params: Vec<_> = params.into_iter().flatten().collect();
+        let names: Vec<_> = params.iter().map(|(p, span)| (p.name, *span)).collect();
+        self.check_duplicates(ErrorCode::DuplicateParam, &names);
+        let return_ty = if self.eat(&TokenKind::Arrow) {
+            Some(self.parse_type())
+        } else {
+            None
+        };
+        let params = params.into_iter().map(|(param, _)| param).collect();
+        (params, return_ty)
+    }
+
+    // `struct Name { field: Type, ... }`. Fields may also be separated by
+    // nothing but a line break.
+    fn parse_struct(&mut self) -> Option<ItemId> {
+        let start = self.bump().span;
+        let name = self.expect_ident();
+        let fields = self.parse_struct_fields();
+        let kind = ItemKind::StructDecl {
+            name: name?,
+            fields,
+        };
+        Some(self.alloc_item(kind, start.to(self.prev_span)))
+    }
+
+    // Reports each name that repeats an earlier one in `names`, pointing
+    // back at the first. Both stay in the AST, lookups find the first.
+    fn check_duplicates(&mut self, code: ErrorCode, names: &[(Symbol, Span)]) {
+        for (i, &(name, span)) in names.iter().enumerate() {
+            if let Some(&(_, first)) = names[..i].iter().find(|(n, _)| *n == name) {
+                let text = self.ctx().symbol_interner.resolve(name);
+                let diag = code
+                    .diagnostic(span, &[text])
+                    .with_label(first, "first defined here");
+                self.ctx().emit(diag);
+            }
+        }
+    }
+
+    // `{ field: Type, ... }`, shared by structs and struct-like union
+    // variants
+    fn parse_struct_fields(&mut self) -> Vec<StructField> {
+        let mut fields: Vec<StructField> = Vec::new();
+        let mut names = Vec::new();
+        if !self.expect(&TokenKind::LBrace, "`{`") {
+            return fields;
+        }
+        while !self.check(&TokenKind::RBrace) && !self.check(&TokenKind::Eof) {
+            let field_span = self.token.span;
+            let Some(field) = self.parse_struct_field() else {
+                break;
+            };
+            names.push((field.name, field_span));
+            fields.push(field);
+            // Separated by a `,` or a line break
+            if !self.eat(&TokenKind::Comma)
+                && !self.check(&TokenKind::RBrace)
+                && !self.at_line_start()
+            {
+                self.expected_here("`,`");
+            }
+        }
+        self.expect(&TokenKind::RBrace, "`}`");
+        self.check_duplicates(ErrorCode::DuplicateField, &names);
+        fields
+    }
+
+    fn parse_struct_field(&mut self) -> Option<StructField> {
+        let name = self.expect_ident()?;
+        self.expect(&TokenKind::Colon, "`:`");
+        let ty = self.parse_type();
+        Some(StructField { name, ty })
+    }
+
+    // `enum Name: Type { A, B = 2, ... }`. Variants without an explicit
+    // value are numbered by a later pass.
+    fn parse_enum(&mut self) -> Option<ItemId> {
+        let start = self.bump().span;
+        let name = self.expect_ident();
+        let backing_ty = if self.eat(&TokenKind::Colon) {
+            Some(self.parse_type())
+        } else {
+            None
+        };
+        let mut variants = Vec::new();
+        if self.expect(&TokenKind::LBrace, "`{`") {
+            variants = self.parse_comma_list(&TokenKind::RBrace, "`}`", |p| {
+                let span = p.token.span;
+                p.parse_enum_variant().map(|v| (v, span))
+            });
+        }
+        let variants: Vec<_> = variants.into_iter().flatten().collect();
+        let names: Vec<_> = variants.iter().map(|(v, span)| (v.name, *span)).collect();
+        self.check_duplicates(ErrorCode::DuplicateVariant, &names);
+
+        let kind = ItemKind::EnumDecl {
+            name: name?,
+            backing_ty,
+            variants: variants.into_iter().map(|(v, _)| v).collect(),
+        };
+        Some(self.alloc_item(kind, start.to(self.prev_span)))
+    }
+
+    fn parse_enum_variant(&mut self) -> Option<EnumVariant> {
+        let name = self.expect_ident()?;
+        let value = if self.eat(&TokenKind::Assign) {
+            let errors = self.ctx().diagnostics.len();
+            let expr = self.parse_expr();
+            let value = if self.ctx().diagnostics.len() > errors {
+                None
+            } else {
+                match const_eval(&self.ctx().ast, expr) {
+                    Ok(value) => Some(value),
+                    Err(diag) => {
+                        self.ctx().emit(*diag);
+                        None
+                    }
+                }
+            };
+            if !self.check(&TokenKind::Comma)
+                && !self.check(&TokenKind::RBrace)
+                && !self.check(&TokenKind::Eof)
+            {
+                // Skip what's left of the value so the next variant parses
+                self.expected_here("`,`");
+                while !self.check(&TokenKind::Comma)
+                    && !self.check(&TokenKind::RBrace)
+                    && !self.check(&TokenKind::Eof)
+                {
+                    self.bump();
+                }
+            }
+            value
+        } else {
+            None
+        };
+        Some(EnumVariant { name, value })
+    }
+
+    // `union Name { A, B(Type, ...), C { field: Type } }`
+    fn parse_union(&mut self) -> Option<ItemId> {
+        let start = self.bump().span;
+        let name = self.expect_ident();
+        let mut variants = Vec::new();
+        if self.expect(&TokenKind::LBrace, "`{`") {
+            variants = self.parse_comma_list(&TokenKind::RBrace, "`}`", |p| {
+                let span = p.token.span;
+                p.parse_union_variant().map(|v| (v, span))
+            });
+        }
+        let variants: Vec<_> = variants.into_iter().flatten().collect();
+        let names: Vec<_> = variants.iter().map(|(v, span)| (v.name, *span)).collect();
+        self.check_duplicates(ErrorCode::DuplicateVariant, &names);
+
+        let kind = ItemKind::UnionDecl {
+            name: name?,
+            variants: variants.into_iter().map(|(v, _)| v).collect(),
+        };
+        Some(self.alloc_item(kind, start.to(self.prev_span)))
+    }
+
+    fn parse_union_variant(&mut self) -> Option<UnionVariant> {
+        let name = self.expect_ident()?;
+        let data = if self.eat(&TokenKind::LParen) {
+            let types = self.parse_comma_list(&TokenKind::RParen, "`)`", |p| p.parse_type());
+            Some(UnionVariantData::Tuple(types))
+        } else if self.check(&TokenKind::LBrace) {
+            Some(UnionVariantData::Struct(self.parse_struct_fields()))
+        } else {
+            None
+        };
+        Some(UnionVariant { name, data })
+    }
+
+    // `impl Type { fn a() { ... } ... }`, only functions may appear inside
+    fn parse_impl(&mut self) -> Option<ItemId> {
+        let start = self.bump().span;
+        let self_ty = self.parse_type();
+        let mut methods = Vec::new();
+        if self.expect(&TokenKind::LBrace, "`{`") {
+            while !self.check(&TokenKind::RBrace) && !self.check(&TokenKind::Eof) {
+                let header = self.parse_item_header();
+                if self.check(&TokenKind::Fn) {
+                    let method = self.parse_fn();
+                    methods.extend(self.attach_header(method, header));
+                    continue;
+                }
+                let span = self.token.span;
+                if self.at_item_start() {
+                    // Parse the whole item so we resume after it
+                    self.parse_item();
+                    self.report(ErrorCode::NonFnInImpl, span, &[]);
+                } else if self.check(&TokenKind::RBrace) {
+                    // Attributes with nothing after them
+                    self.expected_here("`fn`");
+                } else {
+                    let token = self.bump();
+                    self.expected("`fn` or `}`", &token.kind, span);
+                }
+            }
+            self.expect(&TokenKind::RBrace, "`}`");
+        }
+
+        let kind = ItemKind::ImplDecl { self_ty, methods };
+        Some(self.alloc_item(kind, start.to(self.prev_span)))
+    }
+
+    // `const NAME: Type = expr;`, the type can't be inferred
+    fn parse_const(&mut self) -> Option<ItemId> {
+        let start = self.bump().span;
+        let name = self.expect_ident();
+        let ty = if self.eat(&TokenKind::Colon) {
+            Some(self.parse_type())
+        } else {
+            self.report(ErrorCode::ConstWithoutType, self.token.span, &[]);
+            None
+        };
+        self.expect(&TokenKind::Assign, "`=`");
+        let expr = self.with_newline_ends(Self::parse_expr);
+        self.expect_semicolon();
+
+        let kind = ItemKind::ConstDecl {
+            name: name?,
+            ty: ty?,
+            expr,
+        };
+        Some(self.alloc_item(kind, start.to(self.prev_span)))
+    }
+
+    // `extern "C" { fn name(param: Type) -> Type; ... }`
+    fn parse_extern(&mut self) -> Option<ItemId> {
+        let start = self.bump().span;
+        let api = match &self.token.kind {
+            TokenKind::StringLit(api) => {
+                let api = api.clone();
+                self.bump();
+                Some(api)
+            }
+            _ => {
+                self.expected_here("ABI string");
+                None
+            }
+        };
+
+        let mut declarations = Vec::new();
+        if self.expect(&TokenKind::LBrace, "`{`") {
+            while !self.check(&TokenKind::RBrace) && !self.check(&TokenKind::Eof) {
+                if !self.expect(&TokenKind::Fn, "`fn`") {
+                    self.bump();
+                    continue;
+                }
+                declarations.extend(self.parse_fn_sig());
+                self.expect_semicolon();
+            }
+            self.expect(&TokenKind::RBrace, "`}`");
+        }
+
+        let kind = ItemKind::ExternDecl {
+            api: api?,
+            declarations,
+        };
+        Some(self.alloc_item(kind, start.to(self.prev_span)))
+    }
+
+    fn parse_param(&mut self) -> Option<Param> {
+        let name = self.expect_ident()?;
+        self.expect(&TokenKind::Colon, "`:`");
+        let ty = self.parse_type();
+        Some(Param { name, ty })
+    }
+
+    // Prefix type constructors apply to everything after them, so `*T?` is
+    // a pointer to an optional and `**T` a pointer to a pointer.
+    pub fn parse_type(&mut self) -> TypeSpecId {
+        let start = self.token.span;
+        let kind = match self.token.kind {
+            TokenKind::Star => {
+                self.bump();
+                TypeSpecKind::Pointer(self.parse_type())
+            }
+            TokenKind::Ampersand | TokenKind::AndAnd => {
+                self.eat_split(&TokenKind::Ampersand);
+                if self.eat(&TokenKind::Mut) {
+                    TypeSpecKind::MutReference(self.parse_type())
+                } else {
+                    TypeSpecKind::Reference(self.parse_type())
+                }
+            }
+            TokenKind::Question => {
+                self.bump();
+                TypeSpecKind::Optional(self.parse_type())
+            }
+            TokenKind::LBracket => {
+                self.bump();
+                if self.eat(&TokenKind::RBracket) {
+                    TypeSpecKind::Slice(self.parse_type())
+                } else {
+                    let size = self.parse_expr();
+                    // A size naming a constant can only be evaluated once
+                    // names are resolved, but arithmetic that can never
+                    // succeed is reported right away
+                    if let Err(diag) = const_eval(&self.ctx().ast, size)
+                        && diag.code != Some(ErrorCode::NotConstant.code())
+                    {
+                        self.ctx().emit(*diag);
+                    }
+                    self.expect(&TokenKind::RBracket, "`]`");
+                    let elem_ty = self.parse_type();
+                    TypeSpecKind::Array { size, elem_ty }
+                }
+            }
+            TokenKind::Fn => return self.parse_fn_type(),
+            _ => return self.parse_postfix_type(),
+        };
+        self.alloc_type(kind, start.to(self.prev_span))
+    }
+
+    // `<A, B, ...>` after a generic type's name or a `::`, starting at the
+    // `<`. A `>>` or `>=` is split, so `Vec<Vec<i32>>` closes both lists.
+    fn parse_type_args(&mut self) -> Vec<TypeSpecId> {
+        self.bump();
+        let outer = std::mem::replace(&mut self.no_type_args, false);
+        let mut args = Vec::new();
+        while !self.check(&TokenKind::Gt) && !self.check(&TokenKind::Eof) {
+            args.push(self.parse_type());
+            if !self.eat(&TokenKind::Comma) {
+                break;
+            }
+        }
+        if !self.eat_split(&TokenKind::Gt) {
+            self.expected_here("`>`");
+        }
+        self.no_type_args = outer;
+        args
+    }
+
+    // `fn(A, B) -> C`, a missing return type means `void`
+    fn parse_fn_type(&mut self) -> TypeSpecId {
+        let start = self.bump().span;
+        let mut param = Vec::new();
+        if self.expect(&TokenKind::LParen, "`(`") {
+            param = self.parse_comma_list(&TokenKind::RParen, "`)`", |p| p.parse_type());
+        }
+        let return_ty = if self.eat(&TokenKind::Arrow) {
+            self.parse_type()
+        } else {
+            self.alloc_type(TypeSpecKind::Named(Symbol::VOID), self.prev_span)
+        };
+        let kind = TypeSpecKind::Fn { param, return_ty };
+        self.alloc_type(kind, start.to(self.prev_span))
+    }
+
+    // A named, generic or parenthesized type followed by any number of `?`
+    fn parse_postfix_type(&mut self) -> TypeSpecId {
+        let token = self.bump();
+        let kind = match token.kind {
+            TokenKind::Ident(name) => {
+                if self.check(&TokenKind::Lt) && !self.no_type_args {
+                    let args = self.parse_type_args();
+                    TypeSpecKind::Generic { base: name, args }
+                } else {
+                    TypeSpecKind::Named(name)
+                }
+            }
+            TokenKind::Void => TypeSpecKind::Named(Symbol::VOID),
+            TokenKind::LParen => {
+                let outer = std::mem::replace(&mut self.no_type_args, false);
+                let mut trailing_comma = false;
+                let mut elems = self.parse_comma_list(&TokenKind::RParen, "`)`", |p| {
+                    let elem = p.parse_type();
+                    trailing_comma = p.check(&TokenKind::Comma);
+                    elem
+                });
+                self.no_type_args = outer;
+                match elems.pop() {
+                    Some(inner) if elems.is_empty() && !trailing_comma => {
+                        TypeSpecKind::Paren(inner)
+                    }
+                    last => TypeSpecKind::Tuple(elems.into_iter().chain(last).collect()),
+                }
+            }
+            // Already reported by the lexer
+            TokenKind::Error => TypeSpecKind::Error,
+            kind => {
+                self.expected("type", &kind, token.span);
+                TypeSpecKind::Error
+            }
+        };
+        let mut ty = self.alloc_type(kind, token.span.to(self.prev_span));
+        while self.eat(&TokenKind::Question) {
+            ty = self.alloc_type(TypeSpecKind::Optional(ty), token.span.to(self.prev_span));
+        }
+        ty
+    }
+
+    pub fn parse_expr(&mut self) -> ExprId {
+        self.parse_assign()
+    }
+
+    // `target = value` and the compound forms bind loosest of all and
+    // associate to the right, so `a = b = c` assigns `c` to both
+    fn parse_assign(&mut self) -> ExprId {
+        let target = self.parse_binary(0);
+        let Some(op) = assign_op(&self.token.kind) else {
+            return target;
+        };
+        if self.at_line_break_end() {
+            return target;
+        }
+        self.bump();
+        let value = self.parse_assign();
+
+        let target_span = self.expr_span(target);
+        if !self.is_place(target) {
+            self.report(ErrorCode::InvalidAssignTarget, target_span, &[]);
+        }
+        let span = target_span.to(self.expr_span(value));
+        self.alloc_expr(ExprKind::Assign { target, op, value }, span)
+    }
+
+    // Expressions that name a memory location
+    fn is_place(&mut self, id: ExprId) -> bool {
+        matches!(
+            self.ctx().ast.exprs[id].kind,
+            ExprKind::Identifier(_)
+                | ExprKind::MemberAccess { .. }
+                | ExprKind::IndexAccess { .. }
+                | ExprKind::Unary {
+                    op: UnaryOp::Deref,
+                    ..
+                }
+                | ExprKind::Error
+        )
+    }
+
+    // Precedence climbing: only operators binding at least as tightly as
+    // `min_prec` are folded into the left operand
+    fn parse_binary(&mut self, min_prec: u8) -> ExprId {
+        let mut lhs = self.parse_cast();
+        while let Some((op, prec)) = infix_op(&self.token.kind) {
+            if prec < min_prec || self.at_line_break_end() {
+                break;
+            }
+            self.bump();
+            let right_assoc = matches!(&op, InfixOp::Binary(op) if op.is_right_assoc());
+            let rhs = self.parse_binary(if right_assoc { prec } else { prec + 1 });
+            let span = self.expr_span(lhs).to(self.expr_span(rhs));
+            let kind = match op {
+                InfixOp::Binary(op) => ExprKind::Binary { lhs, op, rhs },
+                InfixOp::Pipeline => ExprKind::Pipeline { lhs, rhs },
+            };
+            lhs = self.alloc_expr(kind, span);
+        }
+        lhs
+    }
+
+    // `expr as Type`, binding tighter than any binary operator but looser
+    // than prefix operators, so `-x as u8` casts the negation
+    fn parse_cast(&mut self) -> ExprId {
+        let mut expr = self.parse_unary();
+        while !self.at_line_break_end() && self.eat(&TokenKind::As) {
+            let outer = std::mem::replace(&mut self.no_type_args, true);
+            let target_type = self.parse_type();
+            self.no_type_args = outer;
+            let span = self.expr_span(expr).to(self.prev_span);
+            let kind = ExprKind::Cast {
+                target: expr,
+                target_type,
+            };
+            expr = self.alloc_expr(kind, span);
+        }
+        expr
+    }
+
+    // Prefix operators nest to the right. `&x` takes a read-only reference
+    // and `&mut x` a mutable one, `&&x` is `&(&x)` rather than a stray `&&`.
+    fn parse_unary(&mut self) -> ExprId {
+        let start = self.token.span;
+        let op = match self.token.kind {
+            TokenKind::Ampersand | TokenKind::AndAnd => {
+                self.eat_split(&TokenKind::Ampersand);
+                if self.eat(&TokenKind::Mut) {
+                    UnaryOp::AddressOfMut
+                } else {
+                    UnaryOp::AddressOf
+                }
+            }
+            ref kind => match prefix_op(kind) {
+                Some(op) => {
+                    self.bump();
+                    op
+                }
+                None => return self.parse_postfix(),
+            },
+        };
+        let operand = self.parse_unary();
+        let span = start.to(self.expr_span(operand));
+        self.alloc_expr(ExprKind::Unary { op, operand }, span)
+    }
+
+    // Calls, member and index accesses, left associative so that
+    // `foo.bar(1)[2]` is an index of a call of a member access. Type
+    // arguments are only taken after `::`, as in `f::<T>(x)`, since `f<T>`
+    // would read as a comparison.
+    fn parse_postfix(&mut self) -> ExprId {
+        let mut expr = self.parse_primary();
+        while !self.at_line_break_end() {
+            let start = self.expr_span(expr);
+            let kind = match self.token.kind {
+                TokenKind::LParen => {
+                    self.bump();
+                    let args = self.parse_comma_list(&TokenKind::RParen, "`)`", Self::parse_expr);
+                    ExprKind::Call {
+                        callee: expr,
+                        type_args: Vec::new(),
+                        args,
+                    }
+                }
+                TokenKind::ColonColon => {
+                    self.bump();
+                    if !self.check(&TokenKind::Lt) {
+                        self.expected_here("`<`");
+                        break;
+                    }
+                    let type_args = self.parse_type_args();
+                    if !self.expect(&TokenKind::LParen, "`(`") {
+                        break;
+                    }
+                    let args = self.parse_comma_list(&TokenKind::RParen, "`)`", Self::parse_expr);
+                    ExprKind::Call {
+                        callee: expr,
+                        type_args,
+                        args,
+                    }
+                }
+                TokenKind::Dot => {
+                    self.bump();
+                    match self.expect_ident() {
+                        Some(member) => ExprKind::MemberAccess { expr, member },
+                        None => ExprKind::Error,
+                    }
+                }
+                TokenKind::LBracket => {
+                    self.bump();
+                    let index = self.with_struct_literals(Self::parse_expr);
+                    self.expect(&TokenKind::RBracket, "`]`");
+                    ExprKind::IndexAccess { expr, index }
+                }
+                _ => break,
+            };
+            expr = self.alloc_expr(kind, start.to(self.prev_span));
+        }
+        expr
+    }
+
+    fn parse_primary(&mut self) -> ExprId {
+        if self.at_closure() {
+            return self.parse_closure();
+        }
+        match self.token.kind {
+            TokenKind::If => return self.parse_if(),
+            TokenKind::While => return self.parse_while(),
+            TokenKind::Loop => return self.parse_loop(),
+            TokenKind::For => return self.parse_for(),
+            TokenKind::Match => return self.parse_match(),
+            TokenKind::Return | TokenKind::Break => return self.parse_jump(),
+            TokenKind::LBrace => return self.with_struct_literals(Self::parse_block),
+            _ => {}
+        }
+
+        if self.at_sync_point() {
+            self.expected_here("expression");
+            return self.alloc_expr(ExprKind::Error, self.token.span);
+        }
+
+        let token = self.bump();
+        let mut suffix = None;
+        let kind = match token.kind {
+            TokenKind::IntegerLit(i, s) => {
+                suffix = s;
+                ExprKind::Literal(Literal::Int(i))
+            }
+            TokenKind::FloatLit(f, s) => {
+                suffix = s;
+                ExprKind::Literal(Literal::Float(f))
+            }
+            TokenKind::StringLit(s) => ExprKind::Literal(Literal::String(s)),
+            TokenKind::BoolLit(b) => ExprKind::Literal(Literal::Bool(b)),
+            TokenKind::Char(c) => ExprKind::Literal(Literal::Char(c)),
+            TokenKind::Null => ExprKind::Literal(Literal::Null),
+            TokenKind::Continue => ExprKind::Continue,
+            TokenKind::Ident(name) => {
+                if self.check(&TokenKind::LBrace)
+                    && !self.no_struct_literal
+                    && !self.at_line_break_end()
+                {
+                    return self.parse_struct_lit(name, token.span);
+                }
+                ExprKind::Identifier(name)
+            }
+            TokenKind::LBracket => {
+                let elems = self.parse_comma_list(&TokenKind::RBracket, "`]`", Self::parse_expr);
+                let span = token.span.to(self.prev_span);
+                return self.alloc_expr(ExprKind::ArrayLit(elems), span);
+            }
+            TokenKind::LParen => {
+                let mut trailing_comma = false;
+                let mut elems = self.parse_comma_list(&TokenKind::RParen, "`)`", |p| {
+                    let elem = p.parse_expr();
+                    trailing_comma = p.check(&TokenKind::Comma);
+                    elem
+                });
+                // `(e)` only groups, `()` and `(e,)` are tuples
+                let kind = match elems.pop() {
+                    Some(inner) if elems.is_empty() && !trailing_comma => ExprKind::Paren(inner),
+                    last => ExprKind::Tuple(elems.into_iter().chain(last).collect()),
+                };
+                let span = token.span.to(self.prev_span);
+                return self.alloc_expr(kind, span);
+            }
+            // Already reported by the lexer
+            TokenKind::Error => ExprKind::Error,
+            kind => {
+                self.expected("expression", &kind, token.span);
+                self.synchronize();
+                ExprKind::Error
+            }
+        };
+        let id = self.alloc_expr(kind, token.span);
+        if let Some(suffix) = suffix {
+            self.annotate_literal(id, suffix, token.span);
+        }
+        id
+    }
+
+    // `{ field: expr, ... }` after the struct's name
+    fn parse_struct_lit(&mut self, name: Symbol, start: Span) -> ExprId {
+        self.bump();
+        let fields = self.parse_comma_list(&TokenKind::RBrace, "`}`", |p| {
+            let span = p.token.span;
+            let field = p.expect_ident()?;
+            let value = if p.eat(&TokenKind::Colon) {
+                p.parse_expr()
+            } else {
+                p.alloc_expr(ExprKind::Identifier(field), span)
+            };
+            Some(((field, value), span))
+        });
+        let fields: Vec<_> = fields.into_iter().flatten().collect();
+        let names: Vec<_> = fields.iter().map(|&((f, _), span)| (f, span)).collect();
+        self.check_duplicates(ErrorCode::DuplicateField, &names);
+
+        let fields = fields.into_iter().map(|(field, _)| field).collect();
+        let kind = ExprKind::StructLit { name, fields };
+        self.alloc_expr(kind, start.to(self.prev_span))
+    }
+
+    // `fn(` in expression position starts a closure rather than an item
+    fn at_closure(&mut self) -> bool {
+        self.check(&TokenKind::Fn) && *self.peek() == TokenKind::LParen
+    }
+
+    // `fn(param: Type, ...) -> Type { ... }`
+    fn parse_closure(&mut self) -> ExprId {
+        let start = self.bump().span;
+        let (params, ret_ty) = self.parse_signature();
+        let body = self.with_struct_literals(Self::parse_block);
+        let kind = ExprKind::Closure {
+            params,
+            ret_ty,
+            body,
+        };
+        self.alloc_expr(kind, start.to(self.prev_span))
+    }
+
+    // `return` or `break`, each with an optional value
+    fn parse_jump(&mut self) -> ExprId {
+        let token = self.bump();
+        let value = if self.at_expr_end() {
+            None
+        } else {
+            Some(self.parse_expr())
+        };
+        let kind = match token.kind {
+            TokenKind::Return => ExprKind::Return(value),
+            _ => ExprKind::Break(value),
+        };
+        self.alloc_expr(kind, token.span.to(self.prev_span))
+    }
+
+    // Tokens that can't start an expression and end the one before them,
+    // or a line break that does
+    fn at_expr_end(&self) -> bool {
+        self.at_line_break_end()
+            || matches!(
+                self.token.kind,
+                TokenKind::Semicolon
+                    | TokenKind::RBrace
+                    | TokenKind::RParen
+                    | TokenKind::RBracket
+                    | TokenKind::Comma
+                    | TokenKind::Eof
+            )
+    }
+
+    // `if cond { ... } else if cond { ... } else { ... }`, usable as a value
+    fn parse_if(&mut self) -> ExprId {
+        let start = self.bump().span;
+        let cond = self.parse_head_expr();
+        let then_branch = self.parse_block();
+        let else_branch = if self.eat(&TokenKind::Else) {
+            if self.check(&TokenKind::If) {
+                Some(self.parse_if())
+            } else {
+                Some(self.parse_block())
+            }
+        } else {
+            None
+        };
+        let kind = ExprKind::If {
+            cond,
+            then_branch,
+            else_branch,
+        };
+        self.alloc_expr(kind, start.to(self.prev_span))
+    }
+
+    // `while cond { ... } else { ... }`, the else block runs when the loop
+    // finishes without hitting a `break`
+    fn parse_while(&mut self) -> ExprId {
+        let start = self.bump().span;
+        let cond = self.parse_head_expr();
+        let body = self.parse_block();
+        let else_branch = if self.eat(&TokenKind::Else) {
+            Some(self.parse_block())
+        } else {
+            None
+        };
+        let kind = ExprKind::While {
+            cond,
+            body,
+            else_branch,
+        };
+        self.alloc_expr(kind, start.to(self.prev_span))
+    }
+
+    fn parse_loop(&mut self) -> ExprId {
+        let start = self.bump().span;
+        let body = self.parse_block();
+        self.alloc_expr(ExprKind::Loop { body }, start.to(self.prev_span))
+    }
+
+    // `for x in iterable { ... } else { ... }`
+    fn parse_for(&mut self) -> ExprId {
+        let start = self.bump().span;
+        // `for in xs`: keep the `in`
+        let binding = if self.check(&TokenKind::In) {
+            self.expected_here("identifier");
+            None
+        } else {
+            self.expect_ident()
+        };
+        if binding.is_none() && !self.check(&TokenKind::In) {
+            // Skip the malformed binding so `in` still lines up.
+            self.bump();
+        }
+        self.expect(&TokenKind::In, "`in`");
+        let iterable = self.parse_head_expr();
+        let body = self.parse_block();
+        let else_branch = if self.eat(&TokenKind::Else) {
+            Some(self.parse_block())
+        } else {
+            None
+        };
+        let kind = match binding {
+            Some(binding) => ExprKind::For {
+                binding,
+                iterable,
+                body,
+                else_branch,
+            },
+            None => ExprKind::Error,
+        };
+        self.alloc_expr(kind, start.to(self.prev_span))
+    }
+
+    // `match target { pattern => expr, ... }`. Arms whose body is a block
+    // don't need the separating comma.
+    fn parse_match(&mut self) -> ExprId {
+        let start = self.bump().span;
+        let target = self.parse_head_expr();
+        if !self.expect(&TokenKind::LBrace, "`{`") {
+            return self.alloc_expr(ExprKind::Error, start.to(self.prev_span));
+        }
+
+        let mut cases = Vec::new();
+        while !self.check(&TokenKind::RBrace) && !self.check(&TokenKind::Eof) {
+            let pattern = self.parse_pattern();
+            self.expect(&TokenKind::FatArrow, "`=>`");
+            let body = self.parse_expr();
+            cases.push(MatchCase { pattern, body });
+
+            if self.eat(&TokenKind::Comma) || self.check(&TokenKind::RBrace) {
+                continue;
+            }
+            if !self.is_block_like(body) {
+                self.expected_here("`,` or `}`");
+            }
+        }
+        self.expect(&TokenKind::RBrace, "`}`");
+
+        let kind = ExprKind::Match { target, cases };
+        self.alloc_expr(kind, start.to(self.prev_span))
+    }
+
+    // `p | p | ...`, or-patterns bind loosest so every alternative is
+    // collected into a single `PatternKind::Or`. Each alternative may start
+    // with a `|` of its own, which allows a leading one and reads `a || b`
+    // as `a | |b`.
+    fn parse_pattern(&mut self) -> PatternId {
+        self.eat_split(&TokenKind::BitOr);
+        let start = self.token.span;
+        let first = self.parse_single_pattern();
+        let mut alternatives = vec![first];
+        while self.eat_split(&TokenKind::BitOr) {
+            self.eat_split(&TokenKind::BitOr);
+            alternatives.push(self.parse_single_pattern());
+        }
+        if alternatives.len() == 1 {
+            return first;
+        }
+        self.alloc_pattern(PatternKind::Or(alternatives), start.to(self.prev_span))
+    }
+
+    // A literal, `_`, an identifier binding, a tuple `(p, q)` or a struct
+    // pattern `Name { field: p, other }`
+    fn parse_single_pattern(&mut self) -> PatternId {
+        let token = self.bump();
+        let pattern = match token.kind {
+            TokenKind::IntegerLit(i, _) => PatternKind::Literal(Literal::Int(i)),
+            TokenKind::FloatLit(f, _) => PatternKind::Literal(Literal::Float(f)),
+            TokenKind::StringLit(s) => PatternKind::Literal(Literal::String(s)),
+            TokenKind::BoolLit(b) => PatternKind::Literal(Literal::Bool(b)),
+            TokenKind::Char(c) => PatternKind::Literal(Literal::Char(c)),
+            TokenKind::Null => PatternKind::Literal(Literal::Null),
+            TokenKind::Ident(Symbol::UNDERSCORE) => PatternKind::Wildcard,
+            TokenKind::Ident(name) => {
+                if self.eat(&TokenKind::LBrace) {
+                    let fields = self
+                        .parse_comma_list(&TokenKind::RBrace, "`}`", |p| p.parse_field_pattern());
+                    PatternKind::Struct {
+                        name,
+                        fields: fields.into_iter().flatten().collect(),
+                    }
+                } else {
+                    PatternKind::Identifier(name)
+                }
+            }
+            TokenKind::LParen => {
+                let mut trailing_comma = false;
+                let elems = self.parse_comma_list(&TokenKind::RParen, "`)`", |p| {
+                    let elem = p.parse_pattern();
+                    trailing_comma = p.check(&TokenKind::Comma);
+                    elem
+                });
+                // `(p)` only groups, `(p,)` is a one element tuple
+                if elems.len() == 1 && !trailing_comma {
+                    return elems[0];
+                }
+                PatternKind::Tuple(elems)
+            }
+            // Already reported by the lexer
+            TokenKind::Error => PatternKind::Wildcard,
+            kind => {
+                self.expected("pattern", &kind, token.span);
+                PatternKind::Wildcard
+            }
+        };
+        self.alloc_pattern(pattern, token.span.to(self.prev_span))
+    }
+
+    fn parse_field_pattern(&mut self) -> Option<FieldPattern> {
+        let name = self.expect_ident()?;
+        let pattern = if self.eat(&TokenKind::Colon) {
+            self.parse_pattern()
+        } else {
+            self.alloc_pattern(PatternKind::Identifier(name), self.prev_span)
+        };
+        Some(FieldPattern { name, pattern })
+    }
+
+    // Statements that aren't expressions. Expression statements are left to
+    // `parse_block`, which needs to see them to find the block's value.
+    fn parse_stmt(&mut self) -> Option<StmtId> {
+        match self.token.kind {
+            TokenKind::Val | TokenKind::Var => Some(self.parse_var_decl()),
+            TokenKind::Defer => Some(self.parse_defer()),
+            _ => None,
+        }
+    }
+
+    // `defer expr;`, the `;` is optional after a block
+    fn parse_defer(&mut self) -> StmtId {
+        let start = self.bump().span;
+        let expr = self.with_newline_ends(Self::parse_expr);
+        if !self.eat_semicolon() && !self.is_block_like(expr) && !self.expect_semicolon() {
+            self.synchronize();
+            self.eat(&TokenKind::Semicolon);
+        }
+        self.alloc_stmt(StmtKind::Defer(expr), start.to(self.prev_span))
+    }
+
+    // `val name: Type = expr;` or `var name = undefined;`
+    fn parse_var_decl(&mut self) -> StmtId {
+        let start = self.bump();
+        let is_mutable = start.kind == TokenKind::Var;
+        let name = self.expect_ident();
+        let ty = if self.eat(&TokenKind::Colon) {
+            Some(self.parse_type())
+        } else {
+            None
+        };
+        let init = if self.eat(&TokenKind::Assign) {
+            if self.eat(&TokenKind::Undefined) {
+                VarInit::Undefined
+            } else {
+                VarInit::Expr(self.with_newline_ends(Self::parse_expr))
+            }
+        } else {
+            let keyword = if is_mutable { "var" } else { "val" };
+            let mut diag = ErrorCode::MissingInitializer.diagnostic(self.token.span, &[keyword]);
+            if is_mutable {
+                diag = diag.with_note("use `= undefined` to leave it unset");
+            }
+            self.ctx().emit(diag);
+            VarInit::Undefined
+        };
+        if !self.expect_semicolon() {
+            self.synchronize();
+            self.eat(&TokenKind::Semicolon);
+        }
+
+        let span = start.span.to(self.prev_span);
+        let kind = match name {
+            Some(name) => StmtKind::VarDecl {
+                is_mutable,
+                name,
+                ty,
+                init,
+            },
+            None => StmtKind::Expr(self.alloc_expr(ExprKind::Error, span)),
+        };
+        self.alloc_stmt(kind, span)
+    }
+
+    // `{ stmt; stmt; expr }`. An expression not followed by `;` right before
+    // the closing brace is the value of the block.
+    fn parse_block(&mut self) -> ExprId {
+        let start = self.token.span;
+        if !self.expect(&TokenKind::LBrace, "`{`") {
+            return self.alloc_expr(ExprKind::Error, start);
+        }
+
+        let mut stmts = Vec::new();
+        let mut yield_expr = None;
+        while !self.check(&TokenKind::RBrace) && !self.check(&TokenKind::Eof) {
+            // An item here most likely means the block is missing its `}`
+            if (self.at_item_start() && !self.at_closure()) || self.ctx().should_abort() {
+                break;
+            }
+            let stmt_start = self.token.span;
+            if let Some(stmt) = self.parse_stmt() {
+                stmts.push(stmt);
+                continue;
+            }
+            let expr = self.with_newline_ends(Self::parse_expr);
+            let span = self.expr_span(expr);
+            if self.eat(&TokenKind::Semicolon) {
+                stmts.push(self.alloc_stmt(StmtKind::Expr(expr), span.to(self.prev_span)));
+            } else if self.check(&TokenKind::RBrace) {
+                yield_expr = Some(expr);
+            } else if self.eat_semicolon() {
+                stmts.push(self.alloc_stmt(StmtKind::Expr(expr), span));
+            } else {
+                if !self.is_block_like(expr) {
+                    // Broken expressions have already been reported
+                    if !matches!(self.ctx().ast.exprs[expr].kind, ExprKind::Error) {
+                        self.expected_here("`;` or `}`");
+                    }
+                    self.synchronize();
+                    self.eat(&TokenKind::Semicolon);
+                }
+                stmts.push(self.alloc_stmt(StmtKind::Expr(expr), span));
+            }
+            // Always make progress, even on a token nothing knows how to start
+            if self.token.span == stmt_start {
+                self.bump();
+            }
+        }
+        self.expect(&TokenKind::RBrace, "`}`");
+
+        let kind = ExprKind::Block { stmts, yield_expr };
+        self.alloc_expr(kind, start.to(self.prev_span))
+    }
+
+    // Expressions ending in a block don't need a `;` to be used as statements
+    fn is_block_like(&mut self, id: ExprId) -> bool {
+        matches!(
+            self.ctx().ast.exprs[id].kind,
+            ExprKind::Block { .. }
+                | ExprKind::If { .. }
+                | ExprKind::While { .. }
+                | ExprKind::Loop { .. }
+                | ExprKind::For { .. }
+                | ExprKind::Match { .. }
+        )
+    }
+
+    // Records the type written as a literal suffix (`255u8`) on the
+    // expression so later passes don't need to re-lex it
+    fn annotate_literal(&mut self, id: ExprId, suffix: LitSuffix, span: Span) {
+        let ty = self.alloc_type(TypeSpecKind::Named(suffix.symbol()), span);
+        self.ctx().ast.exprs[id].ty = Some(ty);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Spanned;
+
+    // Renders an expression tree with explicit parentheses
+    fn show(ctx: &CompilerCtx, id: ExprId) -> String {
+        match &ctx.ast.exprs[id].kind {
+            ExprKind::Literal(Literal::Int(i)) => i.to_string(),
+            ExprKind::Literal(lit) => format!("{:?}", lit),
+            ExprKind::Paren(inner) => format!("[{}]", show(ctx, *inner)),
+            ExprKind::Identifier(sym) => ctx.symbol_interner.resolve(*sym).to_string(),
+            ExprKind::Binary { lhs, op, rhs } => {
+                format!("({:?} {} {})", op, show(ctx, *lhs), show(ctx, *rhs))
+            }
+            ExprKind::Pipeline { lhs, rhs } => {
+                format!("(|> {} {})", show(ctx, *lhs), show(ctx, *rhs))
+            }
+            ExprKind::Unary { op, operand } => format!("({:?} {})", op, show(ctx, *operand)),
+            ExprKind::Call {
+                callee,
+                type_args,
+                args,
+            } => {
+                let type_args: String = type_args
+                    .iter()
+                    .map(|t| format!(" <{}>", show_type(ctx, *t)))
+                    .collect();
+                let args: Vec<_> = args.iter().map(|a| show(ctx, *a)).collect();
+                format!(
+                    "(call {}{} [{}])",
+                    show(ctx, *callee),
+                    type_args,
+                    args.join(" ")
+                )
+            }
+            ExprKind::MemberAccess { expr, member } => format!(
+                "(. {} {})",
+                show(ctx, *expr),
+                ctx.symbol_interner.resolve(*member)
+            ),
+            ExprKind::IndexAccess { expr, index } => {
+                format!("(index {} {})", show(ctx, *expr), show(ctx, *index))
+            }
+            ExprKind::Tuple(elems) => {
+                let elems: String = elems
+                    .iter()
+                    .map(|e| format!(" {}", show(ctx, *e)))
+                    .collect();
+                format!("(tuple{})", elems)
+            }
+            ExprKind::ArrayLit(elems) => {
+                let elems: String = elems
+                    .iter()
+                    .map(|e| format!(" {}", show(ctx, *e)))
+                    .collect();
+                format!("(array{})", elems)
+            }
+            ExprKind::StructLit { name, fields } => {
+                let fields: Vec<_> = fields
+                    .iter()
+                    .map(|(f, e)| format!("{}: {}", ctx.symbol_interner.resolve(*f), show(ctx, *e)))
+                    .collect();
+                let name = ctx.symbol_interner.resolve(*name);
+                format!("({} {{{}}})", name, fields.join(", "))
+            }
+            ExprKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => match else_branch {
+                Some(e) => format!(
+                    "(if {} {} {})",
+                    show(ctx, *cond),
+                    show(ctx, *then_branch),
+                    show(ctx, *e)
+                ),
+                None => format!("(if {} {})", show(ctx, *cond), show(ctx, *then_branch)),
+            },
+            ExprKind::Block { stmts, yield_expr } => {
+                let mut parts: Vec<String> = stmts
+                    .iter()
+                    .map(|s| match &ctx.ast.stmts[*s].kind {
+                        StmtKind::Expr(e) => show(ctx, *e),
+                        StmtKind::Defer(e) => format!("(defer {})", show(ctx, *e)),
+                        StmtKind::VarDecl {
+                            is_mutable,
+                            name,
+                            ty,
+                            init,
+                        } => {
+                            let mut s = format!(
+                                "({} {}",
+                                if *is_mutable { "var" } else { "val" },
+                                ctx.symbol_interner.resolve(*name)
+                            );
+                            if let Some(ty) = ty {
+                                s += &format!(": {}", show_type(ctx, *ty));
+                            }
+                            match init {
+                                VarInit::Expr(e) => s += &format!(" = {})", show(ctx, *e)),
+                                VarInit::Undefined => s += " = undefined)",
+                            }
+                            s
+                        }
+                    })
+                    .collect();
+                if let Some(e) = yield_expr {
+                    parts.push(show(ctx, *e));
+                }
+                format!("{{{}}}", parts.join("; "))
+            }
+            ExprKind::While {
+                cond,
+                body,
+                else_branch,
+            } => match else_branch {
+                Some(e) => format!(
+                    "(while {} {} {})",
+                    show(ctx, *cond),
+                    show(ctx, *body),
+                    show(ctx, *e)
+                ),
+                None => format!("(while {} {})", show(ctx, *cond), show(ctx, *body)),
+            },
+            ExprKind::Loop { body } => format!("(loop {})", show(ctx, *body)),
+            ExprKind::Match { target, cases } => {
+                let mut s = format!("(match {}", show(ctx, *target));
+                for case in cases {
+                    s += &format!(
+                        " [{} => {}]",
+                        show_pattern(ctx, case.pattern),
+                        show(ctx, case.body)
+                    );
+                }
+                s + ")"
+            }
+            ExprKind::For {
+                binding,
+                iterable,
+                body,
+                else_branch,
+            } => {
+                let mut s = format!(
+                    "(for {} {} {}",
+                    ctx.symbol_interner.resolve(*binding),
+                    show(ctx, *iterable),
+                    show(ctx, *body)
+                );
+                if let Some(e) = else_branch {
+                    s += &format!(" {}", show(ctx, *e));
+                }
+                s + ")"
+            }
+            ExprKind::Assign { target, op, value } => {
+                format!("(= {:?} {} {})", op, show(ctx, *target), show(ctx, *value))
+            }
+            ExprKind::Cast {
+                target,
+                target_type,
+            } => format!(
+                "(as {} {})",
+                show(ctx, *target),
+                show_type(ctx, *target_type)
+            ),
+            ExprKind::Break(None) => "break".to_string(),
+            ExprKind::Break(Some(e)) => format!("(break {})", show(ctx, *e)),
+            ExprKind::Return(None) => "return".to_string(),
+            ExprKind::Return(Some(e)) => format!("(return {})", show(ctx, *e)),
+            ExprKind::Continue => "continue".to_string(),
+            ExprKind::Closure {
+                params,
+                ret_ty,
+                body,
+            } => format!(
+                "(closure {} {})",
+                show_params(ctx, params, *ret_ty),
+                show(ctx, *body)
+            ),
+            ExprKind::Error => "<error>".to_string(),
+        }
+    }
+
+    fn parse(src: &str) -> String {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let expr = Parser::new(src, &mut ctx).parse_expr();
+        assert!(!ctx.has_errors(), "{:?}", ctx.diagnostics);
+        show(&ctx, expr)
+    }
+
+    fn show_pattern(ctx: &CompilerCtx, id: PatternId) -> String {
+        let list = |ids: &[PatternId], sep: &str| {
+            let parts: Vec<_> = ids.iter().map(|p| show_pattern(ctx, *p)).collect();
+            parts.join(sep)
+        };
+        match &ctx.ast.patterns[id].kind {
+            PatternKind::Literal(Literal::Int(i)) => i.to_string(),
+            PatternKind::Literal(lit) => format!("{:?}", lit),
+            PatternKind::Identifier(sym) => ctx.symbol_interner.resolve(*sym).to_string(),
+            PatternKind::Wildcard => "_".to_string(),
+            PatternKind::Tuple(elems) => format!("(tuple {})", list(elems, " ")),
+            PatternKind::Or(alternatives) => format!("(or {})", list(alternatives, " ")),
+            PatternKind::Struct { name, fields } => {
+                let fields: Vec<_> = fields
+                    .iter()
+                    .map(|f| {
+                        let name = ctx.symbol_interner.resolve(f.name);
+                        format!("{}: {}", name, show_pattern(ctx, f.pattern))
+                    })
+                    .collect();
+                let name = ctx.symbol_interner.resolve(*name);
+                format!("{} {{{}}}", name, fields.join(", "))
+            }
+        }
+    }
+
+    #[test]
+    fn test_precedence() {
+        assert_eq!(parse("1 + 2 * 3"), "(Add 1 (Mul 2 3))");
+        assert_eq!(parse("1 * 2 + 3"), "(Add (Mul 1 2) 3)");
+        assert_eq!(
+            parse("a || b && c == d < e + f * g"),
+            "(Or a (And b (Eq c (Lt d (Add e (Mul f g))))))"
+        );
+        assert_eq!(parse("x |> f || g"), "(|> x (Or f g))");
+    }
+
+    #[test]
+    fn test_left_associativity() {
+        assert_eq!(parse("1 - 2 - 3"), "(Sub (Sub 1 2) 3)");
+        assert_eq!(parse("a / b % c"), "(Mod (Div a b) c)");
+    }
+
+    #[test]
+    fn test_operator_table() {
+        let ops = [
+            BinaryOp::Mul,
+            BinaryOp::Add,
+            BinaryOp::Eq,
+            BinaryOp::And,
+            BinaryOp::Or,
+        ];
+        for pair in ops.windows(2) {
+            assert!(
+                pair[0].precedence() > pair[1].precedence(),
+                "{} <= {}",
+                pair[0],
+                pair[1]
+            );
+        }
+        assert!(BinaryOp::Or.precedence() > PREC_PIPELINE);
+        assert!(!BinaryOp::Sub.is_right_assoc());
+
+        let text: Vec<_> = ops.iter().map(|op| op.to_string()).collect();
+        assert_eq!(text, ["*", "+", "==", "&&", "||"]);
+        assert_eq!(UnaryOp::AddressOfMut.to_string(), "&mut");
+        assert_eq!(AssignOp::Add.to_string(), "+=");
+    }
+
+    #[test]
+    fn test_array_and_struct_literals() {
+        assert_eq!(parse("[1, 2 + 3, [],]"), "(array 1 (Add 2 3) (array))");
+        assert_eq!(parse("[a][0]"), "(index (array a) 0)");
+        assert_eq!(
+            parse("Point { x: 1, y: -2 }.x"),
+            "(. (Point {x: 1, y: (Neg 2)}) x)"
+        );
+        // Shorthand fields
+        assert_eq!(parse("P { x, y: y + 1 }"), "(P {x: x, y: (Add y 1)})");
+        assert_eq!(parse("Empty {}"), "(Empty {})");
+        assert_eq!(
+            parse("Line { a: P { x }, b: [P {}] }"),
+            "(Line {a: (P {x: x}), b: (array (P {}))})"
+        );
+
+        let mut ctx = CompilerCtx::new("test", 16);
+        let expr = Parser::new("P { x: 1 }", &mut ctx).parse_expr();
+        assert_eq!(ctx.ast.exprs[expr].span, Span::new(0, 10));
+    }
+
+    #[test]
+    fn test_tuples() {
+        assert_eq!(parse("(a)"), "[a]");
+        assert_eq!(parse("()"), "(tuple)");
+        assert_eq!(parse("(a,)"), "(tuple a)");
+        assert_eq!(
+            parse("(a, b + 1, (c, d))"),
+            "(tuple a (Add b 1) (tuple c d))"
+        );
+        assert_eq!(parse("(1, 2,) == t"), "(Eq (tuple 1 2) t)");
+        assert_eq!(parse("f((), (x))"), "(call f [(tuple) [x]])");
+
+        assert_eq!(parse_ty("(i32)"), "[i32]");
+        assert_eq!(parse_ty("()"), "(tuple)");
+        assert_eq!(parse_ty("(i32,)"), "(tuple i32)");
+        assert_eq!(
+            parse_ty("(i32, *u8, (bool, f32))"),
+            "(tuple i32 (ptr u8) (tuple bool f32))"
+        );
+        assert_eq!(parse_ty("fn() -> (i32, i32)"), "(fn [] (tuple i32 i32))");
+    }
+
+    #[test]
+    fn test_generic_args() {
+        assert_eq!(parse_ty("Vec<i32>"), "(generic Vec i32)");
+        assert_eq!(
+            parse_ty("Map<str, *Vec<u8>>?"),
+            "(opt (generic Map str (ptr (generic Vec u8))))"
+        );
+        // `>>` closes two lists, `>>>` three
+        assert_eq!(
+            parse_ty("A<B<C<i32>>>"),
+            "(generic A (generic B (generic C i32)))"
+        );
+        assert_eq!(parse_ty("Box<()>"), "(generic Box (tuple))");
+
+        assert_eq!(parse("f::<i32>(x)"), "(call f <i32> [x])");
+        assert_eq!(
+            parse("a.map::<Vec<u8>, bool>()"),
+            "(call (. a map) <(generic Vec u8)> <bool> [])"
+        );
+        // Without `::` a `<` is a comparison
+        assert_eq!(parse("a < b > c"), "(Gt (Lt a b) c)");
+        assert_eq!(parse("x as i32 < y"), "(Lt (as x i32) y)");
+        assert_eq!(
+            parse("x as (Vec<i32>) < y"),
+            "(Lt (as x [(generic Vec i32)]) y)"
+        );
+    }
+
+    #[test]
+    fn test_generic_args_errors() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        Parser::new("val x: Vec<i32 = 1;", &mut ctx).parse_stmt();
+        assert_eq!(ctx.diagnostics.len(), 1);
+        assert_eq!(ctx.diagnostics[0].span, Span::new(15, 16));
+
+        let mut ctx = CompilerCtx::new("test", 16);
+        Parser::new("f::x", &mut ctx).parse_expr();
+        assert_eq!(
+            ctx.diagnostics[0].message,
+            "expected `<`, found Ident(\"x\")"
+        );
+
+        let mut ctx = CompilerCtx::new("test", 16);
+        Parser::new("f::<T>", &mut ctx).parse_expr();
+        assert_eq!(ctx.diagnostics[0].message, "expected `(`, found Eof");
+    }
+
+    #[test]
+    fn test_closures() {
+        assert_eq!(
+            parse("fn(x: i32) -> i32 { x + 1 }"),
+            "(closure (x: i32) -> i32 {(Add x 1)})"
+        );
+        assert_eq!(parse("fn() { }"), "(closure () {})");
+        assert_eq!(
+            parse("map(xs, fn(a: i32, b: *u8) { g(a); })"),
+            "(call map [xs (closure (a: i32, b: (ptr u8)) {(call g [a])})])"
+        );
+        assert_eq!(
+            parse("fn() -> bool { true }()"),
+            "(call (closure () -> bool {Bool(true)}) [])"
+        );
+        // At the start of a statement `fn(` is a closure, `fn name` an item
+        assert_eq!(parse("{ fn() { }; 1 }"), "{(closure () {}); 1}");
+        // Doc comments in between are skipped like everywhere else
+        assert_eq!(parse("{ fn /// x\n() { }; 1 }"), "{(closure () {}); 1}");
+
+        let mut ctx = CompilerCtx::new("test", 16);
+        Parser::new("fn(a: i32, a: i32) { }", &mut ctx).parse_expr();
+        assert_eq!(ctx.diagnostics.len(), 1);
+        assert_eq!(ctx.diagnostics[0].code, Some("E0013"));
+    }
+
+    #[test]
+    fn test_struct_literal_in_head() {
+        // `x {` after `if`, `while`, `for` and `match` starts the body
+        assert_eq!(parse("if x { 1 }"), "(if x {1})");
+        assert_eq!(parse("while a == b { }"), "(while (Eq a b) {})");
+        assert_eq!(parse("for i in xs { }"), "(for i xs {})");
+        assert_eq!(parse("match p { _ => 1 }"), "(match p [_ => 1])");
+        // Unless wrapped in a delimiter
+        assert_eq!(
+            parse("if p == (P { x: 1 }) { }"),
+            "(if (Eq p [(P {x: 1})]) {})"
+        );
+        assert_eq!(
+            parse("for i in f(P {}) { }"),
+            "(for i (call f [(P {})]) {})"
+        );
+        assert_eq!(
+            parse("if xs[P { x }.x] { }"),
+            "(if (index xs (. (P {x: x}) x)) {})"
+        );
+        // And the body itself is back to normal
+        assert_eq!(parse("if c { P { x: 1 } }"), "(if c {(P {x: 1})})");
+    }
+
+    #[test]
+    fn test_struct_literal_duplicate_field() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        Parser::new("P { x: 1, y, x: 2 }", &mut ctx).parse_expr();
+        assert_eq!(ctx.diagnostics.len(), 1);
+        assert_eq!(ctx.diagnostics[0].message, "duplicate field `x`");
+        assert_eq!(ctx.diagnostics[0].span, Span::new(13, 14));
+    }
+
+    #[test]
+    fn test_unary_binds_tighter() {
+        assert_eq!(parse("-a * b"), "(Mul (Neg a) b)");
+        assert_eq!(parse("!a && *b"), "(And (Not a) (Deref b))");
+        assert_eq!(parse("&a - -1"), "(Sub (AddressOf a) (Neg 1))");
+    }
+
+    #[test]
+    fn test_address_of() {
+        assert_eq!(parse("&mut a.b"), "(AddressOfMut (. a b))");
+        assert_eq!(parse("&&a"), "(AddressOf (AddressOf a))");
+        assert_eq!(parse("&&mut a"), "(AddressOf (AddressOfMut a))");
+        assert_eq!(parse("&&&a"), "(AddressOf (AddressOf (AddressOf a)))");
+        // Only split in prefix position
+        assert_eq!(parse("a && &&b"), "(And a (AddressOf (AddressOf b)))");
+
+        let mut ctx = CompilerCtx::new("test", 16);
+        let expr = Parser::new("&&x", &mut ctx).parse_expr();
+        let ExprKind::Unary { operand, .. } = ctx.ast.exprs[expr].kind else {
+            panic!("expected unary");
+        };
+        assert_eq!(ctx.ast.exprs[expr].span, Span::new(0, 3));
+        assert_eq!(ctx.ast.exprs[operand].span, Span::new(1, 3));
+    }
+
+    #[test]
+    fn test_binary_spans() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let expr = Parser::new("foo + bar * 2", &mut ctx).parse_expr();
+        assert_eq!(ctx.ast.exprs[expr].span, Span::new(0, 13));
+        let ExprKind::Binary { rhs, .. } = ctx.ast.exprs[expr].kind else {
+            panic!("expected binary expression");
+        };
+        assert_eq!(ctx.ast.exprs[rhs].span, Span::new(6, 13));
+    }
+
+    #[test]
+    fn test_literals() {
+        assert_eq!(parse("1.5"), "Float(1.5)");
+        assert_eq!(parse("\"hi\""), "String(\"hi\")");
+        assert_eq!(parse("true"), "Bool(true)");
+        assert_eq!(parse("'c'"), "Char('c')");
+        assert_eq!(parse("null"), "Null");
+    }
+
+    #[test]
+    fn test_parens() {
+        assert_eq!(parse("(1 + 2) * 3"), "(Mul [(Add 1 2)] 3)");
+        assert_eq!(parse("((x))"), "[[x]]");
+
+        let mut ctx = CompilerCtx::new("test", 16);
+        let expr = Parser::new(" (a + b) ", &mut ctx).parse_expr();
+        assert_eq!(ctx.ast.exprs[expr].span, Span::new(1, 8));
+    }
+
+    #[test]
+    fn test_literal_suffix_type() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let expr = Parser::new("255u8", &mut ctx).parse_expr();
+        let ty = ctx
+            .ast
+            .exprs
+            .get(expr)
+            .ty
+            .expect("suffix should set the type");
+        assert!(matches!(
+            ctx.ast.type_specs[ty].kind,
+            TypeSpecKind::Named(sym) if sym == Symbol::U8
+        ));
+    }
+
+    #[test]
+    fn test_unexpected_token() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let expr = Parser::new("1 + )", &mut ctx).parse_expr();
+        assert_eq!(show(&ctx, expr), "(Add 1 <error>)");
+        assert_eq!(ctx.diagnostics.len(), 1);
+        assert_eq!(ctx.diagnostics[0].span, Span::new(4, 5));
+
+        let mut ctx = CompilerCtx::new("test", 16);
+        Parser::new("(1", &mut ctx).parse_expr();
+        assert_eq!(ctx.diagnostics.len(), 1);
+    }
+
+    #[test]
+    fn test_postfix_chains() {
+        assert_eq!(parse("foo.bar(1)[2]"), "(index (call (. foo bar) [1]) 2)");
+        assert_eq!(parse("f()"), "(call f [])");
+        assert_eq!(parse("f(a, b + 1,)"), "(call f [a (Add b 1)])");
+        assert_eq!(parse("a.b.c"), "(. (. a b) c)");
+        assert_eq!(parse("-a[0]"), "(Neg (index a 0))");
+        assert_eq!(parse("f(x)(y)"), "(call (call f [x]) [y])");
+    }
+
+    #[test]
+    fn test_postfix_spans() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let expr = Parser::new("foo.bar(1, 2) + 1", &mut ctx).parse_expr();
+        let ExprKind::Binary { lhs, .. } = ctx.ast.exprs[expr].kind else {
+            panic!("expected binary expression");
+        };
+        assert_eq!(ctx.ast.exprs[lhs].span, Span::new(0, 13));
+    }
+
+    #[test]
+    fn test_if_else() {
+        assert_eq!(parse("if c { 1 }"), "(if c {1})");
+        assert_eq!(parse("if a < b { a } else { b }"), "(if (Lt a b) {a} {b})");
+        assert_eq!(
+            parse("if a { 1 } else if b { 2 } else { 3 }"),
+            "(if a {1} (if b {2} {3}))"
+        );
+        assert_eq!(parse("if c { 1 } else { 2 } + 1"), "(Add (if c {1} {2}) 1)");
+    }
+
+    #[test]
+    fn test_if_missing_block() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        Parser::new("if c 1", &mut ctx).parse_expr();
+        assert_eq!(ctx.diagnostics.len(), 1);
+    }
+
+    #[test]
+    fn test_blocks() {
+        assert_eq!(parse("{}"), "{}");
+        assert_eq!(parse("{ a; b; a + b }"), "{a; b; (Add a b)}");
+        assert_eq!(parse("{ f(); }"), "{(call f [])}");
+        assert_eq!(parse("{ { 1 } }"), "{{1}}");
+        assert_eq!(parse("{ if c { x } { y } 2 }"), "{(if c {x}); {y}; 2}");
+    }
+
+    #[test]
+    fn test_block_trailing_semicolon_is_statement() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let expr = Parser::new("{ 1; }", &mut ctx).parse_expr();
+        let ExprKind::Block { stmts, yield_expr } = &ctx.ast.exprs[expr].kind else {
+            panic!("expected block");
+        };
+        assert_eq!(stmts.len(), 1);
+        assert!(yield_expr.is_none());
+        assert_eq!(ctx.ast.stmts[stmts[0]].span, Span::new(2, 4));
+    }
+
+    #[test]
+    fn test_block_missing_semicolon() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        Parser::new("{ a b }", &mut ctx).parse_expr();
+        assert_eq!(ctx.diagnostics.len(), 1);
+        assert_eq!(ctx.diagnostics[0].span, Span::new(4, 5));
+    }
+
+    #[test]
+    fn test_while_and_loop() {
+        assert_eq!(
+            parse("while i < n { f(i); }"),
+            "(while (Lt i n) {(call f [i])})"
+        );
+        assert_eq!(
+            parse("while c { break; } else { 1 }"),
+            "(while c {break} {1})"
+        );
+        assert_eq!(parse("loop { }"), "(loop {})");
+        assert_eq!(
+            parse("loop { if c { continue; } break; }"),
+            "(loop {(if c {continue}); break})"
+        );
+        assert_eq!(parse("{ loop {} x }"), "{(loop {}); x}");
+    }
+
+    #[test]
+    fn test_for() {
+        assert_eq!(parse("for x in xs { f(x); }"), "(for x xs {(call f [x])})");
+        assert_eq!(
+            parse("for i in items.iter() { } else { 0 }"),
+            "(for i (call (. items iter) []) {} {0})"
+        );
+    }
+
+    #[test]
+    fn test_for_bad_binding() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let expr = Parser::new("for 1 in xs { }", &mut ctx).parse_expr();
+        assert!(matches!(ctx.ast.exprs[expr].kind, ExprKind::Error));
+        assert_eq!(ctx.diagnostics.len(), 1);
+        assert_eq!(ctx.diagnostics[0].span, Span::new(4, 5));
+    }
+
+    #[test]
+    fn test_match() {
+        assert_eq!(
+            parse("match x { 1 => a, n => { b } _ => c, }"),
+            "(match x [1 => a] [n => {b}] [_ => c])"
+        );
+        assert_eq!(
+            parse("match c { 'a' => true, \"s\" => null }"),
+            "(match c [Char('a') => Bool(true)] [String(\"s\") => Null])"
+        );
+        assert_eq!(parse("match x {}"), "(match x)");
+    }
+
+    #[test]
+    fn test_match_missing_comma() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        Parser::new("match x { 1 => a 2 => b }", &mut ctx).parse_expr();
+        assert_eq!(ctx.diagnostics.len(), 1);
+        assert_eq!(ctx.diagnostics[0].span, Span::new(17, 18));
+    }
+
+    #[test]
+    fn test_match_bad_pattern() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        Parser::new("match x { + => a }", &mut ctx).parse_expr();
+        assert_eq!(ctx.diagnostics.len(), 1);
+        assert_eq!(ctx.diagnostics[0].message, "expected pattern, found Plus");
+    }
+
+    #[test]
+    fn test_or_patterns() {
+        assert_eq!(
+            parse("match x { 1 | 2 | 3 => a, _ => b }"),
+            "(match x [(or 1 2 3) => a] [_ => b])"
+        );
+        // A leading `|`, and `||` split into two
+        assert_eq!(
+            parse("match x { | 1 | 2 => a, 3 || 4 => b, (5 ||6, y) => c }"),
+            "(match x [(or 1 2) => a] [(or 3 4) => b] [(tuple (or 5 6) y) => c])"
+        );
+
+        let mut ctx = CompilerCtx::new("test", 16);
+        let expr = Parser::new("match x { a ||b => 1 }", &mut ctx).parse_expr();
+        let ExprKind::Match { cases, .. } = &ctx.ast.exprs[expr].kind else {
+            panic!("expected match");
+        };
+        let PatternKind::Or(alternatives) = &ctx.ast.patterns[cases[0].pattern].kind else {
+            panic!("expected or-pattern");
+        };
+        assert_eq!(ctx.ast.patterns[cases[0].pattern].span, Span::new(10, 15));
+        assert_eq!(ctx.ast.patterns[alternatives[1]].span, Span::new(14, 15));
+    }
+
+    #[test]
+    fn test_eat_split() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let mut parser = Parser::new(">> >= || >", &mut ctx);
+        assert!(!parser.eat_split(&TokenKind::BitOr));
+        assert!(parser.eat_split(&TokenKind::Gt));
+        assert_eq!(parser.prev_span, Span::new(0, 1));
+        assert!(parser.eat_split(&TokenKind::Gt));
+        assert_eq!(parser.prev_span, Span::new(1, 2));
+        assert!(parser.eat_split(&TokenKind::Gt));
+        assert_eq!(parser.token.kind, TokenKind::Assign);
+        assert_eq!(parser.token.span, Span::new(4, 5));
+        assert!(parser.eat_split(&TokenKind::Assign));
+        // The first half has to match
+        assert!(!parser.eat_split(&TokenKind::Gt));
+        assert!(parser.eat_split(&TokenKind::BitOr));
+        assert!(parser.eat_split(&TokenKind::BitOr));
+        assert!(parser.eat_split(&TokenKind::Gt));
+        assert_eq!(parser.token.kind, TokenKind::Eof);
+    }
+
+    #[test]
+    fn test_tuple_patterns() {
+        assert_eq!(
+            parse("match x { (a, _) => a, (1,) => b, (n) => n, () => c }"),
+            "(match x [(tuple a _) => a] [(tuple 1) => b] [n => n] [(tuple ) => c])"
+        );
+        assert_eq!(
+            parse("match x { (1 | 2, y) => y }"),
+            "(match x [(tuple (or 1 2) y) => y])"
+        );
+    }
+
+    #[test]
+    fn test_struct_patterns() {
+        assert_eq!(
+            parse("match p { Point { x: 0, y } => y, Point {} => 1 }"),
+            "(match p [Point {x: 0, y: y} => y] [Point {} => 1])"
+        );
+    }
+
+    fn show_type(ctx: &CompilerCtx, id: TypeSpecId) -> String {
+        match &ctx.ast.type_specs[id].kind {
+            TypeSpecKind::Named(sym) => ctx.symbol_interner.resolve(*sym).to_string(),
+            TypeSpecKind::Pointer(inner) => format!("(ptr {})", show_type(ctx, *inner)),
+            TypeSpecKind::Reference(inner) => format!("(ref {})", show_type(ctx, *inner)),
+            TypeSpecKind::MutReference(inner) => {
+                format!("(ref mut {})", show_type(ctx, *inner))
+            }
+            TypeSpecKind::Optional(inner) => format!("(opt {})", show_type(ctx, *inner)),
+            TypeSpecKind::Slice(inner) => format!("(slice {})", show_type(ctx, *inner)),
+            TypeSpecKind::Array { size, elem_ty } => {
+                format!("(array {} {})", show(ctx, *size), show_type(ctx, *elem_ty))
+            }
+            TypeSpecKind::Fn { param, return_ty } => {
+                let params: Vec<_> = param.iter().map(|p| show_type(ctx, *p)).collect();
+                format!("(fn [{}] {})", params.join(" "), show_type(ctx, *return_ty))
+            }
+            TypeSpecKind::Paren(inner) => format!("[{}]", show_type(ctx, *inner)),
+            TypeSpecKind::Tuple(elems) => {
+                let elems: String = elems
+                    .iter()
+                    .map(|t| format!(" {}", show_type(ctx, *t)))
+                    .collect();
+                format!("(tuple{})", elems)
+            }
+            TypeSpecKind::Generic { base, args } => {
+                let args: String = args
+                    .iter()
+                    .map(|t| format!(" {}", show_type(ctx, *t)))
+                    .collect();
+                let base = ctx.symbol_interner.resolve(*base);
+                format!("(generic {}{})", base, args)
+            }
+            TypeSpecKind::Error => "<error>".to_string(),
+        }
+    }
+
+    fn show_sig(
+        ctx: &CompilerCtx,
+        name: Symbol,
+        params: &[Param],
+        ret_ty: Option<TypeSpecId>,
+    ) -> String {
+        let name = ctx.symbol_interner.resolve(name);
+        format!("fn {} {}", name, show_params(ctx, params, ret_ty))
+    }
+
+    fn show_params(ctx: &CompilerCtx, params: &[Param], ret_ty: Option<TypeSpecId>) -> String {
+        let params: Vec<_> = params
+            .iter()
+            .map(|p| {
+                let name = ctx.symbol_interner.resolve(p.name);
+                format!("{}: {}", name, show_type(ctx, p.ty))
+            })
+            .collect();
+        let ret = match ret_ty {
+            Some(ty) => format!(" -> {}", show_type(ctx, ty)),
+            None => String::new(),
+        };
+        format!("({}){}", params.join(", "), ret)
+    }
+
+    fn show_item(ctx: &CompilerCtx, id: ItemId) -> String {
+        match &ctx.ast.items[id].kind {
+            ItemKind::FnDecl {
+                name,
+                params,
+                ret_ty,
+                body,
+            } => format!(
+                "({} {})",
+                show_sig(ctx, *name, params, *ret_ty),
+                show(ctx, *body)
+            ),
+            ItemKind::StructDecl { name, fields } => {
+                let fields: Vec<_> = fields
+                    .iter()
+                    .map(|f| {
+                        let name = ctx.symbol_interner.resolve(f.name);
+                        format!("{}: {}", name, show_type(ctx, f.ty))
+                    })
+                    .collect();
+                let name = ctx.symbol_interner.resolve(*name);
+                format!("(struct {} ({}))", name, fields.join(", "))
+            }
+            ItemKind::EnumDecl {
+                name,
+                backing_ty,
+                variants,
+            } => {
+                let variants: Vec<_> = variants
+                    .iter()
+                    .map(|v| {
+                        let name = ctx.symbol_interner.resolve(v.name);
+                        match v.value {
+                            Some(value) => format!("{} = {}", name, value),
+                            None => name.to_string(),
+                        }
+                    })
+                    .collect();
+                let backing = match backing_ty {
+                    Some(ty) => format!(": {}", show_type(ctx, *ty)),
+                    None => String::new(),
+                };
+                let name = ctx.symbol_interner.resolve(*name);
+                format!("(enum {}{} ({}))", name, backing, variants.join(", "))
+            }
+            ItemKind::UnionDecl { name, variants } => {
+                let variants: Vec<_> = variants
+                    .iter()
+                    .map(|v| {
+                        let name = ctx.symbol_interner.resolve(v.name);
+                        match &v.data {
+                            None => name.to_string(),
+                            Some(UnionVariantData::Tuple(types)) => {
+                                let types: Vec<_> =
+                                    types.iter().map(|t| show_type(ctx, *t)).collect();
+                                format!("{}({})", name, types.join(", "))
+                            }
+                            Some(UnionVariantData::Struct(fields)) => {
+                                let fields: Vec<_> = fields
+                                    .iter()
+                                    .map(|f| {
+                                        let field = ctx.symbol_interner.resolve(f.name);
+                                        format!("{}: {}", field, show_type(ctx, f.ty))
+                                    })
+                                    .collect();
+                                format!("{} {{{}}}", name, fields.join(", "))
+                            }
+                        }
+                    })
+                    .collect();
+                let name = ctx.symbol_interner.resolve(*name);
+                format!("(union {} ({}))", name, variants.join(", "))
+            }
+            ItemKind::ImplDecl { self_ty, methods } => {
+                let methods: Vec<_> = methods.iter().map(|m| show_item(ctx, *m)).collect();
+                format!(
+                    "(impl {} [{}])",
+                    show_type(ctx, *self_ty),
+                    methods.join(" ")
+                )
+            }
+            ItemKind::ConstDecl { name, ty, expr } => format!(
+                "(const {}: {} {})",
+                ctx.symbol_interner.resolve(*name),
+                show_type(ctx, *ty),
+                show(ctx, *expr)
+            ),
+            ItemKind::ExternDecl { api, declarations } => {
+                let sigs: Vec<_> = declarations
+                    .iter()
+                    .map(|sig| format!("({})", show_sig(ctx, sig.name, &sig.params, sig.return_ty)))
+                    .collect();
+                format!("(extern {:?} [{}])", api, sigs.join(" "))
+            }
+        }
+    }
+
+    fn parse_item(src: &str) -> String {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let item = Parser::new(src, &mut ctx).parse_item();
+        assert!(!ctx.has_errors(), "{:?}", ctx.diagnostics);
+        show_item(&ctx, item.unwrap())
+    }
+
+    #[test]
+    fn test_fn_decl() {
+        assert_eq!(parse_item("fn main() { }"), "(fn main () {})");
+        assert_eq!(
+            parse_item("fn add(a: i32, b: i32,) -> i32 { a + b }"),
+            "(fn add (a: i32, b: i32) -> i32 {(Add a b)})"
+        );
+    }
+
+    #[test]
+    fn test_fn_decl_span() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let item = Parser::new("  fn f(x: T) {}", &mut ctx)
+            .parse_item()
+            .unwrap();
+        assert_eq!(ctx.ast.items[item].span, Span::new(2, 15));
+    }
+
+    #[test]
+    fn test_fn_decl_errors() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let item = Parser::new("fn (x: i32) {}", &mut ctx).parse_item();
+        assert!(item.is_none());
+        assert_eq!(ctx.diagnostics.len(), 1);
+
+        let mut ctx = CompilerCtx::new("test", 16);
+        Parser::new("fn f(x: 1) {}", &mut ctx).parse_item().unwrap();
+        assert_eq!(ctx.diagnostics.len(), 1);
+        assert_eq!(ctx.diagnostics[0].span, Span::new(8, 9));
+    }
+
+    #[test]
+    fn test_struct_decl() {
+        assert_eq!(parse_item("struct Unit {}"), "(struct Unit ())");
+        assert_eq!(
+            parse_item("struct Point { x: f32, y: f32, }"),
+            "(struct Point (x: f32, y: f32))"
+        );
+        assert_eq!(
+            parse_item("struct Pair {\n    a: i32\n    b: i32\n}"),
+            "(struct Pair (a: i32, b: i32))"
+        );
+
+        // but not just spaces
+        for src in [
+            "struct S { a: i32 b: u8 }",
+            "union U { A { x: i32 y: i32 } }",
+        ] {
+            let mut ctx = CompilerCtx::new("test", 16);
+            Parser::new(src, &mut ctx).parse_item();
+            assert_eq!(ctx.diagnostics.len(), 1, "{}", src);
+            assert!(
+                ctx.diagnostics[0]
+                    .message
+                    .starts_with("expected `,`, found Ident"),
+                "{}",
+                src
+            );
+        }
+    }
+
+    #[test]
+    fn test_struct_duplicate_field() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let item = Parser::new("struct S { a: i32, a: u8 }", &mut ctx).parse_item();
+        assert_eq!(show_item(&ctx, item.unwrap()), "(struct S (a: i32, a: u8))");
+        assert_eq!(ctx.diagnostics.len(), 1);
+        assert_eq!(ctx.diagnostics[0].message, "duplicate field `a`");
+        assert_eq!(
+            ctx.diagnostics[0].labels,
+            [(Span::new(11, 12), "first defined here".to_string())]
+        );
+        assert_eq!(ctx.diagnostics[0].span, Span::new(19, 20));
+    }
+
+    #[test]
+    fn test_keyword_as_name() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let src = "fn loop(match: i32) { val if = 1; } struct S { else: u8 }";
+        let items = Parser::new(src, &mut ctx).parse_program();
+        assert_eq!(items.len(), 2);
+        assert_eq!(show_item(&ctx, items[1]), "(struct S (else: u8))");
+        let got: Vec<_> = ctx
+            .diagnostics
+            .iter()
+            .map(|d| (d.message.as_str(), d.span))
+            .collect();
+        assert_eq!(
+            got,
+            [
+                (
+                    "`loop` is a reserved keyword and cannot be used as a name",
+                    Span::new(3, 7)
+                ),
+                (
+                    "`match` is a reserved keyword and cannot be used as a name",
+                    Span::new(8, 13)
+                ),
+                (
+                    "`if` is a reserved keyword and cannot be used as a name",
+                    Span::new(26, 28)
+                ),
+                (
+                    "`else` is a reserved keyword and cannot be used as a name",
+                    Span::new(47, 51)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_missing_name_before_item_keyword() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let items = Parser::new("struct\nfn f() {}", &mut ctx).parse_program();
+        assert_eq!(items.len(), 1);
+        assert_eq!(ctx.diagnostics[0].message, "expected identifier, found Fn");
+    }
+
+    #[test]
+    fn test_duplicate_variants_and_params() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let src = "enum E { A, B, A = 2 } union U { X(i32), X } fn f(a: i32, b: u8, a: u8) {}";
+        let items = Parser::new(src, &mut ctx).parse_program();
+        assert_eq!(items.len(), 3);
+        let got: Vec<_> = ctx
+            .diagnostics
+            .iter()
+            .map(|d| (d.message.as_str(), d.span, d.labels[0].0))
+            .collect();
+        assert_eq!(
+            got,
+            [
+                ("duplicate variant `A`", Span::new(15, 16), Span::new(9, 10)),
+                (
+                    "duplicate variant `X`",
+                    Span::new(41, 42),
+                    Span::new(33, 34)
+                ),
+                (
+                    "duplicate parameter `a`",
+                    Span::new(65, 66),
+                    Span::new(50, 51)
+                ),
+            ]
+        );
+        assert_eq!(ctx.diagnostics[2].code, Some("E0013"));
+    }
+
+    #[test]
+    fn test_enum_decl() {
+        assert_eq!(
+            parse_item("enum Color: u8 { Red = 1, Green, Blue }"),
+            "(enum Color: u8 (Red = 1, Green, Blue))"
+        );
+        assert_eq!(
+            parse_item("enum Ord { Less = -1, Equal, Greater, }"),
+            "(enum Ord (Less = -1, Equal, Greater))"
+        );
+        assert_eq!(parse_item("enum Never {}"), "(enum Never ())");
+        assert_eq!(
+            parse_item("enum Flags { A = 1 + 2, B = -(4 * 2), C = 1 % 3 * 8 }"),
+            "(enum Flags (A = 3, B = -8, C = 8))"
+        );
+    }
+
+    #[test]
+    fn test_enum_bad_value() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let item = Parser::new("enum E { A = x, B }", &mut ctx).parse_item();
+        assert_eq!(show_item(&ctx, item.unwrap()), "(enum E (A, B))");
+        assert_eq!(ctx.diagnostics.len(), 1);
+        assert_eq!(ctx.diagnostics[0].span, Span::new(13, 14));
+        assert_eq!(ctx.diagnostics[0].code, Some(ErrorCode::NotConstant.code()));
+
+        let mut ctx = CompilerCtx::new("test", 16);
+        let item = Parser::new("enum E { A = 1 / 0, B = 1 2 3, C = 2 }", &mut ctx).parse_item();
+        assert_eq!(show_item(&ctx, item.unwrap()), "(enum E (A, B = 1, C = 2))");
+        assert_eq!(ctx.diagnostics.len(), 2);
+        assert_eq!(
+            ctx.diagnostics[0].code,
+            Some(ErrorCode::DivisionByZero.code())
+        );
+        assert_eq!(ctx.diagnostics[1].span, Span::new(26, 27));
+    }
+
+    #[test]
+    fn test_array_size_errors() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        Parser::new("[4 * N]i32", &mut ctx).parse_type();
+        assert!(ctx.diagnostics.is_empty(), "{:?}", ctx.diagnostics);
+
+        let mut ctx = CompilerCtx::new("test", 16);
+        Parser::new("[8 / (2 - 2)]i32", &mut ctx).parse_type();
+        assert_eq!(ctx.diagnostics.len(), 1);
+        assert_eq!(
+            ctx.diagnostics[0].code,
+            Some(ErrorCode::DivisionByZero.code())
+        );
+        assert_eq!(ctx.diagnostics[0].span, Span::new(1, 12));
+    }
+
+    #[test]
+    fn test_union_decl() {
+        assert_eq!(
+            parse_item("union Shape { A, B(i32, f64), C { x: i32 } }"),
+            "(union Shape (A, B(i32, f64), C {x: i32}))"
+        );
+        assert_eq!(parse_item("union U { Unit(), }"), "(union U (Unit()))");
+    }
+
+    #[test]
+    fn test_union_bad_type() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let item = Parser::new("union U { A(1), B }", &mut ctx).parse_item();
+        assert_eq!(show_item(&ctx, item.unwrap()), "(union U (A(<error>), B))");
+        assert_eq!(ctx.diagnostics.len(), 1);
+    }
+
+    #[test]
+    fn test_impl_decl() {
+        assert_eq!(parse_item("impl Empty {}"), "(impl Empty [])");
+        assert_eq!(
+            parse_item("impl Point { fn a() {} fn b(x: i32) -> i32 { x } }"),
+            "(impl Point [(fn a () {}) (fn b (x: i32) -> i32 {x})])"
+        );
+    }
+
+    #[test]
+    fn test_impl_non_fn_item() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let item = Parser::new("impl P { struct S {} fn f() {} }", &mut ctx).parse_item();
+        assert_eq!(show_item(&ctx, item.unwrap()), "(impl P [(fn f () {})])");
+        assert_eq!(ctx.diagnostics.len(), 1);
+        assert_eq!(ctx.diagnostics[0].span, Span::new(9, 15));
+    }
+
+    #[test]
+    fn test_const_decl() {
+        assert_eq!(
+            parse_item("const MAX: i32 = 10 * 2;"),
+            "(const MAX: i32 (Mul 10 2))"
+        );
+    }
+
+    #[test]
+    fn test_const_without_type() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let item = Parser::new("const MAX = 10;", &mut ctx).parse_item();
+        assert!(item.is_none());
+        assert_eq!(ctx.diagnostics.len(), 1);
+        assert_eq!(
+            ctx.diagnostics[0].message,
+            "const items need a type annotation"
+        );
+        assert_eq!(ctx.diagnostics[0].span, Span::new(10, 11));
+    }
+
+    #[test]
+    fn test_extern_decl() {
+        assert_eq!(
+            parse_item("extern \"C\" { fn puts(s: str) -> i32; fn exit(code: i32); }"),
+            "(extern \"C\" [(fn puts (s: str) -> i32) (fn exit (code: i32))])"
+        );
+        assert_eq!(parse_item("extern \"C\" {}"), "(extern \"C\" [])");
+    }
+
+    fn parse_ty(src: &str) -> String {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let ty = Parser::new(src, &mut ctx).parse_type();
+        assert!(!ctx.has_errors(), "{:?}", ctx.diagnostics);
+        show_type(&ctx, ty)
+    }
+
+    #[test]
+    fn test_types() {
+        assert_eq!(parse_ty("i32"), "i32");
+        assert_eq!(parse_ty("**u8"), "(ptr (ptr u8))");
+        assert_eq!(parse_ty("&Point"), "(ref Point)");
+        assert_eq!(parse_ty("&&Point"), "(ref (ref Point))");
+        assert_eq!(parse_ty("&mut *T"), "(ref mut (ptr T))");
+        assert_eq!(parse_ty("&&mut T"), "(ref (ref mut T))");
+        assert_eq!(parse_ty("*&mut T"), "(ptr (ref mut T))");
+        assert_eq!(parse_ty("?T"), "(opt T)");
+        assert_eq!(parse_ty("T??"), "(opt (opt T))");
+        assert_eq!(parse_ty("*T?"), "(ptr (opt T))");
+        assert_eq!(parse_ty("(*T)?"), "(opt [(ptr T)])");
+        assert_eq!(parse_ty("[]u8"), "(slice u8)");
+        assert_eq!(parse_ty("[4 * N][2]f32"), "(array (Mul 4 N) (array 2 f32))");
+        assert_eq!(parse_ty("fn(i32, *u8) -> bool"), "(fn [i32 (ptr u8)] bool)");
+        assert_eq!(parse_ty("fn()"), "(fn [] void)");
+    }
+
+    #[test]
+    fn test_fn_types_nest() {
+        // The return type extends as far right as possible
+        assert_eq!(
+            parse_ty("fn(i32) -> fn(i32) -> i32"),
+            "(fn [i32] (fn [i32] i32))"
+        );
+        assert_eq!(parse_ty("(fn(i32) -> i32)"), "[(fn [i32] i32)]");
+        assert_eq!(
+            parse_ty("fn((fn() -> i32)) -> *fn()"),
+            "(fn [[(fn [] i32)]] (ptr (fn [] void)))"
+        );
+        // Prefix operators apply to the whole function type
+        assert_eq!(parse_ty("*fn() -> i32"), "(ptr (fn [] i32))");
+        assert_eq!(parse_ty("?fn() -> i32?"), "(opt (fn [] (opt i32)))");
+        assert_eq!(parse_ty("(fn() -> i32)?"), "(opt [(fn [] i32)])");
+    }
+
+    #[test]
+    fn test_type_spans() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let ty = Parser::new("*[3]T? x", &mut ctx).parse_type();
+        let TypeSpecKind::Pointer(inner) = ctx.ast.type_specs[ty].kind else {
+            panic!("expected pointer");
+        };
+        assert_eq!(ctx.ast.type_specs[ty].span, Span::new(0, 6));
+        assert_eq!(ctx.ast.type_specs[inner].span, Span::new(1, 6));
+
+        let ty = Parser::new("fn(a) -> b", &mut ctx).parse_type();
+        assert_eq!(ctx.ast.type_specs[ty].span, Span::new(0, 10));
+    }
+
+    #[test]
+    fn test_program() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let items = Parser::new(
+            "struct P { x: i32 } fn main() {} const N: u8 = 1; extern \"C\" {}",
+            &mut ctx,
+        )
+        .parse_program();
+        assert!(!ctx.has_errors(), "{:?}", ctx.diagnostics);
+        assert_eq!(items.len(), 4);
+        assert_eq!(show_item(&ctx, items[1]), "(fn main () {})");
+    }
+
+    #[test]
+    fn test_program_skips_stray_tokens() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let items = Parser::new("1 + 2; fn f() {} ) ] fn g() {}", &mut ctx).parse_program();
+        assert_eq!(items.len(), 2);
+        assert_eq!(show_item(&ctx, items[1]), "(fn g () {})");
+        assert_eq!(ctx.diagnostics.len(), 2);
+        assert_eq!(ctx.diagnostics[0].span, Span::new(0, 1));
+        assert_eq!(ctx.diagnostics[1].span, Span::new(17, 18));
+    }
+
+    fn parse_lines(src: &str) -> String {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let mut parser = Parser::new(src, &mut ctx);
+        parser.newline_semicolons = true;
+        let expr = parser.parse_expr();
+        assert!(!ctx.has_errors(), "{:?}", ctx.diagnostics);
+        show(&ctx, expr)
+    }
+
+    #[test]
+    fn test_newline_semicolons() {
+        assert_eq!(
+            parse_lines("{\n    val x = 1\n    var y = x\n    f(x); g(y)\n    y = 2\n    y\n}"),
+            "{(val x = 1); (var y = x); (call f [x]); (call g [y]); (= Assign y 2); y}"
+        );
+        // A line ending in an operator or inside delimiters carries on
+        assert_eq!(
+            parse_lines("{\n    val x = 1 +\n        2\n    f(x,\n      (x\n      - 1))\n}"),
+            "{(val x = (Add 1 2)); (call f [x [(Sub x 1)]])}"
+        );
+        // A line starting with one doesn't
+        assert_eq!(
+            parse_lines("{\n    a\n    -b\n    c\n    (d)\n    return\n    e\n}"),
+            "{a; (Neg b); c; [d]; return; e}"
+        );
+        // unless it can only continue the line before
+        assert_eq!(
+            parse_lines("{\n    a\n    && b\n    x\n        |> g\n    x\n        .foo()\n}"),
+            "{(And a b); (|> x g); (call (. x foo) [])}"
+        );
+        assert_eq!(
+            parse_lines("{\n    val y = a\n        || b as i32\n        + 1\n    y\n}"),
+            "{(val y = (Or a (Add (as b i32) 1))); y}"
+        );
+        // Before a `}` on the same line
+        assert_eq!(parse_lines("{ val x = 1 }"), "{(val x = 1)}");
+        assert_eq!(parse_lines("{ defer f() }"), "{(defer (call f []))}");
+    }
+
+    #[test]
+    fn test_newline_semicolons_items() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let src = "const N: i32 = 1\nextern \"C\" {\n    fn puts(s: *u8) -> i32\n}\nfn main() { puts(N) }";
+        let mut parser = Parser::new(src, &mut ctx);
+        parser.newline_semicolons = true;
+        let items = parser.parse_program();
+        assert!(!ctx.has_errors(), "{:?}", ctx.diagnostics);
+        assert_eq!(items.len(), 3);
+    }
+
+    #[test]
+    fn test_newline_semicolons_off_by_default() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        Parser::new("{\n    val x = 1\n    x\n}", &mut ctx).parse_expr();
+        assert_eq!(ctx.diagnostics.len(), 1);
+        assert_eq!(
+            ctx.diagnostics[0].message,
+            "expected `;`, found Ident(\"x\")"
+        );
+    }
+
+    #[test]
+    fn test_misspelled_item_keyword() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let src = "strcut P {} fn f() {} fnn main() {} fn g() {} blah";
+        let items = Parser::new(src, &mut ctx).parse_program();
+        assert_eq!(items.len(), 2);
+        let notes: Vec<_> = ctx.diagnostics.iter().map(|d| d.notes.clone()).collect();
+        assert_eq!(
+            notes,
+            [
+                vec!["did you mean `struct`?"],
+                vec!["did you mean `fn`?"],
+                vec![]
+            ]
+        );
+    }
+
+    #[test]
+    fn test_recover_inside_block() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let items = Parser::new("fn a() { @@@ } fn b() {}", &mut ctx).parse_program();
+        assert_eq!(items.len(), 2);
+        assert_eq!(show_item(&ctx, items[1]), "(fn b () {})");
+        assert_eq!(ctx.diagnostics.len(), 1);
+        assert_eq!(ctx.diagnostics[0].span, Span::new(9, 10));
+    }
+
+    #[test]
+    fn test_recover_to_next_statement() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let expr = Parser::new("{ a + ) { x } ] ; b; c d; e }", &mut ctx).parse_expr();
+        assert_eq!(show(&ctx, expr), "{(Add a <error>); b; c; e}");
+        assert_eq!(ctx.diagnostics.len(), 2);
+        assert_eq!(ctx.diagnostics[0].span, Span::new(6, 7));
+        assert_eq!(ctx.diagnostics[1].span, Span::new(23, 24));
+    }
+
+    #[test]
+    fn test_recover_missing_brace() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let items = Parser::new("fn a() { x; fn b() {}", &mut ctx).parse_program();
+        assert_eq!(items.len(), 2);
+        assert_eq!(ctx.diagnostics.len(), 1);
+        assert_eq!(ctx.diagnostics[0].message, "expected `}`, found Fn");
+    }
+
+    #[test]
+    fn test_stop_after_max_errors() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        ctx.max_errors = 3;
+        let src = "fn a() { ) } fn b() { ) } fn c() { ) } fn d() { ) } fn e() {}";
+        let items = Parser::new(src, &mut ctx).parse_program();
+        assert!(ctx.should_abort());
+        assert!(items.len() < 5);
+        assert_eq!(ctx.diagnostics.len(), 4);
+        assert_eq!(ctx.diagnostics[3].message, "too many errors, aborting");
+    }
+
+    #[test]
+    fn test_var_decl() {
+        assert_eq!(
+            parse("{ val x = 1; var y: *u8 = undefined; x }"),
+            "{(val x = 1); (var y: (ptr u8) = undefined); x}"
+        );
+        assert_eq!(
+            parse("{ var n: i32 = x + 1; }"),
+            "{(var n: i32 = (Add x 1))}"
+        );
+    }
+
+    #[test]
+    fn test_var_decl_errors() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let expr = Parser::new("{ val x; val y = 2 val z = 3; }", &mut ctx).parse_expr();
+        assert_eq!(
+            show(&ctx, expr),
+            "{(val x = undefined); (val y = 2); (val z = 3)}"
+        );
+        assert_eq!(ctx.diagnostics.len(), 2);
+        assert_eq!(
+            ctx.diagnostics[0].message,
+            "`val` declarations need an initializer"
+        );
+        assert_eq!(ctx.diagnostics[0].span, Span::new(7, 8));
+        assert_eq!(ctx.diagnostics[1].span, Span::new(19, 22));
+    }
+
+    #[test]
+    fn test_jumps() {
+        assert_eq!(parse("{ return; }"), "{return}");
+        assert_eq!(parse("{ return a + 1 }"), "{(return (Add a 1))}");
+        assert_eq!(parse("loop { break x * 2; }"), "(loop {(break (Mul x 2))})");
+        assert_eq!(
+            parse("loop { if done { break } continue; }"),
+            "(loop {(if done {break}); continue})"
+        );
+        assert_eq!(parse("f(return, break)"), "(call f [return break])");
+    }
+
+    #[test]
+    fn test_defer() {
+        assert_eq!(
+            parse("{ defer free(p); defer { close(f) } x }"),
+            "{(defer (call free [p])); (defer {(call close [f])}); x}"
+        );
+    }
+
+    #[test]
+    fn test_assign() {
+        assert_eq!(parse("a = b = c"), "(= Assign a (= Assign b c))");
+        assert_eq!(parse("x += 1 + 2"), "(= Add x (Add 1 2))");
+        assert_eq!(parse("p.x -= 1"), "(= Sub (. p x) 1)");
+        assert_eq!(parse("a[i] *= 2"), "(= Mul (index a i) 2)");
+        assert_eq!(parse("*p /= n || m"), "(= Div (Deref p) (Or n m))");
+    }
+
+    #[test]
+    fn test_assign_invalid_target() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let expr = Parser::new("1 = 2", &mut ctx).parse_expr();
+        assert_eq!(ctx.ast.exprs[expr].span, Span::new(0, 5));
+        assert_eq!(ctx.diagnostics.len(), 1);
+        assert_eq!(ctx.diagnostics[0].message, "invalid assignment target");
+        assert_eq!(ctx.diagnostics[0].code, Some("E0005"));
+        assert_eq!(ctx.diagnostics[0].span, Span::new(0, 1));
+    }
+
+    #[test]
+    fn test_cast() {
+        assert_eq!(parse("x as i64"), "(as x i64)");
+        assert_eq!(parse("x as i32 as i64"), "(as (as x i32) i64)");
+        assert_eq!(parse("-x as u8"), "(as (Neg x) u8)");
+        assert_eq!(parse("a * b as f32 + c"), "(Add (Mul a (as b f32)) c)");
+        assert_eq!(parse("p as *u8"), "(as p (ptr u8))");
+    }
+
+    #[test]
+    fn test_cast_span() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let expr = Parser::new("f(x) as *T + 1", &mut ctx).parse_expr();
+        let ExprKind::Binary { lhs, .. } = ctx.ast.exprs[expr].kind else {
+            panic!("expected binary");
+        };
+        assert_eq!(ctx.ast.exprs[lhs].span, Span::new(0, 10));
+    }
+
+    #[test]
+    fn test_pattern_spans() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let expr = Parser::new("match x { 1 | P { a, b: (c, _) } => y }", &mut ctx).parse_expr();
+        let ExprKind::Match { cases, .. } = &ctx.ast.exprs[expr].kind else {
+            panic!("expected match");
+        };
+        let or = &ctx.ast.patterns[cases[0].pattern];
+        assert_eq!(or.span(), Span::new(10, 32));
+        let PatternKind::Or(alternatives) = &or.kind else {
+            panic!("expected or-pattern");
+        };
+        let strukt = &ctx.ast.patterns[alternatives[1]];
+        assert_eq!(strukt.span(), Span::new(14, 32));
+        let PatternKind::Struct { fields, .. } = &strukt.kind else {
+            panic!("expected struct pattern");
+        };
+        assert_eq!(ctx.ast.patterns[fields[0].pattern].span, Span::new(18, 19));
+        assert_eq!(ctx.ast.patterns[fields[1].pattern].span, Span::new(24, 30));
+    }
+
+    #[test]
+    fn test_doc_comments() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let src = "/// Adds.\n///\n/// Twice.\nfn f() {\n    /// dropped\n    1\n}\n\
+                   /// S\n@packed\n/// more\nstruct S {}\n\
+                   /// gone\n\n// plain\nimpl S {\n    /// g\n    fn g() {}\n}\n\
+                   /// at the end";
+        let items = Parser::new(src, &mut ctx).parse_program();
+        assert!(!ctx.has_errors(), "{:?}", ctx.diagnostics);
+        let docs: Vec<_> = items
+            .iter()
+            .map(|&id| ctx.ast.items[id].doc.clone())
+            .collect();
+        assert_eq!(
+            docs,
+            [
+                Some("Adds.\n\nTwice.".to_string()),
+                Some("S\nmore".to_string()),
+                // Separated by a blank line and a comment, still right before
+                Some("gone".to_string()),
+            ]
+        );
+        let ItemKind::ImplDecl { methods, .. } = &ctx.ast.items[items[2]].kind else {
+            panic!("expected impl");
+        };
+        assert_eq!(ctx.ast.items[methods[0]].doc.as_deref(), Some("g"));
+        assert_eq!(ctx.ast.items[items[1]].attrs.len(), 1);
+    }
+
+    #[test]
+    fn test_attributes() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let src =
+            "@inline fn f() {}\n@repr(\"C\", 8) @anything struct S {}\nimpl S { @test fn g() {} }";
+        let items = Parser::new(src, &mut ctx).parse_program();
+        assert!(!ctx.has_errors(), "{:?}", ctx.diagnostics);
+
+        let attrs = |ctx: &CompilerCtx, id: ItemId| -> Vec<(String, usize, Span)> {
+            ctx.ast.items[id]
+                .attrs
+                .iter()
+                .map(|a| {
+                    let name = ctx.symbol_interner.resolve(a.name).to_string();
+                    (name, a.args.len(), a.span)
+                })
+                .collect()
+        };
+        assert_eq!(
+            attrs(&ctx, items[0]),
+            [("inline".into(), 0, Span::new(0, 7))]
+        );
+        assert_eq!(ctx.ast.items[items[0]].span, Span::new(8, 17));
+        assert_eq!(
+            attrs(&ctx, items[1]),
+            [
+                ("repr".into(), 2, Span::new(18, 31)),
+                ("anything".into(), 0, Span::new(32, 41))
+            ]
+        );
+        let ItemKind::ImplDecl { methods, .. } = &ctx.ast.items[items[2]].kind else {
+            panic!("expected impl");
+        };
+        assert_eq!(
+            attrs(&ctx, methods[0]),
+            [("test".into(), 0, Span::new(63, 68))]
+        );
+    }
+
+    #[test]
+    fn test_attribute_errors() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let items = Parser::new(
+            "@ fn f() {} impl S { @inline } @@inline const C: i32 = 1;",
+            &mut ctx,
+        )
+        .parse_program();
+        let messages: Vec<_> = ctx.diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            [
+                "expected identifier, found Fn",
+                "expected `fn`, found RBrace",
+                "expected identifier, found At",
+            ]
+        );
+        // The items after the broken attributes still parse
+        assert_eq!(items.len(), 3);
+    }
+}
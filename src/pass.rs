@@ -0,0 +1,79 @@
+use crate::context::CompilerCtx;
+
+// One stage of the compiler working over the whole program in `ctx`. A
+// pass that needs the top-level items or produces a result other than
+// changes to `ctx` keeps them in its own fields.
+pub trait Pass {
+    fn name(&self) -> &'static str;
+
+    fn run(&mut self, ctx: &mut CompilerCtx);
+}
+
+// Runs `passes` in order, setting `current_pass` before each so their
+// diagnostics record where they came from. Later passes usually assume a
+// well-formed tree, so the pipeline stops after the first pass that leaves
+// errors behind. Returns whether every pass ran.
+pub fn run_passes(ctx: &mut CompilerCtx, passes: &mut [&mut dyn Pass]) -> bool {
+    for pass in passes.iter_mut() {
+        if ctx.has_errors() {
+            return false;
+        }
+        ctx.current_pass = pass.name();
+        pass.run(ctx);
+    }
+    !ctx.has_errors()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Span;
+    use crate::context::DiagnosticLevel;
+
+    struct Report {
+        name: &'static str,
+        level: DiagnosticLevel,
+        ran: bool,
+    }
+
+    impl Pass for Report {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn run(&mut self, ctx: &mut CompilerCtx) {
+            self.ran = true;
+            ctx.report(self.level, self.name, Span::DUMMY);
+        }
+    }
+
+    fn report(name: &'static str, level: DiagnosticLevel) -> Report {
+        Report {
+            name,
+            level,
+            ran: false,
+        }
+    }
+
+    #[test]
+    fn test_run_passes_records_pass() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let mut a = report("a", DiagnosticLevel::Warning);
+        let mut b = report("b", DiagnosticLevel::Info);
+        assert!(run_passes(&mut ctx, &mut [&mut a, &mut b]));
+        assert_eq!(ctx.current_pass, "b");
+        let passes: Vec<_> = ctx.diagnostics.iter().map(|d| d.pass).collect();
+        assert_eq!(passes, [Some("a"), Some("b")]);
+    }
+
+    #[test]
+    fn test_run_passes_stops_on_error() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let mut a = report("a", DiagnosticLevel::Error);
+        let mut b = report("b", DiagnosticLevel::Info);
+        assert!(!run_passes(&mut ctx, &mut [&mut a, &mut b]));
+        assert!(a.ran);
+        assert!(!b.ran);
+        assert_eq!(ctx.diagnostics.len(), 1);
+    }
+}
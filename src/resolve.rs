@@ -0,0 +1,484 @@
+use std::collections::HashMap;
+
+use crate::ast::{
+    Ast, ExprId, ExprKind, ItemId, ItemKind, Param, PatternId, PatternKind, StmtId, StmtKind,
+};
+use crate::common::{Span, Symbol, SymbolInterner, closest_match};
+use crate::context::{CompilerCtx, Diagnostic};
+use crate::errors::ErrorCode;
+use crate::lexer::TokenKind;
+use crate::pass::Pass;
+use crate::visit::{Visitor, walk_expr, walk_item, walk_stmt};
+
+// What a name refers to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decl {
+    Item(ItemId),
+    // The `index`th function of an `extern` block
+    Extern { item: ItemId, index: usize },
+    Param { func: ItemId, index: usize },
+    ClosureParam { closure: ExprId, index: usize },
+    // A `val` or `var` statement
+    Local(StmtId),
+    // The loop variable of a `for` expression
+    ForBinding(ExprId),
+    // An identifier pattern in a `match` case
+    Binding(PatternId),
+}
+
+// Side table from every resolved `ExprKind::Identifier` to its declaration
+#[derive(Debug, Default)]
+pub struct Resolutions {
+    pub uses: HashMap<ExprId, Decl>,
+}
+
+impl Resolutions {
+    pub fn get(&self, id: ExprId) -> Option<Decl> {
+        self.uses.get(&id).copied()
+    }
+}
+
+// Resolves every identifier in `items`. Items are visible throughout the
+// program regardless of order; locals from their declaration to the end
+// of the enclosing block. Redeclaring a name in the same scope is an
+// error, shadowing one from an outer scope is not.
+//
+// Type names aren't looked at here, primitives only exist once the type
+// checker injects them.
+pub fn resolve(ctx: &mut CompilerCtx, items: &[ItemId]) -> Resolutions {
+    let mut resolver = Resolver {
+        interner: &ctx.symbol_interner,
+        scopes: vec![HashMap::new()],
+        resolutions: Resolutions::default(),
+        diagnostics: Vec::new(),
+    };
+    for &item in items {
+        resolver.declare_item(&ctx.ast, item);
+    }
+    for &item in items {
+        resolver.visit_item(&ctx.ast, item);
+    }
+
+    let Resolver {
+        resolutions,
+        diagnostics,
+        ..
+    } = resolver;
+    for diagnostic in diagnostics {
+        ctx.emit(diagnostic);
+    }
+    resolutions
+}
+
+// `resolve` as a pipeline stage, keeping the resolutions for later passes
+pub struct ResolvePass {
+    pub items: Vec<ItemId>,
+    pub resolutions: Resolutions,
+}
+
+impl ResolvePass {
+    pub fn new(items: Vec<ItemId>) -> Self {
+        Self {
+            items,
+            resolutions: Resolutions::default(),
+        }
+    }
+}
+
+impl Pass for ResolvePass {
+    fn name(&self) -> &'static str {
+        "resolve"
+    }
+
+    fn run(&mut self, ctx: &mut CompilerCtx) {
+        self.resolutions = resolve(ctx, &self.items);
+    }
+}
+
+struct Resolver<'a> {
+    interner: &'a SymbolInterner,
+    // Innermost scope last, each name mapped to its declaration and the
+    // span to point at if it's declared again
+    scopes: Vec<HashMap<Symbol, (Decl, Span)>>,
+    resolutions: Resolutions,
+    // Collected here and emitted once the AST is no longer borrowed
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Resolver<'_> {
+    fn declare_item(&mut self, ast: &Ast, id: ItemId) {
+        let item = &ast.items[id];
+        match &item.kind {
+            ItemKind::FnDecl { name, .. }
+            | ItemKind::StructDecl { name, .. }
+            | ItemKind::EnumDecl { name, .. }
+            | ItemKind::UnionDecl { name, .. }
+            | ItemKind::ConstDecl { name, .. } => self.declare(*name, Decl::Item(id), item.span),
+            ItemKind::ExternDecl { declarations, .. } => {
+                for (index, sig) in declarations.iter().enumerate() {
+                    self.declare(sig.name, Decl::Extern { item: id, index }, item.span);
+                }
+            }
+            // Methods are reached through their type, not by name
+            ItemKind::ImplDecl { .. } => {}
+        }
+    }
+
+    fn declare(&mut self, name: Symbol, decl: Decl, span: Span) {
+        let scope = self.scopes.last_mut().expect("no scope to declare in");
+        if let Some(&(_, first)) = scope.get(&name) {
+            let name = self.interner.resolve(name);
+            self.diagnostics.push(
+                ErrorCode::DuplicateDefinition
+                    .diagnostic(span, &[name])
+                    .with_label(first, "first defined here"),
+            );
+            return;
+        }
+        scope.insert(name, (decl, span));
+    }
+
+    fn lookup(&self, name: Symbol) -> Option<Decl> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(&name).map(|&(decl, _)| decl))
+    }
+
+    // The visible name or keyword closest to a misspelled `name`. Inner
+    // scopes are tried first, and names within a scope in sorted order so
+    // ties don't depend on hashing.
+    fn similar_name(&self, name: Symbol) -> Option<&str> {
+        let mut candidates = Vec::new();
+        for scope in self.scopes.iter().rev() {
+            let mut names: Vec<&str> = scope.keys().map(|&n| self.interner.resolve(n)).collect();
+            names.sort_unstable();
+            candidates.extend(names);
+        }
+        candidates.extend(TokenKind::reserved_words().map(|w| w as &str));
+        closest_match(self.interner.resolve(name), candidates)
+    }
+
+    fn in_scope(&mut self, f: impl FnOnce(&mut Self)) {
+        self.scopes.push(HashMap::new());
+        f(self);
+        self.scopes.pop();
+    }
+
+    // Declares a function's or closure's parameters in the current scope.
+    // Repeated parameters were reported by the parser, the first one is the
+    // one that's visible.
+    fn declare_params(&mut self, ast: &Ast, params: &[Param], decl: impl Fn(usize) -> Decl) {
+        for (index, param) in params.iter().enumerate() {
+            if self
+                .scopes
+                .last()
+                .is_some_and(|s| s.contains_key(&param.name))
+            {
+                continue;
+            }
+            let span = ast.type_specs[param.ty].span;
+            self.declare(param.name, decl(index), span);
+        }
+    }
+
+    // Declares the names bound by a match pattern. The alternatives of an
+    // or-pattern bind the same names, so only the first one declares them.
+    fn bind_pattern(&mut self, ast: &Ast, id: PatternId) {
+        let pattern = &ast.patterns[id];
+        match &pattern.kind {
+            PatternKind::Literal(_) | PatternKind::Wildcard => {}
+            PatternKind::Identifier(name) => self.declare(*name, Decl::Binding(id), pattern.span),
+            PatternKind::Tuple(elems) => {
+                for elem in elems {
+                    self.bind_pattern(ast, *elem);
+                }
+            }
+            PatternKind::Or(alternatives) => {
+                if let Some(first) = alternatives.first() {
+                    self.bind_pattern(ast, *first);
+                }
+            }
+            PatternKind::Struct { fields, .. } => {
+                for field in fields {
+                    self.bind_pattern(ast, field.pattern);
+                }
+            }
+        }
+    }
+}
+
+impl Visitor for Resolver<'_> {
+    fn visit_expr(&mut self, ast: &Ast, id: ExprId) {
+        let expr = &ast.exprs[id];
+        match &expr.kind {
+            ExprKind::Identifier(name) => match self.lookup(*name) {
+                Some(decl) => {
+                    self.resolutions.uses.insert(id, decl);
+                }
+                None => {
+                    let mut diag = ErrorCode::UndeclaredName
+                        .diagnostic(expr.span, &[self.interner.resolve(*name)]);
+                    if let Some(similar) = self.similar_name(*name) {
+                        diag = diag.with_note(&format!("did you mean `{}`?", similar));
+                    }
+                    self.diagnostics.push(diag);
+                }
+            },
+            ExprKind::Block { .. } => self.in_scope(|r| walk_expr(r, ast, id)),
+            ExprKind::For {
+                binding,
+                iterable,
+                body,
+                else_branch,
+            } => {
+                self.visit_expr(ast, *iterable);
+                self.in_scope(|r| {
+                    r.declare(*binding, Decl::ForBinding(id), expr.span);
+                    r.visit_expr(ast, *body);
+                });
+                if let Some(e) = else_branch {
+                    self.visit_expr(ast, *e);
+                }
+            }
+            // The body sees the enclosing locals as well as its parameters
+            ExprKind::Closure { params, .. } => self.in_scope(|r| {
+                r.declare_params(ast, params, |index| Decl::ClosureParam {
+                    closure: id,
+                    index,
+                });
+                walk_expr(r, ast, id);
+            }),
+            ExprKind::Match { target, cases } => {
+                self.visit_expr(ast, *target);
+                for case in cases {
+                    self.in_scope(|r| {
+                        r.bind_pattern(ast, case.pattern);
+                        r.visit_expr(ast, case.body);
+                    });
+                }
+            }
+            _ => walk_expr(self, ast, id),
+        }
+    }
+
+    fn visit_stmt(&mut self, ast: &Ast, id: StmtId) {
+        // The initializer is resolved first, so `val x = x` refers to an
+        // outer `x`
+        walk_stmt(self, ast, id);
+        let stmt = &ast.stmts[id];
+        if let StmtKind::VarDecl { name, .. } = &stmt.kind {
+            self.declare(*name, Decl::Local(id), stmt.span);
+        }
+    }
+
+    fn visit_item(&mut self, ast: &Ast, id: ItemId) {
+        let ItemKind::FnDecl { params, .. } = &ast.items[id].kind else {
+            walk_item(self, ast, id);
+            return;
+        };
+        self.in_scope(|r| {
+            r.declare_params(ast, params, |index| Decl::Param { func: id, index });
+            walk_item(r, ast, id);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    fn resolve_src(src: &str) -> (CompilerCtx, Vec<ItemId>, Resolutions) {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let items = Parser::new(src, &mut ctx).parse_program();
+        assert!(ctx.diagnostics.is_empty(), "parse errors in {:?}", src);
+        let resolutions = resolve(&mut ctx, &items);
+        (ctx, items, resolutions)
+    }
+
+    fn messages(ctx: &CompilerCtx) -> Vec<&str> {
+        ctx.diagnostics.iter().map(|d| d.message.as_str()).collect()
+    }
+
+    // Declarations of the identifiers named `name`, in source order
+    fn uses_of(ctx: &CompilerCtx, res: &Resolutions, name: &str) -> Vec<Decl> {
+        let mut uses: Vec<_> = res
+            .uses
+            .iter()
+            .filter(|(id, _)| {
+                matches!(ctx.ast.exprs[**id].kind,
+                    ExprKind::Identifier(sym) if ctx.symbol_interner.resolve(sym) == name)
+            })
+            .map(|(id, decl)| (ctx.ast.exprs[*id].span.start, *decl))
+            .collect();
+        uses.sort_by_key(|(start, _)| *start);
+        uses.into_iter().map(|(_, decl)| decl).collect()
+    }
+
+    #[test]
+    fn test_resolve_items_params_and_locals() {
+        let (ctx, items, res) =
+            resolve_src("fn f(a: i32) -> i32 { val b = a; g(b) } fn g(x: i32) -> i32 { x }");
+        assert!(ctx.diagnostics.is_empty());
+        assert_eq!(uses_of(&ctx, &res, "g"), [Decl::Item(items[1])]);
+        assert_eq!(
+            uses_of(&ctx, &res, "a"),
+            [Decl::Param {
+                func: items[0],
+                index: 0
+            }]
+        );
+        assert!(matches!(uses_of(&ctx, &res, "b")[..], [Decl::Local(_)]));
+    }
+
+    #[test]
+    fn test_resolve_shadowing() {
+        let (ctx, items, res) =
+            resolve_src("fn f(x: i32) { val y = x; { val x = 1; x; } val x = x; x; }");
+        assert!(ctx.diagnostics.is_empty());
+        let uses = uses_of(&ctx, &res, "x");
+        let param = Decl::Param {
+            func: items[0],
+            index: 0,
+        };
+        assert_eq!(uses[0], param);
+        assert!(matches!(uses[1], Decl::Local(_)));
+        // `val x = x` sees the parameter, the statement after it the local
+        assert_eq!(uses[2], param);
+        assert!(matches!(uses[3], Decl::Local(_)));
+        assert_ne!(uses[1], uses[3]);
+    }
+
+    #[test]
+    fn test_resolve_undeclared() {
+        let (ctx, _, _) = resolve_src("fn f() { { val a = 1; } a + b; }");
+        assert_eq!(
+            messages(&ctx),
+            [
+                "cannot find `a` in this scope",
+                "cannot find `b` in this scope"
+            ]
+        );
+        assert_eq!(ctx.diagnostics[0].code, Some("E0008"));
+        assert_eq!(ctx.diagnostics[1].span, Span::new(28, 29));
+    }
+
+    #[test]
+    fn test_resolve_pass() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let items = Parser::new("fn f(a: i32) { a; b; }", &mut ctx).parse_program();
+        let mut pass = ResolvePass::new(items);
+        assert!(!crate::pass::run_passes(&mut ctx, &mut [&mut pass]));
+        assert_eq!(pass.resolutions.uses.len(), 1);
+        assert_eq!(ctx.diagnostics[0].pass, Some("resolve"));
+    }
+
+    #[test]
+    fn test_resolve_duplicates() {
+        // `val a` shadows the parameter from inside the body block
+        let (ctx, _, _) =
+            resolve_src("fn f(a: i32) { val b = 1; val b = 2; val a = 3; } struct f {}");
+        assert_eq!(
+            messages(&ctx),
+            [
+                "the name `f` is defined multiple times",
+                "the name `b` is defined multiple times",
+            ]
+        );
+        assert_eq!(
+            ctx.diagnostics[0].labels[0],
+            (Span::new(0, 49), "first defined here".into())
+        );
+        assert_eq!(ctx.diagnostics[1].span, Span::new(26, 36));
+    }
+
+    #[test]
+    fn test_resolve_duplicate_params_keep_first() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let items = Parser::new("fn f(a: i32, a: u8) { a; }", &mut ctx).parse_program();
+        let res = resolve(&mut ctx, &items);
+        assert_eq!(ctx.diagnostics.len(), 1);
+        assert_eq!(
+            uses_of(&ctx, &res, "a"),
+            [Decl::Param {
+                func: items[0],
+                index: 0
+            }]
+        );
+    }
+
+    #[test]
+    fn test_resolve_for_and_match_bindings() {
+        let (ctx, _, res) = resolve_src(
+            "fn f(xs: i32) { for x in xs { x; } match xs { (a, b) | (b, a) => a + b, _ => 0 } }",
+        );
+        assert!(ctx.diagnostics.is_empty());
+        assert!(matches!(
+            uses_of(&ctx, &res, "x")[..],
+            [Decl::ForBinding(_)]
+        ));
+        assert!(matches!(uses_of(&ctx, &res, "a")[..], [Decl::Binding(_)]));
+
+        let (ctx, _, _) = resolve_src("fn f(y: i32) { for x in y {} x; match y { z => 1 } z; }");
+        assert_eq!(
+            messages(&ctx),
+            [
+                "cannot find `x` in this scope",
+                "cannot find `z` in this scope"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resolve_closure_params() {
+        let (ctx, items, res) =
+            resolve_src("fn f(a: i32) { val g = fn(b: i32) -> i32 { a + b }; g(a); }");
+        assert!(ctx.diagnostics.is_empty());
+        let param = Decl::Param {
+            func: items[0],
+            index: 0,
+        };
+        assert_eq!(uses_of(&ctx, &res, "a"), [param, param]);
+        assert!(matches!(
+            uses_of(&ctx, &res, "b")[..],
+            [Decl::ClosureParam { index: 0, .. }]
+        ));
+
+        let (ctx, _, _) = resolve_src("fn f() { fn(x: i32) { }; x; }");
+        assert_eq!(messages(&ctx), ["cannot find `x` in this scope"]);
+    }
+
+    #[test]
+    fn test_resolve_suggestions() {
+        let (ctx, _, _) = resolve_src(
+            "fn count(total: i32) -> i32 { val totl = 1; retrun; totla; cout(1); zzz }",
+        );
+        let notes: Vec<_> = ctx.diagnostics.iter().map(|d| d.notes.clone()).collect();
+        assert_eq!(
+            notes,
+            [
+                vec!["did you mean `return`?"],
+                vec!["did you mean `totl`?"],
+                vec!["did you mean `count`?"],
+                vec![],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resolve_extern_and_const() {
+        let (ctx, items, res) = resolve_src(
+            "extern \"C\" { fn puts(s: *u8) -> i32; } const N: i32 = M; const M: i32 = 1; fn f() { puts(N); }",
+        );
+        assert!(ctx.diagnostics.is_empty());
+        assert_eq!(
+            uses_of(&ctx, &res, "puts"),
+            [Decl::Extern {
+                item: items[0],
+                index: 0
+            }]
+        );
+        assert_eq!(uses_of(&ctx, &res, "M"), [Decl::Item(items[2])]);
+    }
+}
@@ -0,0 +1,217 @@
+use crate::ast::{
+    Ast, ExprId, ExprKind, ItemId, ItemKind, StmtId, StmtKind, TypeSpecId, TypeSpecKind,
+};
+use crate::common::Symbol;
+use crate::context::{CompilerCtx, Diagnostic};
+use crate::errors::ErrorCode;
+use crate::pass::Pass;
+use crate::visit::{Visitor, walk_expr, walk_item, walk_stmt};
+
+// Reports every `if` without an `else` whose value is used, such as
+// `val x = if c { 1 };`, since nothing says what it evaluates to when the
+// condition is false. Purely structural, it runs before there are any types:
+// an expression's value counts as used unless it's an expression statement,
+// a loop body, or the tail of a function with no return type or `void`.
+pub fn check_values(ctx: &mut CompilerCtx, items: &[ItemId]) {
+    let mut checker = ValueChecker {
+        used: true,
+        diagnostics: Vec::new(),
+    };
+    for &item in items {
+        checker.visit_item(&ctx.ast, item);
+    }
+    for diagnostic in checker.diagnostics {
+        ctx.emit(diagnostic);
+    }
+}
+
+pub struct ValueCheckPass {
+    pub items: Vec<ItemId>,
+}
+
+impl Pass for ValueCheckPass {
+    fn name(&self) -> &'static str {
+        "value check"
+    }
+
+    fn run(&mut self, ctx: &mut CompilerCtx) {
+        check_values(ctx, &self.items);
+    }
+}
+
+struct ValueChecker {
+    // Whether the value of the expression being visited is used
+    used: bool,
+    diagnostics: Vec<Diagnostic>,
+}
+
+// Whether a function or closure with this return type uses its tail value
+fn returns_value(ast: &Ast, ret_ty: Option<TypeSpecId>) -> bool {
+    ret_ty.is_some_and(|ty| !matches!(ast.type_specs[ty].kind, TypeSpecKind::Named(Symbol::VOID)))
+}
+
+impl ValueChecker {
+    fn visit_with(&mut self, ast: &Ast, id: ExprId, used: bool) {
+        let outer = std::mem::replace(&mut self.used, used);
+        self.visit_expr(ast, id);
+        self.used = outer;
+    }
+}
+
+impl Visitor for ValueChecker {
+    fn visit_item(&mut self, ast: &Ast, id: ItemId) {
+        match &ast.items[id].kind {
+            ItemKind::FnDecl { ret_ty, body, .. } => {
+                self.visit_with(ast, *body, returns_value(ast, *ret_ty))
+            }
+            _ => walk_item(self, ast, id),
+        }
+    }
+
+    fn visit_stmt(&mut self, ast: &Ast, id: StmtId) {
+        match &ast.stmts[id].kind {
+            StmtKind::Expr(e) | StmtKind::Defer(e) => self.visit_with(ast, *e, false),
+            StmtKind::VarDecl { .. } => {
+                let outer = std::mem::replace(&mut self.used, true);
+                walk_stmt(self, ast, id);
+                self.used = outer;
+            }
+        }
+    }
+
+    // Branches and block tails pass the use of their own value down,
+    // everything else uses the values of its operands
+    fn visit_expr(&mut self, ast: &Ast, id: ExprId) {
+        let used = self.used;
+        let expr = &ast.exprs[id];
+        match &expr.kind {
+            ExprKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                if used && else_branch.is_none() {
+                    let diag = ErrorCode::IfWithoutElse.diagnostic(expr.span, &[]);
+                    self.diagnostics.push(diag);
+                }
+                self.visit_with(ast, *cond, true);
+                self.visit_with(ast, *then_branch, used);
+                if let Some(e) = else_branch {
+                    self.visit_with(ast, *e, used);
+                }
+            }
+            ExprKind::Match { target, cases } => {
+                self.visit_with(ast, *target, true);
+                for case in cases {
+                    self.visit_pattern(ast, case.pattern);
+                    self.visit_with(ast, case.body, used);
+                }
+            }
+            ExprKind::Block { stmts, yield_expr } => {
+                for stmt in stmts {
+                    self.visit_stmt(ast, *stmt);
+                }
+                if let Some(e) = yield_expr {
+                    self.visit_with(ast, *e, used);
+                }
+            }
+            ExprKind::Paren(inner) => self.visit_with(ast, *inner, used),
+            // A closure body is checked like a function body, whatever
+            // happens to the closure itself
+            ExprKind::Closure { ret_ty, body, .. } => {
+                self.visit_with(ast, *body, returns_value(ast, *ret_ty))
+            }
+            ExprKind::Loop { body } => self.visit_with(ast, *body, false),
+            ExprKind::While {
+                cond,
+                body,
+                else_branch,
+            } => {
+                self.visit_with(ast, *cond, true);
+                self.visit_with(ast, *body, false);
+                if let Some(e) = else_branch {
+                    self.visit_with(ast, *e, used);
+                }
+            }
+            ExprKind::For {
+                iterable,
+                body,
+                else_branch,
+                ..
+            } => {
+                self.visit_with(ast, *iterable, true);
+                self.visit_with(ast, *body, false);
+                if let Some(e) = else_branch {
+                    self.visit_with(ast, *e, used);
+                }
+            }
+            _ => {
+                self.used = true;
+                walk_expr(self, ast, id);
+                self.used = used;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Span;
+    use crate::parser::Parser;
+
+    fn check(src: &str) -> Vec<Span> {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let items = Parser::new(src, &mut ctx).parse_program();
+        assert!(!ctx.has_errors(), "{:?}", ctx.diagnostics);
+        check_values(&mut ctx, &items);
+        ctx.diagnostics.iter().map(|d| d.span).collect()
+    }
+
+    #[test]
+    fn test_if_as_value_needs_else() {
+        assert_eq!(check("fn f() { val x = if c { 1 }; }"), [Span::new(17, 27)]);
+        assert_eq!(check("fn f() -> i32 { if c { 1 } }"), [Span::new(16, 26)]);
+        assert_eq!(check("fn f() { g(if c { 1 }) }"), [Span::new(11, 21)]);
+        assert_eq!(
+            check("const C: i32 = 1 + (if c { 2 });"),
+            [Span::new(20, 30)]
+        );
+        // Through block tails, match arms and nested branches
+        assert_eq!(
+            check("fn f() -> i32 { match x { _ => { if c { 1 } } } }"),
+            [Span::new(33, 43)]
+        );
+        assert_eq!(
+            check("fn f() -> i32 { if a { 1 } else if b { 2 } }"),
+            [Span::new(32, 42)]
+        );
+    }
+
+    #[test]
+    fn test_if_as_statement() {
+        assert!(check("fn f() { if c { g() } }").is_empty());
+        assert!(check("fn f() -> i32 { if c { return 1; } 0 }").is_empty());
+        assert!(check("fn f() { val x = if c { 1 } else { 2 }; }").is_empty());
+        assert!(check("fn f() -> i32 { while c { if d { 1 } } 0 }").is_empty());
+        assert!(check("fn f() { defer if c { g() }; loop { if c { break; } } }").is_empty());
+        assert!(check("fn f() -> i32 { g(fn() { if c { h() } }); 0 }").is_empty());
+        assert_eq!(
+            check("fn f() { g(fn() -> i32 { if c { 1 } }); }"),
+            [Span::new(25, 35)]
+        );
+        // An explicit `void` is the same as no return type
+        assert!(check("fn f(c: bool) -> void { if c { g(); } }").is_empty());
+        assert!(check("fn f() { g(fn() -> void { if c { h() } }); }").is_empty());
+    }
+
+    #[test]
+    fn test_value_check_pass() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let items = Parser::new("fn f() -> i32 { if c { 1 } }", &mut ctx).parse_program();
+        let mut pass = ValueCheckPass { items };
+        assert!(!crate::pass::run_passes(&mut ctx, &mut [&mut pass]));
+        assert_eq!(ctx.diagnostics[0].code, Some("E0015"));
+        assert_eq!(ctx.diagnostics[0].pass, Some("value check"));
+    }
+}
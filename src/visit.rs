@@ -0,0 +1,644 @@
+use crate::ast::{
+    Ast, ExprId, ExprKind, ItemId, ItemKind, Param, PatternId, PatternKind, StmtId, StmtKind,
+    TypeSpecId, TypeSpecKind, UnionVariantData, VarInit,
+};
+use crate::context::CompilerCtx;
+
+// Read-only traversal of the AST. Every method defaults to the matching
+// `walk_*` function, which visits the node's children in source order. An
+// override does its work before or after calling `walk_*` itself to choose
+// pre- or post-order, or skips the call to stop descending.
+pub trait Visitor {
+    fn visit_expr(&mut self, ast: &Ast, id: ExprId) {
+        walk_expr(self, ast, id);
+    }
+
+    fn visit_stmt(&mut self, ast: &Ast, id: StmtId) {
+        walk_stmt(self, ast, id);
+    }
+
+    fn visit_item(&mut self, ast: &Ast, id: ItemId) {
+        walk_item(self, ast, id);
+    }
+
+    fn visit_type(&mut self, ast: &Ast, id: TypeSpecId) {
+        walk_type(self, ast, id);
+    }
+
+    fn visit_pattern(&mut self, ast: &Ast, id: PatternId) {
+        walk_pattern(self, ast, id);
+    }
+}
+
+pub fn walk_expr<V: Visitor + ?Sized>(v: &mut V, ast: &Ast, id: ExprId) {
+    let expr = &ast.exprs[id];
+    // Type written as a literal suffix
+    if let Some(ty) = expr.ty {
+        v.visit_type(ast, ty);
+    }
+    match &expr.kind {
+        ExprKind::Literal(_) | ExprKind::Identifier(_) | ExprKind::Continue | ExprKind::Error => {}
+        ExprKind::Paren(inner) => v.visit_expr(ast, *inner),
+        ExprKind::Tuple(elems) | ExprKind::ArrayLit(elems) => {
+            for e in elems {
+                v.visit_expr(ast, *e);
+            }
+        }
+        ExprKind::StructLit { fields, .. } => {
+            for (_, e) in fields {
+                v.visit_expr(ast, *e);
+            }
+        }
+        ExprKind::Block { stmts, yield_expr } => {
+            for stmt in stmts {
+                v.visit_stmt(ast, *stmt);
+            }
+            if let Some(e) = yield_expr {
+                v.visit_expr(ast, *e);
+            }
+        }
+        ExprKind::If {
+            cond,
+            then_branch,
+            else_branch,
+        } => {
+            v.visit_expr(ast, *cond);
+            v.visit_expr(ast, *then_branch);
+            if let Some(e) = else_branch {
+                v.visit_expr(ast, *e);
+            }
+        }
+        ExprKind::Match { target, cases } => {
+            v.visit_expr(ast, *target);
+            for case in cases {
+                v.visit_pattern(ast, case.pattern);
+                v.visit_expr(ast, case.body);
+            }
+        }
+        ExprKind::Loop { body } => v.visit_expr(ast, *body),
+        ExprKind::While {
+            cond,
+            body,
+            else_branch,
+        } => {
+            v.visit_expr(ast, *cond);
+            v.visit_expr(ast, *body);
+            if let Some(e) = else_branch {
+                v.visit_expr(ast, *e);
+            }
+        }
+        ExprKind::For {
+            iterable,
+            body,
+            else_branch,
+            ..
+        } => {
+            v.visit_expr(ast, *iterable);
+            v.visit_expr(ast, *body);
+            if let Some(e) = else_branch {
+                v.visit_expr(ast, *e);
+            }
+        }
+        ExprKind::Return(value) | ExprKind::Break(value) => {
+            if let Some(e) = value {
+                v.visit_expr(ast, *e);
+            }
+        }
+        ExprKind::Closure {
+            params,
+            ret_ty,
+            body,
+        } => {
+            walk_signature(v, ast, params, *ret_ty);
+            v.visit_expr(ast, *body);
+        }
+        ExprKind::Binary { lhs, rhs, .. } | ExprKind::Pipeline { lhs, rhs } => {
+            v.visit_expr(ast, *lhs);
+            v.visit_expr(ast, *rhs);
+        }
+        ExprKind::Assign { target, value, .. } => {
+            v.visit_expr(ast, *target);
+            v.visit_expr(ast, *value);
+        }
+        ExprKind::Cast {
+            target,
+            target_type,
+        } => {
+            v.visit_expr(ast, *target);
+            v.visit_type(ast, *target_type);
+        }
+        ExprKind::Unary { operand, .. } => v.visit_expr(ast, *operand),
+        ExprKind::Call {
+            callee,
+            type_args,
+            args,
+        } => {
+            v.visit_expr(ast, *callee);
+            for ty in type_args {
+                v.visit_type(ast, *ty);
+            }
+            for arg in args {
+                v.visit_expr(ast, *arg);
+            }
+        }
+        ExprKind::MemberAccess { expr, .. } => v.visit_expr(ast, *expr),
+        ExprKind::IndexAccess { expr, index } => {
+            v.visit_expr(ast, *expr);
+            v.visit_expr(ast, *index);
+        }
+    }
+}
+
+pub fn walk_stmt<V: Visitor + ?Sized>(v: &mut V, ast: &Ast, id: StmtId) {
+    match &ast.stmts[id].kind {
+        StmtKind::VarDecl { ty, init, .. } => {
+            if let Some(ty) = ty {
+                v.visit_type(ast, *ty);
+            }
+            if let VarInit::Expr(e) = init {
+                v.visit_expr(ast, *e);
+            }
+        }
+        StmtKind::Defer(e) | StmtKind::Expr(e) => v.visit_expr(ast, *e),
+    }
+}
+
+pub fn walk_item<V: Visitor + ?Sized>(v: &mut V, ast: &Ast, id: ItemId) {
+    match &ast.items[id].kind {
+        ItemKind::FnDecl {
+            params,
+            ret_ty,
+            body,
+            ..
+        } => {
+            walk_signature(v, ast, params, *ret_ty);
+            v.visit_expr(ast, *body);
+        }
+        ItemKind::StructDecl { fields, .. } => {
+            for field in fields {
+                v.visit_type(ast, field.ty);
+            }
+        }
+        ItemKind::EnumDecl { backing_ty, .. } => {
+            if let Some(ty) = backing_ty {
+                v.visit_type(ast, *ty);
+            }
+        }
+        ItemKind::UnionDecl { variants, .. } => {
+            for variant in variants {
+                match &variant.data {
+                    None => {}
+                    Some(UnionVariantData::Tuple(types)) => {
+                        for ty in types {
+                            v.visit_type(ast, *ty);
+                        }
+                    }
+                    Some(UnionVariantData::Struct(fields)) => {
+                        for field in fields {
+                            v.visit_type(ast, field.ty);
+                        }
+                    }
+                }
+            }
+        }
+        ItemKind::ImplDecl { self_ty, methods } => {
+            v.visit_type(ast, *self_ty);
+            for method in methods {
+                v.visit_item(ast, *method);
+            }
+        }
+        ItemKind::ConstDecl { ty, expr, .. } => {
+            v.visit_type(ast, *ty);
+            v.visit_expr(ast, *expr);
+        }
+        ItemKind::ExternDecl { declarations, .. } => {
+            for sig in declarations {
+                walk_signature(v, ast, &sig.params, sig.return_ty);
+            }
+        }
+    }
+}
+
+fn walk_signature<V: Visitor + ?Sized>(
+    v: &mut V,
+    ast: &Ast,
+    params: &[Param],
+    ret_ty: Option<TypeSpecId>,
+) {
+    for param in params {
+        v.visit_type(ast, param.ty);
+    }
+    if let Some(ty) = ret_ty {
+        v.visit_type(ast, ty);
+    }
+}
+
+pub fn walk_type<V: Visitor + ?Sized>(v: &mut V, ast: &Ast, id: TypeSpecId) {
+    match &ast.type_specs[id].kind {
+        TypeSpecKind::Named(_) | TypeSpecKind::Error => {}
+        TypeSpecKind::Pointer(inner)
+        | TypeSpecKind::Reference(inner)
+        | TypeSpecKind::MutReference(inner)
+        | TypeSpecKind::Optional(inner)
+        | TypeSpecKind::Slice(inner)
+        | TypeSpecKind::Paren(inner) => v.visit_type(ast, *inner),
+        TypeSpecKind::Array { size, elem_ty } => {
+            v.visit_expr(ast, *size);
+            v.visit_type(ast, *elem_ty);
+        }
+        TypeSpecKind::Fn { param, return_ty } => {
+            for ty in param {
+                v.visit_type(ast, *ty);
+            }
+            v.visit_type(ast, *return_ty);
+        }
+        TypeSpecKind::Tuple(elems) | TypeSpecKind::Generic { args: elems, .. } => {
+            for ty in elems {
+                v.visit_type(ast, *ty);
+            }
+        }
+    }
+}
+
+pub fn walk_pattern<V: Visitor + ?Sized>(v: &mut V, ast: &Ast, id: PatternId) {
+    match &ast.patterns[id].kind {
+        PatternKind::Literal(_) | PatternKind::Identifier(_) | PatternKind::Wildcard => {}
+        PatternKind::Tuple(elems) | PatternKind::Or(elems) => {
+            for elem in elems {
+                v.visit_pattern(ast, *elem);
+            }
+        }
+        PatternKind::Struct { fields, .. } => {
+            for field in fields {
+                v.visit_pattern(ast, field.pattern);
+            }
+        }
+    }
+}
+
+// Traversal that may rewrite nodes in place. Children are visited before
+// the default methods return, so an override that calls `walk_*_mut` first
+// sees already rewritten children and can then overwrite its own node
+// through `ctx.ast`.
+pub trait MutVisitor {
+    fn fold_expr(&mut self, ctx: &mut CompilerCtx, id: ExprId) {
+        walk_expr_mut(self, ctx, id);
+    }
+
+    fn fold_stmt(&mut self, ctx: &mut CompilerCtx, id: StmtId) {
+        walk_stmt_mut(self, ctx, id);
+    }
+
+    fn fold_item(&mut self, ctx: &mut CompilerCtx, id: ItemId) {
+        walk_item_mut(self, ctx, id);
+    }
+
+    fn fold_type(&mut self, ctx: &mut CompilerCtx, id: TypeSpecId) {
+        walk_type_mut(self, ctx, id);
+    }
+}
+
+// The `walk_*_mut` functions copy the node's child ids out first so the
+// visitor is free to mutate the arenas while recursing.
+
+pub fn walk_expr_mut<V: MutVisitor + ?Sized>(v: &mut V, ctx: &mut CompilerCtx, id: ExprId) {
+    let expr = &ctx.ast.exprs[id];
+    let (ty, kind) = (expr.ty, expr.kind.clone());
+    if let Some(ty) = ty {
+        v.fold_type(ctx, ty);
+    }
+    match kind {
+        ExprKind::Literal(_) | ExprKind::Identifier(_) | ExprKind::Continue | ExprKind::Error => {}
+        ExprKind::Paren(inner) | ExprKind::Loop { body: inner } => v.fold_expr(ctx, inner),
+        ExprKind::Tuple(elems) | ExprKind::ArrayLit(elems) => {
+            for e in elems {
+                v.fold_expr(ctx, e);
+            }
+        }
+        ExprKind::StructLit { fields, .. } => {
+            for (_, e) in fields {
+                v.fold_expr(ctx, e);
+            }
+        }
+        ExprKind::Block { stmts, yield_expr } => {
+            for stmt in stmts {
+                v.fold_stmt(ctx, stmt);
+            }
+            if let Some(e) = yield_expr {
+                v.fold_expr(ctx, e);
+            }
+        }
+        ExprKind::If {
+            cond,
+            then_branch: body,
+            else_branch,
+        }
+        | ExprKind::While {
+            cond,
+            body,
+            else_branch,
+        }
+        | ExprKind::For {
+            iterable: cond,
+            body,
+            else_branch,
+            ..
+        } => {
+            v.fold_expr(ctx, cond);
+            v.fold_expr(ctx, body);
+            if let Some(e) = else_branch {
+                v.fold_expr(ctx, e);
+            }
+        }
+        ExprKind::Match { target, cases } => {
+            v.fold_expr(ctx, target);
+            for case in cases {
+                v.fold_expr(ctx, case.body);
+            }
+        }
+        ExprKind::Return(value) | ExprKind::Break(value) => {
+            if let Some(e) = value {
+                v.fold_expr(ctx, e);
+            }
+        }
+        ExprKind::Closure {
+            params,
+            ret_ty,
+            body,
+        } => {
+            for param in params {
+                v.fold_type(ctx, param.ty);
+            }
+            if let Some(ty) = ret_ty {
+                v.fold_type(ctx, ty);
+            }
+            v.fold_expr(ctx, body);
+        }
+        ExprKind::Binary { lhs, rhs, .. } | ExprKind::Pipeline { lhs, rhs } => {
+            v.fold_expr(ctx, lhs);
+            v.fold_expr(ctx, rhs);
+        }
+        ExprKind::Assign { target, value, .. } => {
+            v.fold_expr(ctx, target);
+            v.fold_expr(ctx, value);
+        }
+        ExprKind::Cast {
+            target,
+            target_type,
+        } => {
+            v.fold_expr(ctx, target);
+            v.fold_type(ctx, target_type);
+        }
+        ExprKind::Unary { operand, .. } => v.fold_expr(ctx, operand),
+        ExprKind::Call {
+            callee,
+            type_args,
+            args,
+        } => {
+            v.fold_expr(ctx, callee);
+            for ty in type_args {
+                v.fold_type(ctx, ty);
+            }
+            for arg in args {
+                v.fold_expr(ctx, arg);
+            }
+        }
+        ExprKind::MemberAccess { expr, .. } => v.fold_expr(ctx, expr),
+        ExprKind::IndexAccess { expr, index } => {
+            v.fold_expr(ctx, expr);
+            v.fold_expr(ctx, index);
+        }
+    }
+}
+
+pub fn walk_stmt_mut<V: MutVisitor + ?Sized>(v: &mut V, ctx: &mut CompilerCtx, id: StmtId) {
+    match ctx.ast.stmts[id].kind.clone() {
+        StmtKind::VarDecl { ty, init, .. } => {
+            if let Some(ty) = ty {
+                v.fold_type(ctx, ty);
+            }
+            if let VarInit::Expr(e) = init {
+                v.fold_expr(ctx, e);
+            }
+        }
+        StmtKind::Defer(e) | StmtKind::Expr(e) => v.fold_expr(ctx, e),
+    }
+}
+
+pub fn walk_item_mut<V: MutVisitor + ?Sized>(v: &mut V, ctx: &mut CompilerCtx, id: ItemId) {
+    match ctx.ast.items[id].kind.clone() {
+        ItemKind::FnDecl {
+            params,
+            ret_ty,
+            body,
+            ..
+        } => {
+            for param in params {
+                v.fold_type(ctx, param.ty);
+            }
+            if let Some(ty) = ret_ty {
+                v.fold_type(ctx, ty);
+            }
+            v.fold_expr(ctx, body);
+        }
+        ItemKind::StructDecl { fields, .. } => {
+            for field in fields {
+                v.fold_type(ctx, field.ty);
+            }
+        }
+        ItemKind::EnumDecl { backing_ty, .. } => {
+            if let Some(ty) = backing_ty {
+                v.fold_type(ctx, ty);
+            }
+        }
+        ItemKind::UnionDecl { variants, .. } => {
+            for variant in variants {
+                match variant.data {
+                    None => {}
+                    Some(UnionVariantData::Tuple(types)) => {
+                        for ty in types {
+                            v.fold_type(ctx, ty);
+                        }
+                    }
+                    Some(UnionVariantData::Struct(fields)) => {
+                        for field in fields {
+                            v.fold_type(ctx, field.ty);
+                        }
+                    }
+                }
+            }
+        }
+        ItemKind::ImplDecl { self_ty, methods } => {
+            v.fold_type(ctx, self_ty);
+            for method in methods {
+                v.fold_item(ctx, method);
+            }
+        }
+        ItemKind::ConstDecl { ty, expr, .. } => {
+            v.fold_type(ctx, ty);
+            v.fold_expr(ctx, expr);
+        }
+        ItemKind::ExternDecl { declarations, .. } => {
+            for sig in declarations {
+                for param in sig.params {
+                    v.fold_type(ctx, param.ty);
+                }
+                if let Some(ty) = sig.return_ty {
+                    v.fold_type(ctx, ty);
+                }
+            }
+        }
+    }
+}
+
+pub fn walk_type_mut<V: MutVisitor + ?Sized>(v: &mut V, ctx: &mut CompilerCtx, id: TypeSpecId) {
+    match ctx.ast.type_specs[id].kind.clone() {
+        TypeSpecKind::Named(_) | TypeSpecKind::Error => {}
+        TypeSpecKind::Pointer(inner)
+        | TypeSpecKind::Reference(inner)
+        | TypeSpecKind::MutReference(inner)
+        | TypeSpecKind::Optional(inner)
+        | TypeSpecKind::Slice(inner)
+        | TypeSpecKind::Paren(inner) => v.fold_type(ctx, inner),
+        TypeSpecKind::Array { size, elem_ty } => {
+            v.fold_expr(ctx, size);
+            v.fold_type(ctx, elem_ty);
+        }
+        TypeSpecKind::Fn { param, return_ty } => {
+            for ty in param {
+                v.fold_type(ctx, ty);
+            }
+            v.fold_type(ctx, return_ty);
+        }
+        TypeSpecKind::Tuple(elems) | TypeSpecKind::Generic { args: elems, .. } => {
+            for ty in elems {
+                v.fold_type(ctx, ty);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{Symbol, SymbolInterner};
+    use crate::parser::Parser;
+
+    struct IdentCollector<'a> {
+        interner: &'a SymbolInterner,
+        names: Vec<String>,
+    }
+
+    impl Visitor for IdentCollector<'_> {
+        fn visit_expr(&mut self, ast: &Ast, id: ExprId) {
+            if let ExprKind::Identifier(sym) = ast.exprs[id].kind {
+                self.names.push(self.interner.resolve(sym).to_string());
+            }
+            walk_expr(self, ast, id);
+        }
+    }
+
+    // Records the kind of every node after its children
+    #[derive(Default)]
+    struct PostOrder {
+        order: Vec<String>,
+    }
+
+    impl Visitor for PostOrder {
+        fn visit_expr(&mut self, ast: &Ast, id: ExprId) {
+            walk_expr(self, ast, id);
+            let name = match &ast.exprs[id].kind {
+                ExprKind::Binary { .. } => "binary",
+                ExprKind::Literal(_) => "lit",
+                ExprKind::Identifier(_) => "ident",
+                ExprKind::Cast { .. } => "cast",
+                _ => "other",
+            };
+            self.order.push(name.to_string());
+        }
+
+        fn visit_type(&mut self, ast: &Ast, id: TypeSpecId) {
+            walk_type(self, ast, id);
+            self.order.push("type".to_string());
+        }
+    }
+
+    #[derive(Default)]
+    struct Counter {
+        types: usize,
+        patterns: usize,
+        stmts: usize,
+        items: usize,
+        bindings: Vec<Symbol>,
+    }
+
+    impl Visitor for Counter {
+        fn visit_stmt(&mut self, ast: &Ast, id: StmtId) {
+            self.stmts += 1;
+            walk_stmt(self, ast, id);
+        }
+
+        fn visit_item(&mut self, ast: &Ast, id: ItemId) {
+            self.items += 1;
+            walk_item(self, ast, id);
+        }
+
+        fn visit_type(&mut self, ast: &Ast, id: TypeSpecId) {
+            self.types += 1;
+            walk_type(self, ast, id);
+        }
+
+        fn visit_pattern(&mut self, ast: &Ast, id: PatternId) {
+            self.patterns += 1;
+            if let PatternKind::Identifier(sym) = &ast.patterns[id].kind {
+                self.bindings.push(*sym);
+            }
+            walk_pattern(self, ast, id);
+        }
+    }
+
+    #[test]
+    fn test_collect_identifiers() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let expr = Parser::new("{ val x = a + f(b); x.y[c] }", &mut ctx).parse_expr();
+        let mut v = IdentCollector {
+            interner: &ctx.symbol_interner,
+            names: Vec::new(),
+        };
+        v.visit_expr(&ctx.ast, expr);
+        assert_eq!(v.names, ["a", "f", "b", "x", "c"]);
+    }
+
+    #[test]
+    fn test_post_order() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let expr = Parser::new("1u8 + x as *i32", &mut ctx).parse_expr();
+        let mut v = PostOrder::default();
+        v.visit_expr(&ctx.ast, expr);
+        assert_eq!(
+            v.order,
+            ["type", "lit", "ident", "type", "type", "cast", "binary"]
+        );
+    }
+
+    #[test]
+    fn test_walk_items() {
+        let mut ctx = CompilerCtx::new("test", 16);
+        let items = Parser::new(
+            "impl P { fn f(a: *T) -> [2]u8 { val z: i32 = 0; match a { (q, 1) | r => q } } } \
+             union U { A(i32, f32), B { x: T } }",
+            &mut ctx,
+        )
+        .parse_program();
+        let mut v = Counter::default();
+        for item in items {
+            v.visit_item(&ctx.ast, item);
+        }
+        assert_eq!(v.items, 3);
+        // P, *T, T, [2]u8, u8, i32, i32, f32, T
+        assert_eq!(v.types, 9);
+        assert_eq!(v.stmts, 1);
+        assert_eq!(v.patterns, 5);
+        assert_eq!(v.bindings.len(), 2);
+    }
+}